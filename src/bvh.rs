@@ -0,0 +1,394 @@
+use crate::objects::{BoundingBox, TriangleFace};
+use crate::vector::Vec3;
+
+/// Nodes with at most this many triangles aren't split any further.
+const MAX_LEAF_TRIANGLES: usize = 4;
+/// The children of nodes with at least this many triangles are built in
+/// parallel. Below it, splitting up the work costs more than it saves.
+const PARALLEL_BUILD_TRIANGLES: usize = 4096;
+
+/// A triangle in a `Bvh`, with the indices to find it back in the world.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BvhTriangle {
+    pub face: TriangleFace,
+    pub object_index: usize,
+    pub face_index: usize,
+}
+
+/// A bounding volume hierarchy: a binary tree of axis-aligned bounding boxes
+/// around groups of triangles. A ray only has to be tested against the
+/// triangles in the boxes it passes through, instead of against every
+/// triangle.
+#[derive(Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<BvhTriangle>,
+}
+
+#[derive(Debug, PartialEq)]
+struct BvhNode {
+    bounds: BoundingBox,
+    kind: BvhNodeKind,
+}
+
+#[derive(Debug, PartialEq)]
+enum BvhNodeKind {
+    /// The triangles in `Bvh::triangles[start..end]`.
+    Leaf { start: usize, end: usize },
+    /// The indices of the child nodes in `Bvh::nodes`.
+    Interior { left: usize, right: usize },
+}
+
+impl Bvh {
+    /// Build a hierarchy over the triangles, by recursively splitting them in
+    /// half along the longest axis of their centers. The top levels are built
+    /// on Rayon's thread pool, but the splits don't depend on the number of
+    /// threads, so neither does the hierarchy.
+    pub fn new(mut triangles: Vec<BvhTriangle>) -> Bvh {
+        let nodes = if triangles.is_empty() {
+            Vec::new()
+        } else {
+            build_nodes(&mut triangles, 0)
+        };
+        Bvh { nodes, triangles }
+    }
+
+    /// Move the triangles of an object to its new `faces`, indexed like the
+    /// faces the hierarchy was built from, and fit the boxes around them
+    /// again. This is much cheaper than building a new hierarchy, but the
+    /// boxes can end up overlapping more, so rays have to visit more of them.
+    pub fn refit(&mut self, object_index: usize, faces: &[TriangleFace]) {
+        for triangle in self.triangles.iter_mut() {
+            if triangle.object_index == object_index {
+                triangle.face = faces[triangle.face_index];
+            }
+        }
+        // Children come after their parent, so going backwards fits every
+        // child before its parent.
+        for index in (0..self.nodes.len()).rev() {
+            let bounds = match self.nodes[index].kind {
+                BvhNodeKind::Leaf { start, end } => {
+                    let mut bounds = triangle_bounds(self.triangles[start].face);
+                    for triangle in &self.triangles[start + 1..end] {
+                        bounds.merge(&triangle_bounds(triangle.face));
+                    }
+                    bounds
+                }
+                BvhNodeKind::Interior { left, right } => {
+                    let mut bounds = self.nodes[left].bounds;
+                    bounds.merge(&self.nodes[right].bounds);
+                    bounds
+                }
+            };
+            self.nodes[index].bounds = bounds;
+        }
+    }
+
+    #[cfg(test)]
+    /// The number of triangles the hierarchy was built from.
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Find the nearest triangle along a ray. `intersect` tests a single
+    /// triangle and returns the ray's t value at the hit, if it counts as a
+    /// hit. Boxes that the ray only passes through before `t_min`, or after
+    /// the nearest hit found so far, are skipped. Returns the nearest hit
+    /// triangle and its t value.
+    pub fn nearest<F>(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        t_min: f32,
+        mut intersect: F,
+    ) -> Option<(&BvhTriangle, f32)>
+    where
+        F: FnMut(&BvhTriangle) -> Option<f32>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inverse_direction = Vec3 {
+            x: 1.0 / direction.x,
+            y: 1.0 / direction.y,
+            z: 1.0 / direction.z,
+        };
+        let ray = Ray {
+            origin,
+            inverse_direction,
+            t_min,
+        };
+        let mut nearest = None;
+        self.nearest_in_node(0, &ray, &mut intersect, &mut nearest);
+        nearest
+    }
+
+    fn nearest_in_node<'a, F>(
+        &'a self,
+        index: usize,
+        ray: &Ray,
+        intersect: &mut F,
+        nearest: &mut Option<(&'a BvhTriangle, f32)>,
+    ) where
+        F: FnMut(&BvhTriangle) -> Option<f32>,
+    {
+        match self.nodes[index].kind {
+            BvhNodeKind::Leaf { start, end } => {
+                for triangle in &self.triangles[start..end] {
+                    if let Some(t) = intersect(triangle) {
+                        if nearest.is_none_or(|(_, t_nearest)| t < t_nearest) {
+                            *nearest = Some((triangle, t));
+                        }
+                    }
+                }
+            }
+            BvhNodeKind::Interior { left, right } => {
+                // Visit the nearest child first, so the other one can often
+                // be skipped.
+                let mut children = [left, right].map(|child| (child, self.entry(child, ray)));
+                if children[1].1 < children[0].1 {
+                    children.swap(0, 1);
+                }
+                for (child, entry) in children {
+                    let t_nearest = nearest.map_or(f32::INFINITY, |(_, t)| t);
+                    if entry.is_some_and(|t| t <= t_nearest) {
+                        self.nearest_in_node(child, ray, intersect, nearest);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get the t value at which a ray enters the bounding box of a node, or
+    /// `None` if it misses the box.
+    fn entry(&self, index: usize, ray: &Ray) -> Option<f32> {
+        let bounds = self.nodes[index].bounds;
+        let mut t_enter = ray.t_min;
+        let mut t_exit = f32::INFINITY;
+        for axis in 0..3 {
+            let origin = component(ray.origin, axis);
+            let inverse = component(ray.inverse_direction, axis);
+            let t0 = (component(bounds.min, axis) - origin) * inverse;
+            let t1 = (component(bounds.max, axis) - origin) * inverse;
+            // A NaN (a ray in the plane of a side of the box) is ignored by
+            // min and max.
+            t_enter = t_enter.max(t0.min(t1));
+            t_exit = t_exit.min(t0.max(t1));
+        }
+        (t_enter <= t_exit).then_some(t_enter)
+    }
+}
+
+/// Build the nodes for `triangles`, which start at index `start` of all the
+/// hierarchy's triangles, in depth-first order with the root first. The
+/// indices of child nodes are relative to the returned nodes. The children of
+/// large nodes are built in parallel.
+fn build_nodes(triangles: &mut [BvhTriangle], start: usize) -> Vec<BvhNode> {
+    if triangles.len() < PARALLEL_BUILD_TRIANGLES {
+        let mut nodes = Vec::new();
+        build_nodes_serial(triangles, start, &mut nodes);
+        return nodes;
+    }
+    let end = start + triangles.len();
+    let (bounds, split) = sort_for_split(triangles);
+    if !split {
+        return vec![BvhNode {
+            bounds,
+            kind: BvhNodeKind::Leaf { start, end },
+        }];
+    }
+    let (left_triangles, right_triangles) = triangles.split_at_mut(triangles.len() / 2);
+    let right_start = start + left_triangles.len();
+    let (left_nodes, right_nodes) = rayon::join(
+        || build_nodes(left_triangles, start),
+        || build_nodes(right_triangles, right_start),
+    );
+
+    // The left child follows this node, and the right child follows the
+    // left child's subtree.
+    let right = 1 + left_nodes.len();
+    let mut nodes = Vec::with_capacity(right + right_nodes.len());
+    nodes.push(BvhNode {
+        bounds,
+        kind: BvhNodeKind::Interior { left: 1, right },
+    });
+    nodes.extend(left_nodes.into_iter().map(|node| node.offset(1)));
+    nodes.extend(right_nodes.into_iter().map(|node| node.offset(right)));
+    nodes
+}
+
+/// Build the node for `triangles` and its children like `build_nodes`, but
+/// on the current thread, adding them to `nodes`. Returns the node's index.
+fn build_nodes_serial(
+    triangles: &mut [BvhTriangle],
+    start: usize,
+    nodes: &mut Vec<BvhNode>,
+) -> usize {
+    let end = start + triangles.len();
+    let (bounds, split) = sort_for_split(triangles);
+    let index = nodes.len();
+    // Reserve the node's spot before its children are added.
+    nodes.push(BvhNode {
+        bounds,
+        kind: BvhNodeKind::Leaf { start, end },
+    });
+    if split {
+        let (left_triangles, right_triangles) = triangles.split_at_mut(triangles.len() / 2);
+        let right_start = start + left_triangles.len();
+        let left = build_nodes_serial(left_triangles, start, nodes);
+        let right = build_nodes_serial(right_triangles, right_start, nodes);
+        nodes[index].kind = BvhNodeKind::Interior { left, right };
+    }
+    index
+}
+
+/// Get the bounds of the triangles, and whether they should be split into
+/// two nodes. If so, they are sorted along the longest axis of their centers,
+/// so the split can be made in the middle.
+fn sort_for_split(triangles: &mut [BvhTriangle]) -> (BoundingBox, bool) {
+    let mut bounds = triangle_bounds(triangles[0].face);
+    let mut centers = BoundingBox::from_point(center(triangles[0].face));
+    for triangle in triangles.iter() {
+        bounds.merge(&triangle_bounds(triangle.face));
+        centers.grow(center(triangle.face));
+    }
+
+    let extent = centers.max - centers.min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    if triangles.len() <= MAX_LEAF_TRIANGLES || component(extent, axis) <= 0.0 {
+        return (bounds, false);
+    }
+    triangles.sort_by(|a, b| {
+        component(center(a.face), axis).total_cmp(&component(center(b.face), axis))
+    });
+    (bounds, true)
+}
+
+impl BvhNode {
+    /// Move the indices of the node's children by `by`.
+    fn offset(self, by: usize) -> BvhNode {
+        let kind = match self.kind {
+            BvhNodeKind::Interior { left, right } => BvhNodeKind::Interior {
+                left: left + by,
+                right: right + by,
+            },
+            leaf => leaf,
+        };
+        BvhNode { kind, ..self }
+    }
+}
+
+/// A ray prepared for the slab test against bounding boxes.
+struct Ray {
+    origin: Vec3,
+    inverse_direction: Vec3,
+    t_min: f32,
+}
+
+fn triangle_bounds((v0, v1, v2): TriangleFace) -> BoundingBox {
+    let mut bounds = BoundingBox::from_point(v0);
+    bounds.grow(v1);
+    bounds.grow(v2);
+    bounds
+}
+
+fn center((v0, v1, v2): TriangleFace) -> Vec3 {
+    (v0 + v1 + v2) / 3.0
+}
+
+fn component(v: Vec3, axis: usize) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+#[test]
+fn test_build_does_not_depend_on_thread_count() {
+    use crate::sampling::Rng;
+    use crate::vector::vec3;
+
+    // Enough triangles for the top levels to be built in parallel.
+    let mut rng = Rng::new(3);
+    let mut random = || vec3(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 100.0;
+    let triangles: Vec<BvhTriangle> = (0..4 * PARALLEL_BUILD_TRIANGLES)
+        .map(|face_index| {
+            let corner = random();
+            BvhTriangle {
+                face: (corner, corner + random() * 0.01, corner + random() * 0.01),
+                object_index: 0,
+                face_index,
+            }
+        })
+        .collect();
+    let build = |threads: usize| {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| Bvh::new(triangles.clone()))
+    };
+    let serial = build(1);
+    let parallel = build(4);
+    assert_eq!(parallel.nodes, serial.nodes);
+    assert_eq!(parallel.triangles, serial.triangles);
+    assert_eq!(serial.triangle_count(), triangles.len());
+    // Every triangle ends up in exactly one leaf.
+    let mut leaf_triangles = 0;
+    for node in &serial.nodes {
+        if let BvhNodeKind::Leaf { start, end } = node.kind {
+            leaf_triangles += end - start;
+        }
+    }
+    assert_eq!(leaf_triangles, triangles.len());
+}
+
+#[test]
+fn test_refit() {
+    use crate::vector::vec3;
+
+    // A row of small right triangles, alternating between two objects.
+    let triangle = |x: f32, y: f32| {
+        (
+            vec3(x, y, 0.0),
+            vec3(x + 0.5, y, 0.0),
+            vec3(x, y + 0.5, 0.0),
+        )
+    };
+    let triangles: Vec<BvhTriangle> = (0..20)
+        .map(|i| BvhTriangle {
+            face: triangle(i as f32, 0.0),
+            object_index: i % 2,
+            face_index: i / 2,
+        })
+        .collect();
+    let mut bvh = Bvh::new(triangles);
+    // Find the triangle straight below a point, looking down the z axis.
+    let find = |bvh: &Bvh, x: f32, y: f32| {
+        let intersect = |t: &BvhTriangle| {
+            let (v0, _, _) = t.face;
+            let (dx, dy) = (x - v0.x, y - v0.y);
+            (dx >= 0.0 && dy >= 0.0 && dx + dy <= 0.5).then_some(5.0)
+        };
+        bvh.nearest(vec3(x, y, 5.0), vec3(0.0, 0.0, -1.0), 0.0, intersect)
+            .map(|(t, _)| (t.object_index, t.face_index))
+    };
+    assert_eq!(find(&bvh, 3.1, 0.1), Some((1, 1)));
+
+    // Move the faces of object 1 out of the row.
+    let moved: Vec<TriangleFace> = (0..10)
+        .map(|face_index| triangle((2 * face_index + 1) as f32, 10.0))
+        .collect();
+    bvh.refit(1, &moved);
+    assert_eq!(find(&bvh, 3.1, 0.1), None);
+    assert_eq!(find(&bvh, 3.1, 10.1), Some((1, 1)));
+    assert_eq!(find(&bvh, 4.1, 0.1), Some((0, 2)));
+    assert_eq!(bvh.nodes[0].bounds.max.y, 10.5);
+}
@@ -2,7 +2,24 @@ use std::f32::consts::PI;
 
 use crate::errors::*;
 use crate::util::{Resolution, SphericalCoordinates};
-use crate::vector::{vec3, Vec3};
+use crate::vector::{vec3, Mat3, Vec3};
+
+use serde::{Deserialize, Serialize};
+
+/// The largest supported horizontal field of view in degrees. The image
+/// plane grows with the tangent of half the field of view, so closer to 180
+/// degrees it becomes too large to represent with f32 precision.
+pub const MAX_FIELD_OF_VIEW_DEG: f32 = 179.0;
+
+/// The largest angle (in radians) the camera can look up or down with
+/// `rotate_yaw_pitch`. Looking straight up or down would make the yaw
+/// undefined and flip the image.
+pub const MAX_PITCH: f32 = PI / 2.0 - 0.01;
+
+/// View directions with a y component beyond this (up or down) are treated
+/// as looking straight up or down, where the yaw of the view direction is
+/// undefined.
+const VERTICAL_VIEW_Y: f32 = 1.0 - 1e-6;
 
 #[derive(Debug, Copy, Clone, Default)]
 pub struct ImagePlane {
@@ -12,13 +29,68 @@ pub struct ImagePlane {
     pub bottom_left: Vec3,
 }
 
+/// Serializable description of a camera, used to store the camera as part of a
+/// scene file. The resolution is not part of the settings, as it depends on
+/// where the scene is rendered to.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct CameraSettings {
+    pub pos: Vec3,
+    pub look_at: Vec3,
+    /// Horizontal field of view in degrees.
+    pub field_of_view: f32,
+    /// Vertical field of view in degrees. When not set, it follows from the
+    /// horizontal field of view and the aspect ratio.
+    #[serde(default)]
+    pub field_of_view_vertical: Option<f32>,
+    #[serde(default)]
+    pub projection_model: ProjectionModel,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            pos: vec3(0.0, 2.5, 5.0),
+            look_at: vec3(0.0, 0.0, 0.0),
+            field_of_view: 90.0,
+            field_of_view_vertical: None,
+            projection_model: ProjectionModel::default(),
+        }
+    }
+}
+
+/// How pixels are mapped to ray directions.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, PartialEq)]
+pub enum ProjectionModel {
+    /// Rays go through evenly spaced points on the flat image plane. Straight
+    /// lines stay straight, but objects near the edges get stretched at wide
+    /// fields of view.
+    #[default]
+    Planar,
+    /// Rays are evenly spaced in angle (an equidistant "fisheye" projection),
+    /// which reduces the stretching near the edges at wide fields of view.
+    Spherical,
+}
+
 /// A minimal camera struct that exists in 3D space.
 pub struct Camera {
     pub pos: Vec3,
     field_of_view_horizontal: f32,
+    /// Vertical field of view in radians, or `None` to derive it from the
+    /// horizontal field of view and the aspect ratio.
+    field_of_view_vertical: Option<f32>,
     view_direction: Vec3,
+    /// Rotation (in radians) of the camera around its view direction. At a
+    /// positive roll, the camera is tilted clockwise, as seen from the
+    /// camera, so the scene appears rotated counterclockwise.
+    roll: f32,
     pub image_plane: ImagePlane,
     pub resolution: Resolution, // A 2-vector representing the camera resolution.
+    pub projection_model: ProjectionModel,
+    /// Offset of the sampled point within every pixel, in pixels. Set with
+    /// `jitter`.
+    pub subpixel_offset: (f32, f32),
+    /// The width of a pixel divided by its height, for anamorphic rendering.
+    pixel_aspect: f32,
 }
 
 impl Camera {
@@ -29,10 +101,9 @@ impl Camera {
         field_of_view_horizontal: f32,
         resolution: Resolution,
     ) -> Result<Camera, CameraSettingError> {
-        // TODO: make this a result, instead of using assert
-        // Using a field of view greater or equal to 180 degrees leads to
-        // unspecific behavior, as does a value lower or equal to zero.
-        if field_of_view_horizontal >= 180.0 || field_of_view_horizontal <= 0.0 {
+        // Using a field of view close to 180 degrees leads to an infinitely
+        // large image plane, and a value lower or equal to zero makes no sense.
+        if field_of_view_horizontal > MAX_FIELD_OF_VIEW_DEG || field_of_view_horizontal <= 0.0 {
             return Err(CameraSettingError::InvalidFOV(field_of_view_horizontal));
         }
         // Internally, field_of_view is used as radians, so we convert here.
@@ -41,40 +112,79 @@ impl Camera {
         let mut camera = Camera {
             pos,
             field_of_view_horizontal: field_of_view,
+            field_of_view_vertical: None,
             view_direction,
+            roll: 0.0,
             image_plane: ImagePlane::default(),
             resolution,
+            projection_model: ProjectionModel::default(),
+            subpixel_offset: (0.0, 0.0),
+            pixel_aspect: 1.0,
         };
         camera.image_plane = camera.get_image_plane();
         Ok(camera)
     }
 
+    /// Create a new camera from (deserialized) camera settings.
+    pub fn from_settings(
+        settings: &CameraSettings,
+        resolution: Resolution,
+    ) -> Result<Camera, CameraSettingError> {
+        let view_direction = settings.look_at - settings.pos;
+        let mut camera = Camera::new(
+            settings.pos,
+            view_direction,
+            settings.field_of_view,
+            resolution,
+        )?;
+        camera.projection_model = settings.projection_model;
+        if let Some(field_of_view_vertical) = settings.field_of_view_vertical {
+            camera.set_field_of_view_vertical_deg(field_of_view_vertical)?;
+        }
+        Ok(camera)
+    }
+
     fn get_image_plane(&self) -> ImagePlane {
+        // Looking (nearly) straight up or down, the yaw below follows from
+        // rounding errors in the x and z components, so use a fixed "up"
+        // instead: +z when looking up and -z when looking down, which is
+        // where "up" ends up when tilting the default camera (looking at -z).
+        if self.view_direction.y.abs() > VERTICAL_VIEW_Y {
+            let up = vec3(0.0, 0.0, self.view_direction.y.signum());
+            return self.get_oriented_image_plane(self.view_direction, up);
+        }
+
         // Calculate the vectors pointing to the middle of the side edges
         // without accounting for the rotation of self's view_direction.
         let rotation_angle = self.field_of_view_horizontal / 2.0;
         let len = (1.0 / rotation_angle.cos()).abs();
-        let mut right = vec3(rotation_angle.cos(), 0.0, rotation_angle.sin()) * len;
-        let mut left = vec3(rotation_angle.cos(), 0.0, -rotation_angle.sin()) * len;
+        let right = vec3(rotation_angle.cos(), 0.0, rotation_angle.sin()) * len;
+        let left = vec3(rotation_angle.cos(), 0.0, -rotation_angle.sin()) * len;
 
-        // Rotate the vectors into place. First rotate up/down (pan/pitch), then
-        // rotate around the vertical y-axis (yaw).
+        // Rotate the vectors into place. First tilt them around the view
+        // direction (roll), then rotate up/down (pan/pitch), then rotate
+        // around the vertical y-axis (yaw).
+        // Before pitching, the view direction is the positive x-axis, so we
+        // can roll by rotating around it. `rotation_x` turns the opposite way
+        // of the right-handed roll, hence the minus.
         // The angle of the view_vector with the xz-plane.
         // The right and left vectors initially point to the right, so we can
-        // pitch by rotating around the y-axis.
+        // pitch by rotating around the z-axis.
         let view_angle_y = self.view_direction.y.asin();
-        right.rotate_z_rad(view_angle_y);
-        left.rotate_z_rad(view_angle_y);
         // The angle of the view_vector with the positive x-axis.
         let view_angle_xz = self.view_direction.z.atan2(self.view_direction.x);
-        right.rotate_y_rad(view_angle_xz);
-        left.rotate_y_rad(view_angle_xz);
+        let rotation = Mat3::rotation_y(view_angle_xz)
+            * Mat3::rotation_z(view_angle_y)
+            * Mat3::rotation_x(-self.roll);
+        let right = rotation * right;
+        let left = rotation * left;
 
         // Calculate the vector pointing "up" from the normal, i.e. the vector
         // orthogonal to the normal and the vector pointing to the right.
         let mut up = right.cross_product(self.view_direction);
-        let size_up = rotation_angle.tan() / self.get_aspect_ratio();
+        let size_up = self.get_image_plane_half_height();
         up.set_length(size_up);
+
         // Calculate all the corner's (relative) position.
         let top_left_relative = left + up;
         let top_right_relative = right + up;
@@ -109,9 +219,74 @@ impl Camera {
         self.view_direction = direction_normal;
         self.image_plane = self.get_image_plane();
     }
+    /// Make the camera point in the direction given by spherical angles (in
+    /// radians): `theta` is the angle with the positive z-axis, and `phi` the
+    /// angle around it, measured from the positive x-axis.
+    pub fn look_direction_from_spherical(&mut self, theta: f32, phi: f32) {
+        self.set_view_direction(SphericalCoordinates::new(1.0, theta, phi).into());
+    }
+    /// Turn the camera by `yaw` radians to the right and tilt it by `pitch`
+    /// radians up, like looking around in a first person game. The pitch is
+    /// clamped to `MAX_PITCH` up or down.
+    pub fn rotate_yaw_pitch(&mut self, yaw: f32, pitch: f32) {
+        let current_pitch = self.view_direction.y.clamp(-1.0, 1.0).asin();
+        let current_yaw = self.view_direction.z.atan2(self.view_direction.x);
+        let pitch = (current_pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+        let yaw = current_yaw + yaw;
+        self.set_view_direction(vec3(
+            pitch.cos() * yaw.cos(),
+            pitch.sin(),
+            pitch.cos() * yaw.sin(),
+        ));
+    }
+    /// Point the camera in a direction, with `up` being the direction that
+    /// appears as "up" in the image. Unlike `set_view_direction`, this also
+    /// works when looking straight up or down. `up` can't be parallel to the
+    /// direction.
+    pub fn set_orientation(&mut self, direction: Vec3, up: Vec3) {
+        let forward = direction.normalized();
+        self.view_direction = forward;
+        self.image_plane = self.get_oriented_image_plane(forward, up);
+    }
+    /// Get the image plane for looking in the (normalized) direction
+    /// `forward`, with `up` appearing as "up" in the image before the roll is
+    /// applied.
+    fn get_oriented_image_plane(&self, forward: Vec3, up: Vec3) -> ImagePlane {
+        let mut right = forward.cross_product(up).normalized();
+        right.rotate_around_axis(forward, self.roll);
+        let up = right.cross_product(forward);
+        let half_width = (self.field_of_view_horizontal / 2.0).tan();
+        let half_height = self.get_image_plane_half_height();
+        let center = self.pos + forward;
+        ImagePlane {
+            top_left: center - right * half_width + up * half_height,
+            top_right: center + right * half_width + up * half_height,
+            bottom_right: center + right * half_width - up * half_height,
+            bottom_left: center - right * half_width - up * half_height,
+        }
+    }
+    pub fn get_roll(&self) -> f32 {
+        self.roll
+    }
+    /// Set the rotation (in radians) of the camera around its view direction,
+    /// to tilt the horizon.
+    pub fn set_roll(&mut self, roll: f32) {
+        self.roll = roll;
+        self.image_plane = self.get_image_plane();
+    }
+    /// Offset the point that is sampled within every pixel by (dx, dy)
+    /// pixels, e.g. to take several samples per pixel for anti-aliasing. A
+    /// jitter of (0, 0) samples the regular pixel ray again.
+    pub fn jitter(&mut self, dx: f32, dy: f32) {
+        self.subpixel_offset = (dx, dy);
+    }
     pub fn get_view_direction(&self) -> Vec3 {
         self.view_direction
     }
+    /// Get the unit vector pointing to the right in the image.
+    pub fn get_right_vector(&self) -> Vec3 {
+        (self.image_plane.top_right - self.image_plane.top_left).normalized()
+    }
     /// Get the camera's field of view in radians.
     pub fn get_field_of_view_horizontal(&self) -> f32 {
         self.field_of_view_horizontal
@@ -135,7 +310,9 @@ impl Camera {
         field_of_view_horizontal: f32,
     ) -> Result<(), CameraSettingError> {
         // Make sure we got a valid
-        if field_of_view_horizontal >= PI || field_of_view_horizontal <= 0.0 {
+        if field_of_view_horizontal > MAX_FIELD_OF_VIEW_DEG * (PI / 180.0)
+            || field_of_view_horizontal <= 0.0
+        {
             return Err(CameraSettingError::InvalidFOV(field_of_view_horizontal));
         }
         // Set the new FOV but also update the image plane.
@@ -143,7 +320,369 @@ impl Camera {
         self.image_plane = self.get_image_plane();
         Ok(())
     }
+    /// Get the camera's vertical field of view in radians. Unless it was set
+    /// explicitly, it follows from the horizontal field of view and the image
+    /// aspect ratio, in a way that depends on the projection model.
+    pub fn get_field_of_view_vertical(&self) -> f32 {
+        match (self.field_of_view_vertical, self.projection_model) {
+            (Some(field_of_view), _) => field_of_view,
+            (None, ProjectionModel::Planar) => {
+                let half_width = (self.field_of_view_horizontal / 2.0).tan();
+                2.0 * (half_width / self.get_image_aspect_ratio()).atan()
+            }
+            (None, ProjectionModel::Spherical) => {
+                self.field_of_view_horizontal / self.get_image_aspect_ratio()
+            }
+        }
+    }
+    /// Get the camera's vertical field of view in degrees.
+    pub fn get_field_of_view_vertical_deg(&self) -> f32 {
+        self.get_field_of_view_vertical() * (180.0 / PI)
+    }
+    /// Set the camera's vertical field of view in degrees, independent of the
+    /// horizontal field of view.
+    pub fn set_field_of_view_vertical_deg(
+        &mut self,
+        field_of_view_vertical: f32,
+    ) -> Result<(), CameraSettingError> {
+        self.set_field_of_view_vertical(field_of_view_vertical * (PI / 180.0))
+    }
+    /// Set the camera's vertical field of view (in radians), independent of
+    /// the horizontal field of view.
+    pub fn set_field_of_view_vertical(
+        &mut self,
+        field_of_view_vertical: f32,
+    ) -> Result<(), CameraSettingError> {
+        if field_of_view_vertical > MAX_FIELD_OF_VIEW_DEG * (PI / 180.0)
+            || field_of_view_vertical <= 0.0
+        {
+            return Err(CameraSettingError::InvalidFOV(field_of_view_vertical));
+        }
+        self.field_of_view_vertical = Some(field_of_view_vertical);
+        self.image_plane = self.get_image_plane();
+        Ok(())
+    }
+    /// Get half the height of the image plane, which lies at distance 1 from
+    /// the camera.
+    fn get_image_plane_half_height(&self) -> f32 {
+        match self.field_of_view_vertical {
+            Some(field_of_view) => (field_of_view / 2.0).tan(),
+            None => (self.field_of_view_horizontal / 2.0).tan() / self.get_image_aspect_ratio(),
+        }
+    }
     pub fn get_aspect_ratio(&self) -> f32 {
         self.resolution.w as f32 / self.resolution.h as f32
     }
+    /// Get the aspect ratio of the image plane, i.e. of the part of the scene
+    /// that is captured. This differs from `get_aspect_ratio` when the pixels
+    /// aren't square.
+    pub fn get_image_aspect_ratio(&self) -> f32 {
+        self.get_aspect_ratio() * self.pixel_aspect
+    }
+    pub fn get_pixel_aspect(&self) -> f32 {
+        self.pixel_aspect
+    }
+    /// Set the width of a pixel divided by its height. At 2.0, every pixel
+    /// covers twice as much of the scene horizontally as vertically, so the
+    /// image is squeezed horizontally, to be stretched again on a wide
+    /// display. The default of 1.0 gives square pixels.
+    pub fn set_pixel_aspect(&mut self, pixel_aspect: f32) -> Result<(), CameraSettingError> {
+        if !(pixel_aspect > 0.0 && pixel_aspect.is_finite()) {
+            return Err(CameraSettingError::InvalidPixelAspect(pixel_aspect));
+        }
+        self.pixel_aspect = pixel_aspect;
+        self.image_plane = self.get_image_plane();
+        Ok(())
+    }
+    /// Project a point in world space onto the screen. The result is in
+    /// pixels from the top left corner of the image, such that the pixel ray
+    /// of pixel (x, y) goes through screen position (x, y). Returns `None` for
+    /// points behind the camera.
+    pub fn world_to_screen(&self, point: Vec3) -> Option<(f32, f32)> {
+        let to_point = point - self.pos;
+        let depth = to_point * self.view_direction;
+        if depth <= 0.0 {
+            return None;
+        }
+        let plane = self.image_plane;
+        let (alpha, beta) = match self.projection_model {
+            ProjectionModel::Planar => {
+                // Intersect the line towards the point with the image plane.
+                let plane_center = (plane.top_left + plane.bottom_right) * 0.5;
+                let plane_distance = (plane_center - self.pos) * self.view_direction;
+                let on_plane = to_point * (plane_distance / depth) + self.pos - plane.top_left;
+                let right = plane.top_right - plane.top_left;
+                let down = plane.bottom_left - plane.top_left;
+                (
+                    (on_plane * right) / (right * right),
+                    (on_plane * down) / (down * down),
+                )
+            }
+            ProjectionModel::Spherical => {
+                // The inverse of the equidistant projection of the pixel
+                // rays: the angle with the view direction is proportional to
+                // the distance from the center of the image.
+                let right = (plane.top_right - plane.top_left).normalized();
+                let up = (plane.top_left - plane.bottom_left).normalized();
+                let direction = to_point.normalized();
+                let (side, height) = (direction * right, direction * up);
+                let sideways = side.hypot(height);
+                // More precise than the arc cosine of the forward component
+                // for points close to the view direction.
+                let angle = sideways.atan2(direction * self.view_direction);
+                let (x, y) = if sideways == 0.0 {
+                    (0.0, 0.0)
+                } else {
+                    (angle * side / sideways, angle * height / sideways)
+                };
+                (
+                    x / self.field_of_view_horizontal + 0.5,
+                    0.5 - y / self.get_field_of_view_vertical(),
+                )
+            }
+        };
+        let (dx, dy) = self.subpixel_offset;
+        Some((
+            alpha * self.resolution.w as f32 - dx,
+            beta * self.resolution.h as f32 - dy,
+        ))
+    }
+}
+
+#[test]
+fn test_wide_field_of_view() {
+    let camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        MAX_FIELD_OF_VIEW_DEG,
+        Resolution { w: 200, h: 100 },
+    )
+    .unwrap();
+    let plane = camera.image_plane;
+    let half_width = (MAX_FIELD_OF_VIEW_DEG / 2.0).to_radians().tan();
+    for corner in [
+        plane.top_left,
+        plane.top_right,
+        plane.bottom_right,
+        plane.bottom_left,
+    ] {
+        assert!(corner.x.is_finite() && corner.y.is_finite() && corner.z.is_finite());
+        assert!((corner.z + 1.0).abs() < 1e-3);
+        assert!((corner.x.abs() - half_width).abs() / half_width < 1e-3);
+        assert!((corner.y.abs() - half_width / 2.0).abs() / half_width < 1e-3);
+    }
+    assert!(plane.top_left.x < 0.0 && plane.top_left.y > 0.0);
+
+    let mut camera = camera;
+    assert!(camera.set_field_of_view_horizontal_deg(179.5).is_err());
+    assert!(camera
+        .set_field_of_view_horizontal_deg(MAX_FIELD_OF_VIEW_DEG)
+        .is_ok());
+}
+
+#[test]
+fn test_look_direction_from_spherical() {
+    let mut camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 4, h: 4 },
+    )
+    .unwrap();
+    camera.look_direction_from_spherical(PI / 2.0, 0.0);
+    assert!((camera.get_view_direction() - vec3(1.0, 0.0, 0.0)).len() < 1e-6);
+    camera.look_direction_from_spherical(PI, 0.0);
+    assert!((camera.get_view_direction() - vec3(0.0, 0.0, -1.0)).len() < 1e-6);
+}
+
+#[test]
+fn test_world_to_screen() {
+    let mut camera = Camera::new(
+        vec3(1.0, 2.0, 3.0),
+        vec3(1.0, -1.0, -1.0),
+        90.0,
+        Resolution { w: 64, h: 48 },
+    )
+    .unwrap();
+    let close =
+        |(x, y): (f32, f32), (ex, ey): (f32, f32)| (x - ex).abs() < 1e-3 && (y - ey).abs() < 1e-3;
+    // The center of the image plane, and any point further along the view
+    // direction, is in the center of the screen.
+    let plane = camera.image_plane;
+    let center = (plane.top_left + plane.bottom_right) * 0.5;
+    assert!(close(camera.world_to_screen(center).unwrap(), (32.0, 24.0)));
+    let far = camera.pos + camera.get_view_direction() * 10.0;
+    assert!(close(camera.world_to_screen(far).unwrap(), (32.0, 24.0)));
+    // The corners of the image plane are the corners of the screen.
+    assert!(close(
+        camera.world_to_screen(plane.top_left).unwrap(),
+        (0.0, 0.0)
+    ));
+    assert!(close(
+        camera.world_to_screen(plane.bottom_right).unwrap(),
+        (64.0, 48.0)
+    ));
+    // Points behind the camera aren't on the screen.
+    let behind = camera.pos - camera.get_view_direction();
+    assert_eq!(camera.world_to_screen(behind), None);
+
+    camera.projection_model = ProjectionModel::Spherical;
+    assert!(close(camera.world_to_screen(far).unwrap(), (32.0, 24.0)));
+}
+
+#[test]
+fn test_pixel_aspect() {
+    let mut camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 64, h: 32 },
+    )
+    .unwrap();
+    let square = camera.image_plane;
+    assert!(camera.set_pixel_aspect(0.0).is_err());
+    camera.set_pixel_aspect(2.0).unwrap();
+    let anamorphic = camera.image_plane;
+    // The horizontal field of view stays the same, while the image plane
+    // becomes half as high.
+    for (a, b) in [
+        (square.top_left, anamorphic.top_left),
+        (square.top_right, anamorphic.top_right),
+        (square.bottom_right, anamorphic.bottom_right),
+        (square.bottom_left, anamorphic.bottom_left),
+    ] {
+        assert!((a.x - b.x).abs() < 1e-6 && (a.z - b.z).abs() < 1e-6);
+        assert!((a.y / 2.0 - b.y).abs() < 1e-6);
+    }
+    assert!((anamorphic.top_left.y - 0.25).abs() < 1e-6);
+}
+
+#[test]
+fn test_field_of_view_vertical() {
+    let mut camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 200, h: 100 },
+    )
+    .unwrap();
+    // By default, the vertical field of view follows from the aspect ratio.
+    let implied = 2.0 * 0.5_f32.atan() * (180.0 / PI);
+    assert!((camera.get_field_of_view_vertical_deg() - implied).abs() < 1e-4);
+    assert!((camera.image_plane.top_left.y - 0.5).abs() < 1e-6);
+
+    assert!(camera.set_field_of_view_vertical_deg(0.0).is_err());
+    assert!(camera.set_field_of_view_vertical_deg(180.0).is_err());
+    camera.set_field_of_view_vertical_deg(90.0).unwrap();
+    assert!((camera.get_field_of_view_vertical_deg() - 90.0).abs() < 1e-4);
+    // The image plane is now as high as it is wide, and changing the
+    // horizontal field of view leaves its height alone.
+    assert!((camera.image_plane.top_left.y - 1.0).abs() < 1e-6);
+    assert!((camera.image_plane.top_right.x - 1.0).abs() < 1e-6);
+    camera.set_field_of_view_horizontal_deg(120.0).unwrap();
+    assert!((camera.image_plane.top_left.y - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_roll() {
+    let mut camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 100, h: 100 },
+    )
+    .unwrap();
+    let level = camera.image_plane;
+    assert!(level.top_left.y > level.bottom_left.y);
+
+    // Tilted a quarter turn clockwise, the left edge of the image is at the
+    // top, with its top end on the right.
+    camera.set_roll(PI / 2.0);
+    let tilted = camera.image_plane;
+    assert!((tilted.top_left - vec3(1.0, 1.0, -1.0)).len() < 1e-6);
+    assert!((tilted.bottom_left - vec3(-1.0, 1.0, -1.0)).len() < 1e-6);
+    assert!((tilted.top_right - vec3(1.0, -1.0, -1.0)).len() < 1e-6);
+
+    // Looking somewhere else keeps the roll.
+    camera.look_at(vec3(1.0, 0.0, 0.0));
+    let plane = camera.image_plane;
+    assert!((plane.top_left.y - plane.bottom_left.y).abs() < 1e-6);
+}
+
+#[test]
+fn test_right_vector() {
+    let mut camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(1.0, 0.0, 0.0),
+        90.0,
+        Resolution { w: 100, h: 50 },
+    )
+    .unwrap();
+    assert!((camera.get_right_vector() - vec3(0.0, 0.0, 1.0)).len() < 1e-6);
+    camera.look_at(vec3(0.0, -1.0, -1.0));
+    assert!((camera.get_right_vector() - vec3(1.0, 0.0, 0.0)).len() < 1e-6);
+}
+
+#[test]
+fn test_rotate_yaw_pitch() {
+    let mut camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 100, h: 50 },
+    )
+    .unwrap();
+    camera.rotate_yaw_pitch(PI / 2.0, 0.0);
+    assert!((camera.get_view_direction() - vec3(1.0, 0.0, 0.0)).len() < 1e-6);
+    camera.rotate_yaw_pitch(0.0, PI / 4.0);
+    let up_and_right = vec3(1.0, 1.0, 0.0).normalized();
+    assert!((camera.get_view_direction() - up_and_right).len() < 1e-6);
+
+    // Looking up stops just short of straight up.
+    camera.rotate_yaw_pitch(0.0, PI);
+    let direction = camera.get_view_direction();
+    assert!((direction.y - MAX_PITCH.sin()).abs() < 1e-6);
+    assert!(direction.x > 0.0);
+}
+
+#[test]
+fn test_look_at_straight_up_and_down() {
+    let mut camera = Camera::new(
+        vec3(1.0, 2.0, 3.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 100, h: 50 },
+    )
+    .unwrap();
+    for target in [vec3(1.0, 10.0, 3.0), vec3(1.0, -10.0, 3.0)] {
+        camera.look_at(target);
+        let plane = camera.image_plane;
+        for corner in [
+            plane.top_left,
+            plane.top_right,
+            plane.bottom_right,
+            plane.bottom_left,
+        ] {
+            assert!(corner.x.is_finite() && corner.y.is_finite() && corner.z.is_finite());
+        }
+        let across = plane.top_right - plane.top_left;
+        let down = plane.bottom_left - plane.top_left;
+        assert!((across.len() - 2.0).abs() < 1e-4);
+        assert!((down.len() - 1.0).abs() < 1e-4);
+        assert!(across.cross_product(down).len() > 1.0);
+        // The image plane is centered on the view direction.
+        let center = (plane.top_left + plane.bottom_right) * 0.5;
+        assert!((center - camera.pos - camera.get_view_direction()).len() < 1e-4);
+
+        // Rounding errors in the direction don't spin the image around.
+        let nudged = [vec3(1e-5, 0.0, 0.0), vec3(0.0, 0.0, 1e-5)].map(|offset| {
+            camera.look_at(target + offset);
+            camera.image_plane.top_left
+        });
+        assert!((nudged[0] - plane.top_left).len() < 1e-4);
+        assert!((nudged[1] - plane.top_left).len() < 1e-4);
+        // Tilting slightly towards -z, off the vertical, barely changes it.
+        camera.look_at(target + vec3(0.0, 0.0, -0.05));
+        assert!((camera.image_plane.top_left - plane.top_left).len() < 0.05);
+    }
 }
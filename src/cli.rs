@@ -0,0 +1,211 @@
+use std::path::{Path, PathBuf};
+
+use crate::camera::Camera;
+use crate::errors::ArgumentError;
+use crate::renderer::{Renderer, AMBIENT_OCCLUSION_DISTANCE};
+use crate::sampling::SamplingConfig;
+use crate::util::{save_frame_as_png, save_frame_as_ppm, Resolution};
+use crate::world::Scene;
+
+const USAGE: &str =
+    "Usage: renderer render --scene <scene.json> --out <image.png|image.ppm> [--width <w>] [--height <h>] [--ao <samples>] [--cubemap <face size>]";
+
+/// The suffixes added to the output file name of each cubemap face, in the
+/// order they are rendered and loaded as a skybox: +X, -X, +Y, -Y, +Z, -Z.
+const CUBEMAP_FACE_SUFFIXES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+/// Arguments of the `render` subcommand, which renders a single frame of a
+/// scene to an image file without opening a window.
+#[derive(Debug)]
+pub struct RenderArgs {
+    pub scene: PathBuf,
+    pub out: PathBuf,
+    pub resolution: Resolution,
+    /// The sampling settings of the renderer. `--ao` sets the number of
+    /// samples per vertex ambient occlusion is baked with before rendering.
+    pub sampling: SamplingConfig,
+    /// Render the six faces of a cubemap of this size from the camera's
+    /// position, instead of the camera's view.
+    pub cubemap_size: Option<u32>,
+}
+
+impl RenderArgs {
+    /// Parse the arguments following the `render` subcommand.
+    pub fn parse(args: &[String]) -> Result<RenderArgs, ArgumentError> {
+        let mut scene = None;
+        let mut out = None;
+        let mut resolution = Resolution { w: 800, h: 600 };
+        let mut sampling = SamplingConfig::default();
+        let mut cubemap_size = None;
+
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            if ![
+                "--scene",
+                "--out",
+                "--width",
+                "--height",
+                "--ao",
+                "--cubemap",
+            ]
+            .contains(&flag.as_str())
+            {
+                return Err(ArgumentError::UnknownArgument(flag.clone()));
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| ArgumentError::MissingValue(flag.clone()))?;
+            let parse_u32 = |min| {
+                value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|v| *v >= min)
+                    .ok_or_else(|| ArgumentError::InvalidValue(flag.clone(), value.clone()))
+            };
+            match flag.as_str() {
+                "--scene" => scene = Some(PathBuf::from(value)),
+                "--out" => out = Some(PathBuf::from(value)),
+                "--width" => resolution.w = parse_u32(1)?,
+                "--height" => resolution.h = parse_u32(1)?,
+                "--ao" => sampling.ao = parse_u32(0)?,
+                "--cubemap" => cubemap_size = Some(parse_u32(1)?),
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(RenderArgs {
+            scene: scene.ok_or(ArgumentError::MissingArgument("--scene"))?,
+            out: out.ok_or(ArgumentError::MissingArgument("--out"))?,
+            resolution,
+            sampling,
+            cubemap_size,
+        })
+    }
+}
+
+/// Run the `render` subcommand: load the scene, render one frame and save it
+/// as a png or ppm. Returns the process exit code.
+pub fn run_render_command(args: &[String]) -> i32 {
+    let args = match RenderArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    match render_scene_to_file(&args) {
+        Ok(paths) => {
+            for path in paths {
+                println!("Saved render to {}", path.display());
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("Rendering failed: {}", e);
+            1
+        }
+    }
+}
+
+/// Render the scene and save it, returning the paths of the saved images.
+fn render_scene_to_file(args: &RenderArgs) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut scene = Scene::load(Some(&args.scene))?;
+    println!("{}", scene.world.summary());
+    for warning in scene.validate() {
+        println!("Scene warning: {}", warning);
+    }
+    scene.world.cache_faces();
+    let renderer = Renderer {
+        sampling: args.sampling,
+        ..Renderer::default()
+    };
+    if renderer.sampling.ao > 0 {
+        renderer.bake_ambient_occlusion(&mut scene.world, AMBIENT_OCCLUSION_DISTANCE);
+    }
+    if let Some(face_size) = args.cubemap_size {
+        let faces = renderer.render_cubemap(&scene.world, scene.camera.pos, face_size);
+        let resolution = Resolution {
+            w: face_size,
+            h: face_size,
+        };
+        let mut paths = Vec::new();
+        for (face, suffix) in faces.iter().zip(CUBEMAP_FACE_SUFFIXES) {
+            let path = cubemap_face_path(&args.out, suffix);
+            save_frame(face, resolution, &path)?;
+            paths.push(path);
+        }
+        return Ok(paths);
+    }
+    let camera = Camera::from_settings(&scene.camera, args.resolution)?;
+    if args
+        .out
+        .extension()
+        .is_some_and(|extension| extension == "ppm")
+    {
+        renderer.render_to_ppm(&scene.world, &camera, &args.out)?;
+    } else {
+        renderer.render_to_png(&scene.world, &camera, &args.out)?;
+    }
+    Ok(vec![args.out.clone()])
+}
+
+/// Save a frame as a ppm when the path ends in `.ppm`, and as a png otherwise.
+fn save_frame(
+    frame_buffer: &[u8],
+    resolution: Resolution,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if path.extension().is_some_and(|extension| extension == "ppm") {
+        save_frame_as_ppm(frame_buffer, resolution, path)
+    } else {
+        save_frame_as_png(frame_buffer, resolution, path)
+    }
+}
+
+/// Get the path a cubemap face is saved to: the output path with the face's
+/// suffix added to the file name, e.g. `sky_px.png` for `sky.png`.
+fn cubemap_face_path(out: &Path, suffix: &str) -> PathBuf {
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    let mut file_name = format!("{}_{}", stem, suffix);
+    if let Some(extension) = out.extension() {
+        file_name += &format!(".{}", extension.to_string_lossy());
+    }
+    out.with_file_name(file_name)
+}
+
+#[test]
+fn test_parse_render_args() {
+    let to_args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+    let args = to_args(&["--scene", "s.json", "--out", "o.png", "--width", "1920"]);
+    let parsed = RenderArgs::parse(&args).unwrap();
+    assert_eq!(parsed.scene, PathBuf::from("s.json"));
+    assert_eq!(parsed.out, PathBuf::from("o.png"));
+    assert_eq!((parsed.resolution.w, parsed.resolution.h), (1920, 600));
+    assert_eq!(parsed.sampling, SamplingConfig::default());
+    let args = to_args(&["--scene", "s.json", "--out", "o.png", "--ao", "64"]);
+    assert_eq!(RenderArgs::parse(&args).unwrap().sampling.ao, 64);
+    let args = to_args(&["--scene", "s.json", "--out", "o.png", "--cubemap", "256"]);
+    assert_eq!(RenderArgs::parse(&args).unwrap().cubemap_size, Some(256));
+
+    assert!(RenderArgs::parse(&to_args(&["--scene", "s.json"])).is_err());
+    assert!(RenderArgs::parse(&to_args(&["--scene", "s.json", "--out"])).is_err());
+    assert!(RenderArgs::parse(&to_args(&[
+        "--out", "o.png", "--scene", "s", "--width", "0"
+    ]))
+    .is_err());
+    assert!(matches!(
+        RenderArgs::parse(&to_args(&["--fast"])),
+        Err(ArgumentError::UnknownArgument(_))
+    ));
+}
+
+#[test]
+fn test_cubemap_face_path() {
+    let path = cubemap_face_path(Path::new("out/sky.png"), "px");
+    assert_eq!(path, PathBuf::from("out/sky_px.png"));
+    assert_eq!(
+        cubemap_face_path(Path::new("sky"), "nz"),
+        PathBuf::from("sky_nz")
+    );
+}
@@ -0,0 +1,126 @@
+use std::ops::{Add, AddAssign, Mul, Sub};
+
+use crate::objects::LightIntensity;
+
+use rgb::*;
+
+/// A linear RGB color used while shading, with channels on the same 0 to 255
+/// scale as `RGBA8`. Unlike `RGBA8` the channels aren't limited to that range,
+/// so light can be accumulated without clipping in between.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Color(pub [f32; 3]);
+
+impl Color {
+    pub const BLACK: Color = Color([0.0; 3]);
+
+    pub fn new(r: f32, g: f32, b: f32) -> Color {
+        Color([r, g, b])
+    }
+
+    #[cfg(test)]
+    pub fn r(&self) -> f32 {
+        self.0[0]
+    }
+
+    #[cfg(test)]
+    pub fn g(&self) -> f32 {
+        self.0[1]
+    }
+
+    #[cfg(test)]
+    pub fn b(&self) -> f32 {
+        self.0[2]
+    }
+
+    /// Apply a function to every channel.
+    pub fn map<F: Fn(f32) -> f32>(self, f: F) -> Color {
+        Color(self.0.map(f))
+    }
+
+    /// Convert to `RGBA8`, saturating channels outside of 0 to 255.
+    pub fn to_rgba8(self) -> RGBA8 {
+        let [r, g, b] = self.0.map(|c| c.clamp(0.0, 255.0) as u8);
+        RGBA8::new(r, g, b, 255)
+    }
+}
+
+impl Add for Color {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Color([
+            self.0[0] + rhs.0[0],
+            self.0[1] + rhs.0[1],
+            self.0[2] + rhs.0[2],
+        ])
+    }
+}
+
+impl AddAssign for Color {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Color {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Color([
+            self.0[0] - rhs.0[0],
+            self.0[1] - rhs.0[1],
+            self.0[2] - rhs.0[2],
+        ])
+    }
+}
+
+impl Mul<f32> for Color {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        self.map(|c| c * rhs)
+    }
+}
+
+/// Componentwise multiplication, e.g. to filter light by a surface color.
+impl Mul for Color {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Color([
+            self.0[0] * rhs.0[0],
+            self.0[1] * rhs.0[1],
+            self.0[2] * rhs.0[2],
+        ])
+    }
+}
+
+impl From<RGBA8> for Color {
+    fn from(rgba: RGBA8) -> Self {
+        Color::new(rgba.r as f32, rgba.g as f32, rgba.b as f32)
+    }
+}
+
+impl From<LightIntensity> for Color {
+    fn from(intensity: LightIntensity) -> Self {
+        Color::new(intensity.r, intensity.g, intensity.b)
+    }
+}
+
+#[test]
+fn test_color_saturation() {
+    let rgba = Color::new(254.9, 255.0, 255.5).to_rgba8();
+    assert_eq!(rgba, RGBA8::new(254, 255, 255, 255));
+    let rgba = (Color::new(200.0, 100.0, -10.0) * 2.0).to_rgba8();
+    assert_eq!(rgba, RGBA8::new(255, 200, 0, 255));
+
+    let mut sum = Color::BLACK;
+    sum += Color::new(250.0, 1.0, 2.0);
+    sum += Color::new(10.0, 1.0, 2.0);
+    assert_eq!(sum, Color::new(260.0, 2.0, 4.0));
+    assert_eq!(
+        sum - Color::new(10.0, 1.0, 5.0),
+        Color::new(250.0, 1.0, -1.0)
+    );
+    assert_eq!(sum * Color::new(0.5, 1.0, 0.0), Color::new(130.0, 2.0, 0.0));
+}
@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::renderer::MultithreadingMethod;
+use crate::util::{load_object_from_file_json, Resolution};
+
+use serde::{Deserialize, Serialize};
+
+/// The file the startup configuration is read from, unless another file is
+/// given by the `RENDERER_CONFIG` environment variable.
+pub const DEFAULT_CONFIG_PATH: &str = "renderer.json";
+
+/// Settings used when starting the interactive renderer. Every field is
+/// optional in the config file, missing fields keep their default value.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub width: u32,
+    pub height: u32,
+    pub multithreading_method: MultithreadingMethod,
+    /// Limit the renderer to this many threads, instead of one per core.
+    pub thread_count: Option<usize>,
+    pub grayscale: bool,
+    /// The initial `sampling.ao` of the renderer: bake ambient occlusion with
+    /// this many samples per vertex when the scene is loaded, 0 to skip it.
+    pub ao_samples: u32,
+    /// The maximum time in milliseconds to spend on rendering a frame, after
+    /// which the rest of the frame is rendered in the next one.
+    pub time_budget_ms: Option<u64>,
+    /// The scene to load, `res/scenes/default.json` is used when this isn't set.
+    pub scene: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            width: 800,
+            height: 600,
+            multithreading_method: MultithreadingMethod::Rayon,
+            thread_count: None,
+            grayscale: false,
+            ao_samples: 0,
+            time_budget_ms: None,
+            scene: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load the startup configuration, falling back to the defaults when
+    /// there is no config file.
+    pub fn load() -> Result<Config, Box<dyn Error>> {
+        let path = std::env::var_os("RENDERER_CONFIG")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+        Config::load_from(&path)
+    }
+
+    /// Load the configuration from a json file, falling back to the defaults
+    /// when the file doesn't exist.
+    pub fn load_from(path: &Path) -> Result<Config, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        load_object_from_file_json(path)
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        Resolution {
+            w: self.width,
+            h: self.height,
+        }
+    }
+}
+
+#[test]
+fn test_partial_config() {
+    let config: Config = serde_json::from_str(r#"{ "width": 320, "grayscale": true }"#).unwrap();
+    assert_eq!((config.width, config.height), (320, 600));
+    assert!(config.grayscale);
+    assert!(config.scene.is_none());
+
+    let config = Config::load_from(Path::new("does/not/exist.json")).unwrap();
+    assert_eq!((config.width, config.height), (800, 600));
+}
@@ -11,6 +11,9 @@ quick_error! {
         PhiOutOfBounds {
             display("Phi out of legal range [0, 2*PI]")
         }
+        NonFiniteInput {
+            display("Cannot convert a vector with NaN or infinite components.")
+        }
     }
 }
 
@@ -20,7 +23,97 @@ quick_error! {
     #[derive(Debug)]
     pub enum CameraSettingError {
         InvalidFOV(value: f32) {
-            display("Field of view value {} is invalid, allowed values: 0 < FOV < (PI rad or 180 degrees)", value)
+            display("Field of view value {} is invalid, allowed values: 0 < FOV <= 179 degrees", value)
+        }
+        InvalidPixelAspect(value: f32) {
+            display("Pixel aspect ratio {} is invalid, it must be larger than 0", value)
+        }
+    }
+}
+
+quick_error! {
+    /// Errors that occur while parsing command line arguments.
+    #[derive(Debug)]
+    pub enum ArgumentError {
+        UnknownArgument(arg: String) {
+            display("Unknown argument: {}", arg)
+        }
+        MissingValue(flag: String) {
+            display("Missing value for argument {}", flag)
+        }
+        MissingArgument(flag: &'static str) {
+            display("Missing required argument {}", flag)
+        }
+        InvalidValue(flag: String, value: String) {
+            display("Invalid value '{}' for argument {}", value, flag)
+        }
+    }
+}
+
+quick_error! {
+    /// Common mistakes in a scene that don't prevent it from being rendered,
+    /// but likely make it look different than intended.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum SceneWarning {
+        DarkLight(light_index: usize) {
+            display("Light {} has zero intensity.", light_index)
+        }
+        NoDiffuse(object: String) {
+            display("The material of {} has no diffuse reflection.", object)
+        }
+        InvisibleMaterial(object: String) {
+            display("The material of {} is black and reflects no light, so it's invisible.", object)
+        }
+        ExtremeFieldOfView(fov: f32) {
+            display("The camera's field of view of {} degrees is extreme.", fov)
+        }
+        DegenerateTriangle(object_index: usize, face_index: usize) {
+            display("Face {} of vertex object {} has no area.", face_index, object_index)
+        }
+        MissingMesh(instance_index: usize) {
+            display("Instance {} refers to a mesh that doesn't exist.", instance_index)
+        }
+        MissingMaterialLibrary(path: String) {
+            display("Material library {} can't be read, so its materials aren't used.", path)
+        }
+    }
+}
+
+quick_error! {
+    /// Mistakes in a scene that make it impossible to render.
+    #[derive(Debug, PartialEq)]
+    pub enum SceneError {
+        FaceIndexOutOfRange(object: String, face_index: usize) {
+            display("Face {} of {} refers to a vertex that doesn't exist.", face_index, object)
+        }
+    }
+}
+
+quick_error! {
+    /// Errors that occur while parsing a Wavefront .obj file.
+    #[derive(Debug)]
+    pub enum ObjParseError {
+        InvalidVertex(line: usize) {
+            display("Line {}: a vertex needs three numeric coordinates.", line)
+        }
+        InvalidFace(line: usize) {
+            display("Line {}: a face needs at least three valid vertex indices.", line)
+        }
+        IndexOutOfRange(line: usize, index: i64) {
+            display("Line {}: vertex index {} doesn't refer to a vertex.", line, index)
+        }
+        InvalidMaterialValue(path: String, line: usize) {
+            display("{}, line {}: a color needs three numbers and a shininess one.", path, line)
+        }
+    }
+}
+
+quick_error! {
+    /// Errors about frame buffers that don't fit the image they should hold.
+    #[derive(Debug)]
+    pub enum FrameBufferError {
+        WrongLength(expected: usize, actual: usize) {
+            display("The frame buffer is {} bytes long, but the resolution needs {} bytes.", actual, expected)
         }
     }
 }
@@ -1,18 +1,194 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use winit::event::{Event, VirtualKeyCode};
 use winit::event_loop::ControlFlow;
 use winit_input_helper::WinitInputHelper;
 
 use pixels::Pixels;
 
-use crate::camera::Camera;
+use crate::camera::{Camera, ProjectionModel, MAX_FIELD_OF_VIEW_DEG};
 
-use crate::renderer::MultithreadingMethod;
+use crate::objects::Material;
+use crate::renderer::RenderMode;
 use crate::renderer::Renderer;
+use crate::renderer::{Filter, MultithreadingMethod};
 use crate::util::move_pyramid;
 use crate::vector::*;
-use crate::world::World;
+use crate::world::{ObjectHandle, Scene, World};
 
 const STEPSIZE: f32 = 0.2;
+/// How far the camera turns (in radians) per pixel the mouse moves.
+const MOUSE_SENSITIVITY: f32 = 0.005;
+/// The tile size (in pixels) used when switching to tiled multithreading.
+const DEFAULT_TILE_SIZE: u32 = 16;
+/// The material presets that selected objects can be turned into.
+const GLASS_REFRACTIVE_INDEX: f32 = 1.5;
+const METAL_ROUGHNESS: f32 = 0.2;
+
+/// The key for every action of `handle_input`. The default bindings are the
+/// layout described in the comments of `handle_input`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyBindings {
+    pub decrease_diffuse: VirtualKeyCode,
+    pub increase_diffuse: VirtualKeyCode,
+    pub decrease_specular: VirtualKeyCode,
+    pub increase_specular: VirtualKeyCode,
+    pub decrease_shine: VirtualKeyCode,
+    pub increase_shine: VirtualKeyCode,
+    pub decrease_reflectivity: VirtualKeyCode,
+    pub increase_reflectivity: VirtualKeyCode,
+    pub make_glass: VirtualKeyCode,
+    pub make_metal: VirtualKeyCode,
+    pub toggle_wireframe: VirtualKeyCode,
+    pub decrease_field_of_view: VirtualKeyCode,
+    pub increase_field_of_view: VirtualKeyCode,
+    pub quit: VirtualKeyCode,
+    pub move_forward: VirtualKeyCode,
+    pub move_backward: VirtualKeyCode,
+    pub move_right: VirtualKeyCode,
+    pub move_left: VirtualKeyCode,
+    /// Moving down is always bound to Shift, as it's a modifier key.
+    pub move_up: VirtualKeyCode,
+    pub pyramid_left: VirtualKeyCode,
+    pub pyramid_right: VirtualKeyCode,
+    pub pyramid_down: VirtualKeyCode,
+    pub pyramid_up: VirtualKeyCode,
+    pub pyramid_backward: VirtualKeyCode,
+    pub pyramid_forward: VirtualKeyCode,
+    pub reload_scene: VirtualKeyCode,
+    pub screenshot: VirtualKeyCode,
+    pub print_ray_stats: VirtualKeyCode,
+    pub toggle_pause: VirtualKeyCode,
+    pub toggle_balls: VirtualKeyCode,
+    pub toggle_triangles: VirtualKeyCode,
+    pub toggle_planes: VirtualKeyCode,
+    pub toggle_boxes: VirtualKeyCode,
+    pub cycle_render_mode: VirtualKeyCode,
+    pub cycle_preset: VirtualKeyCode,
+    pub save_preset: VirtualKeyCode,
+    pub save_world: VirtualKeyCode,
+    pub toggle_bvh: VirtualKeyCode,
+    pub toggle_grayscale: VirtualKeyCode,
+    pub toggle_projection: VirtualKeyCode,
+    pub cycle_multithreading: VirtualKeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            decrease_diffuse: VirtualKeyCode::J,
+            increase_diffuse: VirtualKeyCode::U,
+            decrease_specular: VirtualKeyCode::K,
+            increase_specular: VirtualKeyCode::I,
+            decrease_shine: VirtualKeyCode::L,
+            increase_shine: VirtualKeyCode::O,
+            decrease_reflectivity: VirtualKeyCode::H,
+            increase_reflectivity: VirtualKeyCode::Y,
+            make_glass: VirtualKeyCode::Key5,
+            make_metal: VirtualKeyCode::Key6,
+            toggle_wireframe: VirtualKeyCode::F,
+            decrease_field_of_view: VirtualKeyCode::Minus,
+            increase_field_of_view: VirtualKeyCode::Equals,
+            quit: VirtualKeyCode::Escape,
+            move_forward: VirtualKeyCode::W,
+            move_backward: VirtualKeyCode::S,
+            move_right: VirtualKeyCode::D,
+            move_left: VirtualKeyCode::A,
+            move_up: VirtualKeyCode::Space,
+            pyramid_left: VirtualKeyCode::Left,
+            pyramid_right: VirtualKeyCode::Right,
+            pyramid_down: VirtualKeyCode::Down,
+            pyramid_up: VirtualKeyCode::Up,
+            pyramid_backward: VirtualKeyCode::End,
+            pyramid_forward: VirtualKeyCode::Home,
+            reload_scene: VirtualKeyCode::R,
+            screenshot: VirtualKeyCode::F12,
+            print_ray_stats: VirtualKeyCode::F2,
+            toggle_pause: VirtualKeyCode::T,
+            toggle_balls: VirtualKeyCode::Key1,
+            toggle_triangles: VirtualKeyCode::Key2,
+            toggle_planes: VirtualKeyCode::Key3,
+            toggle_boxes: VirtualKeyCode::Key4,
+            cycle_render_mode: VirtualKeyCode::V,
+            cycle_preset: VirtualKeyCode::Tab,
+            save_preset: VirtualKeyCode::F5,
+            save_world: VirtualKeyCode::F6,
+            toggle_bvh: VirtualKeyCode::B,
+            toggle_grayscale: VirtualKeyCode::G,
+            toggle_projection: VirtualKeyCode::P,
+            cycle_multithreading: VirtualKeyCode::M,
+        }
+    }
+}
+
+#[test]
+fn test_default_key_bindings_are_unique() {
+    let keys = KeyBindings::default();
+    let bound = [
+        keys.decrease_diffuse,
+        keys.increase_diffuse,
+        keys.decrease_specular,
+        keys.increase_specular,
+        keys.decrease_shine,
+        keys.increase_shine,
+        keys.decrease_reflectivity,
+        keys.increase_reflectivity,
+        keys.make_glass,
+        keys.make_metal,
+        keys.toggle_wireframe,
+        keys.decrease_field_of_view,
+        keys.increase_field_of_view,
+        keys.quit,
+        keys.move_forward,
+        keys.move_backward,
+        keys.move_right,
+        keys.move_left,
+        keys.move_up,
+        keys.pyramid_left,
+        keys.pyramid_right,
+        keys.pyramid_down,
+        keys.pyramid_up,
+        keys.pyramid_backward,
+        keys.pyramid_forward,
+        keys.reload_scene,
+        keys.screenshot,
+        keys.print_ray_stats,
+        keys.toggle_pause,
+        keys.toggle_balls,
+        keys.toggle_triangles,
+        keys.toggle_planes,
+        keys.toggle_boxes,
+        keys.cycle_render_mode,
+        keys.cycle_preset,
+        keys.save_preset,
+        keys.save_world,
+        keys.toggle_bvh,
+        keys.toggle_grayscale,
+        keys.toggle_projection,
+        keys.cycle_multithreading,
+    ];
+    for (i, key) in bound.iter().enumerate() {
+        assert!(!bound[i + 1..].contains(key), "{:?} is bound twice", key);
+    }
+}
+
+/// The state of the interactive session that input acts on, besides the
+/// world, camera and renderer.
+pub struct AppState {
+    pub key_bindings: KeyBindings,
+    /// Which ball the camera looks at after a left click.
+    pub click_count: u8,
+    /// The object picked with the right mouse button.
+    pub selected: Option<ObjectHandle>,
+    /// The renderer presets that the preset key cycles through.
+    pub presets: Vec<Renderer>,
+    /// The scene file to reload, or `None` for the default scene.
+    pub scene_path: Option<PathBuf>,
+}
+
+/// Accessor for one of the numeric properties of a `Material`.
+type MaterialField = fn(&mut Material) -> &mut f32;
 
 /// Handle input.
 pub fn handle_input(
@@ -23,77 +199,320 @@ pub fn handle_input(
     camera: &mut Camera,
     renderer: &mut Renderer,
     pixels: &mut Pixels,
-    multithreading: &mut bool,
-    click_count: &mut u8,
+    app: &mut AppState,
 ) {
+    let keys = app.key_bindings;
     // Check if the left mouse button was pressed.
     if input.mouse_pressed(0) {
-        *click_count += 1;
-        *click_count = *click_count % 3;
-        if let Some(ball) = world.balls.get(*click_count as usize) {
+        app.click_count += 1;
+        app.click_count %= 3;
+        if let Some(ball) = world.balls.get(app.click_count as usize) {
             camera.look_at(ball.pos);
-            let c = *click_count + 1;
+            let c = app.click_count + 1;
             println!("Looking at ball: {c:?}");
         }
     }
 
-    // Change the camera FOV:
-    if input.key_pressed(VirtualKeyCode::Minus) {
-        if camera.get_field_of_view_horizontal_deg() > 1.0 {
-            // Unwrap is safe because decreasing the FOV will only return an
-            // error if the value is smaller than, or equal to 0.
-            camera
-                .set_field_of_view_horizontal_deg(camera.get_field_of_view_horizontal_deg() - 1.0)
-                .unwrap();
+    // Select the object under the mouse with the right mouse button.
+    if input.mouse_pressed(1) {
+        if let Some(Ok((x, y))) = input.mouse().map(|pos| pixels.window_pos_to_pixel(pos)) {
+            app.selected = renderer.pick(world, camera, x as u32, y as u32);
+            match app.selected {
+                Some(handle) => println!("Selected {:?}.", handle),
+                None => println!("Nothing selected."),
+            }
         }
     }
-    if input.key_pressed(VirtualKeyCode::Equals) {
-        if camera.get_field_of_view_horizontal_deg() < 179.0 {
-            // Unwrap is safe because increasing the FOV will only return an
-            // error if the value is greater than 180.
-            camera
-                .set_field_of_view_horizontal_deg(camera.get_field_of_view_horizontal_deg() + 1.0)
-                .unwrap();
+
+    // Look around by dragging with the right mouse button held.
+    if input.mouse_held(1) {
+        let (dx, dy) = input.mouse_diff();
+        if dx != 0.0 || dy != 0.0 {
+            camera.rotate_yaw_pitch(dx * MOUSE_SENSITIVITY, -dy * MOUSE_SENSITIVITY);
         }
     }
 
+    // Tune the material of the selected object: J/U for the diffuse
+    // constant, K/I for the specular constant, L/O for the shine and H/Y for
+    // the reflectivity, which can't go above 1. 5 and 6 turn it into glass
+    // and metal.
+    if let Some(material) = app.selected.and_then(|handle| world.material_mut(handle)) {
+        let adjustments: [(VirtualKeyCode, MaterialField, f32); 8] = [
+            (keys.decrease_diffuse, |m| &mut m.diffuse_constant, -5.0),
+            (keys.increase_diffuse, |m| &mut m.diffuse_constant, 5.0),
+            (keys.decrease_specular, |m| &mut m.specular_constant, -5.0),
+            (keys.increase_specular, |m| &mut m.specular_constant, 5.0),
+            (keys.decrease_shine, |m| &mut m.shine, -1.0),
+            (keys.increase_shine, |m| &mut m.shine, 1.0),
+            (keys.decrease_reflectivity, |m| &mut m.reflectivity, -0.1),
+            (keys.increase_reflectivity, |m| &mut m.reflectivity, 0.1),
+        ];
+        let mut changed = false;
+        for (key, field, step) in adjustments {
+            if input.key_pressed(key) {
+                let value = field(material);
+                *value = (*value + step).max(0.0);
+                changed = true;
+            }
+        }
+        if input.key_pressed(keys.make_glass) {
+            *material = Material::glass(GLASS_REFRACTIVE_INDEX);
+            changed = true;
+        }
+        if input.key_pressed(keys.make_metal) {
+            *material = Material::metal(material.ambient_constant, METAL_ROUGHNESS);
+            changed = true;
+        }
+        if changed {
+            material.reflectivity = material.reflectivity.min(1.0);
+            println!(
+                "diffuse: {}, specular: {}, shine: {}, reflectivity: {}",
+                material.diffuse_constant,
+                material.specular_constant,
+                material.shine,
+                material.reflectivity
+            );
+        }
+    }
+
+    // Show or hide the edges of the selected object.
+    if input.key_pressed(keys.toggle_wireframe) {
+        if let Some(handle) = app.selected {
+            match world.wireframes.iter().position(|&h| h == handle) {
+                Some(i) => {
+                    world.wireframes.remove(i);
+                }
+                None => world.wireframes.push(handle),
+            }
+        }
+    }
+
+    // Change the camera FOV:
+    if input.key_pressed(keys.decrease_field_of_view)
+        && camera.get_field_of_view_horizontal_deg() > 1.0
+    {
+        // Unwrap is safe because decreasing the FOV will only return an
+        // error if the value is smaller than, or equal to 0.
+        camera
+            .set_field_of_view_horizontal_deg(camera.get_field_of_view_horizontal_deg() - 1.0)
+            .unwrap();
+    }
+    if input.key_pressed(keys.increase_field_of_view)
+        && camera.get_field_of_view_horizontal_deg() < MAX_FIELD_OF_VIEW_DEG
+    {
+        // Unwrap is safe because the FOV is capped at the maximum.
+        let field_of_view =
+            (camera.get_field_of_view_horizontal_deg() + 1.0).min(MAX_FIELD_OF_VIEW_DEG);
+        camera
+            .set_field_of_view_horizontal_deg(field_of_view)
+            .unwrap();
+    }
+
     // Close events
-    if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+    if input.key_pressed(keys.quit) || input.quit() {
         *control_flow = ControlFlow::Exit;
         return;
     }
 
-    // Move camera (for debug purposes).
-    if input.key_pressed(VirtualKeyCode::Left) {
+    // Fly the camera around: W/S move along the view direction, A/D strafe
+    // sideways and Space/Shift move straight up and down.
+    let movements = [
+        (keys.move_forward, camera.get_view_direction()),
+        (keys.move_backward, -camera.get_view_direction()),
+        (keys.move_right, camera.get_right_vector()),
+        (keys.move_left, -camera.get_right_vector()),
+        (keys.move_up, vec3(0.0, 1.0, 0.0)),
+    ];
+    for (key, direction) in movements {
+        if input.key_held(key) {
+            camera.translate(direction * STEPSIZE);
+        }
+    }
+    if input.held_shift() {
+        camera.translate(vec3(0.0, -STEPSIZE, 0.0));
+    }
+
+    // Move the pyramid (for debug purposes).
+    if input.key_pressed(keys.pyramid_left) {
         move_pyramid(world, vec3(-STEPSIZE, 0.0, 0.0));
         // camera.translate(vec3(-STEPSIZE, 0.0, 0.0));
     }
-    if input.key_pressed(VirtualKeyCode::Right) {
+    if input.key_pressed(keys.pyramid_right) {
         move_pyramid(world, vec3(STEPSIZE, 0.0, 0.0));
         // camera.translate(vec3(STEPSIZE, 0.0, 0.0));
     }
-    if input.key_pressed(VirtualKeyCode::Down) {
+    if input.key_pressed(keys.pyramid_down) {
         move_pyramid(world, vec3(0.0, -STEPSIZE, 0.0));
         // camera.translate(vec3(0.0, -STEPSIZE, 0.0));
     }
-    if input.key_pressed(VirtualKeyCode::Up) {
+    if input.key_pressed(keys.pyramid_up) {
         move_pyramid(world, vec3(0.0, STEPSIZE, 0.0));
         // camera.translate(vec3(0.0, STEPSIZE, 0.0));
     }
-    if input.key_pressed(VirtualKeyCode::End) {
+    if input.key_pressed(keys.pyramid_backward) {
         move_pyramid(world, vec3(0.0, 0.0, -STEPSIZE));
         // camera.translate(vec3(0.0, 0.0, -STEPSIZE));
     }
-    if input.key_pressed(VirtualKeyCode::Home) {
+    if input.key_pressed(keys.pyramid_forward) {
         move_pyramid(world, vec3(0.0, 0.0, STEPSIZE));
         // camera.translate(vec3(0.0, 0.0, STEPSIZE));
     }
 
-    if input.key_pressed(VirtualKeyCode::G) {
-        renderer.grayscale = !renderer.grayscale;
+    // Reload the scene from disk, keeping the current scene if that fails.
+    if input.key_pressed(keys.reload_scene) {
+        let reloaded = Scene::load(app.scene_path.as_deref()).and_then(|scene| {
+            let new_camera = Camera::from_settings(&scene.camera, camera.resolution)?;
+            Ok((scene, new_camera))
+        });
+        match reloaded {
+            Ok((scene, new_camera)) => {
+                for warning in scene.validate() {
+                    println!("Scene warning: {}", warning);
+                }
+                let paused = world.paused;
+                *world = scene.world;
+                world.paused = paused;
+                app.selected = None;
+                *camera = new_camera;
+                println!("Reloaded the scene.");
+            }
+            Err(e) => println!("Failed to reload the scene: {}", e),
+        }
     }
 
-    if input.key_pressed(VirtualKeyCode::M) {
+    // Save a screenshot of the current frame.
+    if input.key_pressed(keys.screenshot) {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let path = format!("screenshot_{}.png", seconds);
+        match renderer.render_to_png(world, camera, &path) {
+            Ok(()) => println!("Saved a screenshot to {}.", path),
+            Err(e) => println!("Failed to save a screenshot: {}", e),
+        }
+    }
+
+    // Count the rays the current frame takes, to see what reflections cost.
+    if input.key_pressed(keys.print_ray_stats) {
+        let stats = renderer.ray_stats(world, camera);
+        println!(
+            "{} camera rays and {} reflected or refracted rays per frame.",
+            stats.camera_rays, stats.secondary_rays
+        );
+    }
+
+    // Pause or resume the animation ("T" for time).
+    if input.key_pressed(keys.toggle_pause) {
+        world.paused = !world.paused;
+        println!(
+            "{} the animation.",
+            if world.paused { "Paused" } else { "Resumed" }
+        );
+    }
+
+    // Show or hide whole categories of objects, to see each one in isolation.
+    if input.key_pressed(keys.toggle_balls) {
+        renderer.render_balls = !renderer.render_balls;
+    }
+    if input.key_pressed(keys.toggle_triangles) {
+        renderer.render_triangles = !renderer.render_triangles;
+    }
+    if input.key_pressed(keys.toggle_planes) {
+        renderer.render_planes = !renderer.render_planes;
+    }
+    if input.key_pressed(keys.toggle_boxes) {
+        renderer.render_boxes = !renderer.render_boxes;
+    }
+
+    if input.key_pressed(keys.cycle_render_mode) {
+        renderer.render_mode = match renderer.render_mode {
+            RenderMode::Shaded => RenderMode::DiffuseOnly,
+            RenderMode::DiffuseOnly => RenderMode::SpecularOnly,
+            RenderMode::SpecularOnly => RenderMode::Depth,
+            RenderMode::Depth => RenderMode::Normals,
+            RenderMode::Normals => RenderMode::Shaded,
+        };
+        println!("Switching to render mode {:?}.", renderer.render_mode);
+    }
+
+    // Cycle through the renderer presets.
+    let presets = &app.presets;
+    if input.key_pressed(keys.cycle_preset) && !presets.is_empty() {
+        let next = presets
+            .iter()
+            .position(|preset| preset == renderer)
+            .map_or(0, |i| (i + 1) % presets.len());
+        *renderer = presets[next].clone();
+        println!("Switching to renderer preset {}.", next + 1);
+    }
+
+    // Save the current renderer settings, so they can be added to the
+    // presets in `res/presets`.
+    if input.key_pressed(keys.save_preset) {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let path = format!("preset_{}.json", seconds);
+        match renderer.save_preset(&path) {
+            Ok(()) => println!("Saved the renderer settings to {}.", path),
+            Err(e) => println!("Failed to save the renderer settings: {}", e),
+        }
+    }
+
+    // Save the world with the changes made to it, like moved objects and
+    // edited materials. The file can be loaded like any scene file.
+    if input.key_pressed(keys.save_world) {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let path = format!("world_{}.json", seconds);
+        match world.save_to_file_json(&path) {
+            Ok(()) => println!("Saved the world to {}.", path),
+            Err(e) => println!("Failed to save the world: {}", e),
+        }
+    }
+
+    // Compare the speed of the bounding volume hierarchy with testing every
+    // triangle.
+    if input.key_pressed(keys.toggle_bvh) {
+        renderer.use_bvh = !renderer.use_bvh;
+        println!(
+            "{} the bounding volume hierarchy.",
+            if renderer.use_bvh {
+                "Using"
+            } else {
+                "Not using"
+            }
+        );
+    }
+
+    if input.key_pressed(keys.toggle_grayscale) {
+        match renderer
+            .filters
+            .iter()
+            .position(|&f| f == Filter::Grayscale)
+        {
+            Some(i) => {
+                renderer.filters.remove(i);
+            }
+            None => renderer.filters.push(Filter::Grayscale),
+        }
+    }
+
+    if input.key_pressed(keys.toggle_projection) {
+        camera.projection_model = match camera.projection_model {
+            ProjectionModel::Planar => {
+                println!("Switching to spherical projection.");
+                ProjectionModel::Spherical
+            }
+            ProjectionModel::Spherical => {
+                println!("Switching to planar projection.");
+                ProjectionModel::Planar
+            }
+        };
+    }
+
+    if input.key_pressed(keys.cycle_multithreading) {
         renderer.multithreading_method = match renderer.multithreading_method {
             MultithreadingMethod::None => {
                 println!("Switching to crossbeam multithreading.");
@@ -104,6 +523,12 @@ pub fn handle_input(
                 MultithreadingMethod::Rayon
             }
             MultithreadingMethod::Rayon => {
+                println!("Switching to tiled multithreading.");
+                MultithreadingMethod::Tiled {
+                    tile_size: DEFAULT_TILE_SIZE,
+                }
+            }
+            MultithreadingMethod::Tiled { .. } => {
                 println!("Disabled multithreading.");
                 MultithreadingMethod::None
             }
@@ -1,39 +1,64 @@
 #![forbid(unsafe_code)]
 
+mod bvh;
 mod camera;
+mod cli;
+mod color;
+mod config;
 mod errors;
 mod input;
 mod objects;
+mod overlay;
 mod renderer;
+mod sampling;
+mod texture;
 mod util;
 mod vector;
 mod world;
 
-use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use pixels::{Error, Pixels, SurfaceTexture};
+use rgb::RGBA8;
 use winit::dpi::LogicalSize;
 use winit::event::Event;
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
-use camera::{Camera, ImagePlane};
-use input::handle_input;
-use objects::*;
-use renderer::{MultithreadingMethod, Renderer};
-use rgb::*;
-use util::{load_object_from_file_json, print_frame_time, Resolution};
-use vector::*;
-use world::World;
+use camera::Camera;
+use config::Config;
+use input::{handle_input, AppState, KeyBindings};
+use overlay::Overlay;
+use renderer::{Filter, Renderer, AMBIENT_OCCLUSION_DISTANCE};
+use sampling::SamplingConfig;
+use util::{print_frame_time, FrameStats};
+use world::Scene;
 
 #[macro_use]
 extern crate quick_error;
 
+/// The color of the edges drawn over objects with their wireframe shown.
+const WIREFRAME_COLOR: RGBA8 = RGBA8::new(0, 255, 0, 255);
+/// The color of the rectangle drawn around the selected object.
+const SELECTION_COLOR: RGBA8 = RGBA8::new(255, 255, 0, 255);
+/// The colors of the label in the top left corner that names the render mode
+/// and renderer preset.
+const LABEL_COLOR: RGBA8 = RGBA8::new(255, 255, 255, 255);
+const LABEL_BACKGROUND_COLOR: RGBA8 = RGBA8::new(0, 0, 0, 255);
+
 fn main() -> Result<(), Error> {
-    let resolution_w: u32 = 800;
-    let resolution_h: u32 = 600;
+    // Render a single frame without opening a window when the `render`
+    // subcommand is used.
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("render") {
+        std::process::exit(cli::run_render_command(&args[2..]));
+    }
+
+    let config = Config::load().expect("Failed to load the config file.");
+    let resolution_w: u32 = config.width;
+    let resolution_h: u32 = config.height;
 
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
@@ -54,70 +79,53 @@ fn main() -> Result<(), Error> {
         Pixels::new(resolution_w, resolution_h, surface_texture)?
     };
 
-    let mut world = World::new();
-    world.color = RGBA8::new(196, 196, 196, 255);
-
-    let floor: VertexObject = load_object_from_file_json("res/objects/floor.json").unwrap();
-    world.vertex_objects.push(floor);
-
-    let pyramid: VertexObject = load_object_from_file_json("res/objects/pyramid.json").unwrap();
-    world.vertex_objects.push(pyramid);
-
-    // Add three balls to the world:
-    let ball1: Ball = load_object_from_file_json("res/objects/ball1.json").unwrap();
-    let ball2: Ball = load_object_from_file_json("res/objects/ball2.json").unwrap();
-    let ball3: Ball = load_object_from_file_json("res/objects/ball3.json").unwrap();
-    world.balls.push(ball1);
-    world.balls.push(ball2);
-    world.balls.push(ball3);
-
-    let triangle: VertexObject = load_object_from_file_json("res/objects/triangle.json").unwrap();
-    world.vertex_objects.push(triangle);
-
-    // Add two lights to the world:
-    let light1_color = LightIntensity::new(120.0, 120.0, 120.0);
-    let light1_pos = vec3(0.0, 3.0, 0.0);
-    // Add two lights to the scene:
-    let light1 = Light {
-        pos: light1_pos,
-        diffuse_intensity: light1_color,
-        specular_intensity: light1_color,
-    };
-    world.lights.push(light1);
-
-    // let light1_ball = Ball { pos: light1_pos, rad: 0.25, is_light: true,
-    //     material: Material { ambient_constant: light1_color, diffuse_constant: 300.0, specular_constant: 1.0, shine: 5.0 } };
-    // world.items.push(light1_ball);
-
-    // let light2_color = LightIntensity::new(1000.0, 1000.0, 1000.0);
-    // let light2 = Light {
-    //     pos: vec3(-2.0, 10.0, 5.0),
-    //     diffuse_intensity: light2_color,
-    //     specular_intensity: light2_color,
-    // };
-    // world.lights.push(light2);
-
-    let mut camera = Camera::new(
-        vec3(0.0, 2.5, 5.0),
-        vec3(0.0, 0.0, -1.0),
-        90.0,
-        Resolution {
-            w: resolution_w,
-            h: resolution_h,
+    // An optional scene file can be passed as the first argument, otherwise
+    // the scene from the config file or the default scene is used.
+    let scene_path = args.get(1).map(PathBuf::from).or(config.scene.clone());
+    let scene = Scene::load(scene_path.as_deref()).expect("Failed to load the scene.");
+    println!("{}", scene.world.summary());
+    for warning in scene.validate() {
+        println!("Scene warning: {}", warning);
+    }
+    let mut world = scene.world;
+    let mut camera = Camera::from_settings(&scene.camera, config.resolution())
+        .expect("Failed to create camera, likely because of invalid parameters.");
+
+    let mut renderer = Renderer {
+        // The grayscale setting predates the list of filters.
+        filters: if config.grayscale {
+            vec![Filter::Grayscale]
+        } else {
+            Vec::new()
         },
-    )
-    .expect("Failed to create camera, likely because of invalid parameters.");
-    camera.look_at(ball1.pos);
-
-    let mut renderer: Renderer = Renderer {
-        grayscale: false,
-        multithreading_method: MultithreadingMethod::Rayon,
+        multithreading_method: config.multithreading_method,
+        thread_count: config.thread_count,
+        time_budget_ms: config.time_budget_ms,
+        sampling: SamplingConfig {
+            ao: config.ao_samples,
+            ..SamplingConfig::default()
+        },
+        ..Renderer::default()
     };
 
+    if renderer.sampling.ao > 0 {
+        world.cache_faces();
+        renderer.bake_ambient_occlusion(&mut world, AMBIENT_OCCLUSION_DISTANCE);
+    }
+
     let app_start = Instant::now();
     let mut frame_time_ms = 0.0;
-    let mut multithreading = false;
-    let mut click_count: u8 = 0;
+    // The first row that still has to be rendered, when a frame didn't fit in
+    // the time budget.
+    let mut next_row = 0;
+    let mut app = AppState {
+        key_bindings: KeyBindings::default(),
+        click_count: 0,
+        selected: None,
+        presets: load_presets("res/presets"),
+        scene_path,
+    };
+    let mut frame_stats = FrameStats::default();
 
     event_loop.run(move |event, _, control_flow| {
         let frame_start = Instant::now();
@@ -131,8 +139,7 @@ fn main() -> Result<(), Error> {
                 &mut camera,
                 &mut renderer,
                 &mut pixels,
-                &mut multithreading,
-                &mut click_count,
+                &mut app,
             );
         }
 
@@ -140,14 +147,29 @@ fn main() -> Result<(), Error> {
         // Update internal stateand request a redraw
         world.update(frame_time_ms, time);
         // if let Some(pyramid) = world.vertex_objects.get_mut(1) {
-        //     let look_at = pyramid.pos() + *pyramid.vertices.get(0).unwrap();
+        //     let look_at = pyramid.pos + *pyramid.vertices.get(0).unwrap();
         //     // println!("Looking at: {look_at:?}");
         //     camera.look_at(look_at);
         // }
 
         // Draw the current frame
         if let Event::RedrawRequested(_) = event {
-            renderer.render_world(&world, &camera, pixels.get_frame());
+            renderer.render_world_within_budget(&world, &camera, pixels.get_frame(), &mut next_row);
+            let mut overlay = Overlay::new(pixels.get_frame(), renderer.frame_layout(&camera));
+            for &handle in &world.wireframes {
+                if let Some(faces) = world.object_faces(handle) {
+                    overlay.draw_wireframe(&camera, &faces, WIREFRAME_COLOR);
+                }
+            }
+            if let Some(faces) = app.selected.and_then(|handle| world.object_faces(handle)) {
+                overlay.draw_bounding_rect(&camera, &faces, SELECTION_COLOR);
+            }
+            let mode = format!("{:?}", renderer.render_mode);
+            let label = match app.presets.iter().position(|preset| *preset == renderer) {
+                Some(i) => format!("Preset {} {}", i + 1, mode),
+                None => mode,
+            };
+            overlay.draw_label(1, 1, &label, LABEL_COLOR, LABEL_BACKGROUND_COLOR);
             // world.draw(&camera, pixels.get_frame());
             if pixels
                 .render()
@@ -157,6 +179,19 @@ fn main() -> Result<(), Error> {
                 *control_flow = ControlFlow::Exit;
                 return;
             }
+            frame_stats.record(frame_start.elapsed().as_micros() as f32 / 1000.0);
+        }
+
+        // Report on the performance of the session when the window closes.
+        if let Event::LoopDestroyed = event {
+            println!(
+                "\nRendered at {}x{} with {:?} multithreading.\n{}",
+                camera.resolution.w,
+                camera.resolution.h,
+                renderer.multithreading_method,
+                frame_stats.report()
+            );
+            return;
         }
 
         frame_time_ms = frame_start.elapsed().as_micros() as f32 / 1000.0;
@@ -164,3 +199,23 @@ fn main() -> Result<(), Error> {
         window.request_redraw();
     });
 }
+
+/// Load all renderer presets in a directory, sorted by file name. Presets that
+/// fail to load are skipped.
+fn load_presets<P: AsRef<Path>>(dir: P) -> Vec<Renderer> {
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| Some(e.ok()?.path())).collect(),
+        Err(_) => return Vec::new(),
+    };
+    paths.sort();
+    paths
+        .iter()
+        .filter_map(|path| match Renderer::load_preset(path) {
+            Ok(preset) => Some(preset),
+            Err(e) => {
+                println!("Failed to load preset {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect()
+}
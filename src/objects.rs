@@ -3,19 +3,236 @@ use crate::vector::Vec3;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read};
 use std::ops::{Deref, DerefMut};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
 use rgb::*;
 
 /// Material that can be put on an object.
-#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone, Copy)]
 pub struct Material {
     pub ambient_constant: RGBA8,
     pub diffuse_constant: f32,
     pub specular_constant: f32,
+    /// The color of the diffuse reflection, which tints the light per
+    /// channel. Defaults to the material's color at the hit point.
+    #[serde(default)]
+    pub diffuse_color: Option<RGBA8>,
+    /// The color of the specular highlights. Defaults to white, so highlights
+    /// take the color of the light.
+    #[serde(default)]
+    pub specular_color: Option<RGBA8>,
     pub shine: f32,
+    #[serde(default)]
+    pub pattern: MaterialPattern,
+    /// Baked ambient occlusion factor that the ambient term is multiplied by,
+    /// from 0.0 (fully occluded) to 1.0 (unoccluded). No occlusion if `None`.
+    #[serde(default)]
+    pub ao_constant: Option<f32>,
+    /// How much of the surroundings the material mirrors, from 0.0 (not at
+    /// all) to 1.0 (a perfect mirror).
+    #[serde(default)]
+    pub reflectivity: f32,
+    /// How much light passes through the material, from 0.0 (opaque) to 1.0
+    /// (fully transparent, like clear glass).
+    #[serde(default)]
+    pub transparency: f32,
+    /// How strongly light bends when it enters the material, e.g. 1.0 for air
+    /// and about 1.5 for glass.
+    #[serde(default = "default_refractive_index")]
+    pub refractive_index: f32,
+    /// The index in the world's `textures` of an image that gives the
+    /// diffuse color of faces with UV coordinates.
+    #[serde(default)]
+    pub texture: Option<usize>,
+}
+
+fn default_refractive_index() -> f32 {
+    1.0
+}
+
+/// A procedural pattern that determines the color of a material at each point
+/// in the world.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum MaterialPattern {
+    /// A single color: the material's `ambient_constant`.
+    #[default]
+    Solid,
+    /// Grid lines along the world x and z axes, drawn over the material's
+    /// `ambient_constant`, like the ground grid of a 3D editor. Every
+    /// `major_every`-th line is drawn as a major line.
+    Grid {
+        spacing: f32,
+        line_width: f32,
+        major_every: u32,
+        minor_color: RGBA8,
+        major_color: RGBA8,
+    },
+    /// Square tiles of `scale` by `scale` along the world x and z axes,
+    /// alternating between two colors, like a checkerboard floor.
+    Checkerboard {
+        color_a: RGBA8,
+        color_b: RGBA8,
+        scale: f32,
+    },
+}
+
+/// The default material is a visible, matte mid-gray. (An all-zero material
+/// would render pure black, which is rarely what's intended.)
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            ambient_constant: RGBA8::new(128, 128, 128, 255),
+            diffuse_constant: 35.0,
+            specular_constant: 10.0,
+            diffuse_color: None,
+            specular_color: None,
+            shine: 7.0,
+            pattern: MaterialPattern::Solid,
+            ao_constant: None,
+            reflectivity: 0.0,
+            transparency: 0.0,
+            refractive_index: default_refractive_index(),
+            texture: None,
+        }
+    }
+}
+
+impl Material {
+    /// A clear dielectric, like glass or water, with refractive index `ior`
+    /// (about 1.5 for glass, 1.33 for water). It reflects the share of light
+    /// given by the Fresnel equations for light hitting it head-on, and lets
+    /// the rest pass through, so nothing is lost or added.
+    pub fn glass(ior: f32) -> Material {
+        let reflectivity = ((ior - 1.0) / (ior + 1.0)).powi(2);
+        Material {
+            ambient_constant: RGBA8::new(255, 255, 255, 255),
+            diffuse_constant: 0.0,
+            specular_constant: 40.0,
+            shine: 60.0,
+            reflectivity,
+            transparency: 1.0 - reflectivity,
+            refractive_index: ior,
+            ..Material::default()
+        }
+    }
+
+    /// A metal that reflects its surroundings tinted with `color`, like gold
+    /// or copper. `roughness` goes from 0.0 (polished, a tinted mirror) to 1.0
+    /// (brushed, with a wide highlight and barely any reflection). Metals
+    /// don't let any light through.
+    pub fn metal(color: RGBA8, roughness: f32) -> Material {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Material {
+            ambient_constant: color,
+            diffuse_constant: 10.0,
+            specular_constant: 40.0,
+            specular_color: Some(color),
+            shine: 2.0 + 98.0 * (1.0 - roughness),
+            reflectivity: 0.9 * (1.0 - roughness),
+            transparency: 0.0,
+            ..Material::default()
+        }
+    }
+
+    /// Check whether the material renders pure black no matter how it is lit,
+    /// making the object effectively invisible.
+    pub fn is_invisible(&self) -> bool {
+        let black = self.ambient_constant.r == 0
+            && self.ambient_constant.g == 0
+            && self.ambient_constant.b == 0;
+        black
+            && self.pattern == MaterialPattern::Solid
+            && self.diffuse_constant == 0.0
+            && self.specular_constant == 0.0
+            && self.reflectivity == 0.0
+            && self.transparency == 0.0
+    }
+
+    /// Get the color of the material at a point in world space.
+    pub fn color_at(&self, point: Vec3) -> RGBA8 {
+        match self.pattern {
+            MaterialPattern::Solid => self.ambient_constant,
+            MaterialPattern::Grid {
+                spacing,
+                line_width,
+                major_every,
+                minor_color,
+                major_color,
+            } => {
+                // Check whether a coordinate lies within half a line width of
+                // a multiple of `spacing`.
+                let on_line = |c: f32, spacing: f32| {
+                    let distance = (c - (c / spacing).round() * spacing).abs();
+                    distance <= line_width / 2.0
+                };
+                let major_spacing = spacing * major_every.max(1) as f32;
+                if on_line(point.x, major_spacing) || on_line(point.z, major_spacing) {
+                    major_color
+                } else if on_line(point.x, spacing) || on_line(point.z, spacing) {
+                    minor_color
+                } else {
+                    self.ambient_constant
+                }
+            }
+            MaterialPattern::Checkerboard {
+                color_a,
+                color_b,
+                scale,
+            } => {
+                let tile = (point.x / scale).floor() + (point.z / scale).floor();
+                if tile.rem_euclid(2.0) == 0.0 {
+                    color_a
+                } else {
+                    color_b
+                }
+            }
+        }
+    }
+
+    /// Linearly blend two materials, where `t` = 0.0 gives `self` and `t` = 1.0
+    /// gives `other`.
+    pub fn blend(&self, other: &Material, t: f32) -> Material {
+        let t = t.clamp(0.0, 1.0);
+        let lerp = |a: f32, b: f32| a * (1.0 - t) + b * t;
+        let lerp_u8 = |a: u8, b: u8| lerp(a as f32, b as f32).round() as u8;
+        let lerp_rgba8 = |a: RGBA8, b: RGBA8| {
+            RGBA8::new(
+                lerp_u8(a.r, b.r),
+                lerp_u8(a.g, b.g),
+                lerp_u8(a.b, b.b),
+                lerp_u8(a.a, b.a),
+            )
+        };
+        let white = RGBA8::new(255, 255, 255, 255);
+        Material {
+            ambient_constant: lerp_rgba8(self.ambient_constant, other.ambient_constant),
+            diffuse_constant: lerp(self.diffuse_constant, other.diffuse_constant),
+            specular_constant: lerp(self.specular_constant, other.specular_constant),
+            diffuse_color: match (self.diffuse_color, other.diffuse_color) {
+                (None, None) => None,
+                (a, b) => Some(lerp_rgba8(
+                    a.unwrap_or(self.ambient_constant),
+                    b.unwrap_or(other.ambient_constant),
+                )),
+            },
+            specular_color: match (self.specular_color, other.specular_color) {
+                (None, None) => None,
+                (a, b) => Some(lerp_rgba8(a.unwrap_or(white), b.unwrap_or(white))),
+            },
+            shine: lerp(self.shine, other.shine),
+            pattern: if t < 0.5 { self.pattern } else { other.pattern },
+            ao_constant: match (self.ao_constant, other.ao_constant) {
+                (None, None) => None,
+                (a, b) => Some(lerp(a.unwrap_or(1.0), b.unwrap_or(1.0))),
+            },
+            reflectivity: lerp(self.reflectivity, other.reflectivity),
+            transparency: lerp(self.transparency, other.transparency),
+            refractive_index: lerp(self.refractive_index, other.refractive_index),
+            texture: if t < 0.5 { self.texture } else { other.texture },
+        }
+    }
 }
 
 /// A ball object.
@@ -26,7 +243,46 @@ pub struct Ball {
     pub material: Material,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// An infinite plane through `point`, e.g. a floor without edges.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Plane {
+    pub point: Vec3,
+    /// The direction the plane faces. It doesn't need to be normalized.
+    pub normal: Vec3,
+    pub material: Material,
+}
+
+/// An axis-aligned box between the corners `min` and `max`.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct BoxAABB {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub material: Material,
+}
+
+/// A shape that rays are tested against one by one, unlike triangles, which
+/// are found through the world's bounding volume hierarchy.
+#[derive(Clone, Copy)]
+pub enum Primitive<'a> {
+    Ball(&'a Ball),
+    Box(&'a BoxAABB),
+    Plane(&'a Plane),
+}
+
+/// One entry in a file holding several objects, tagged with the kind of
+/// object, e.g. `{ "type": "ball", "pos": ..., ... }`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ObjectEntry {
+    Ball(Ball),
+    VertexObject(Box<VertexObject>),
+    Plane(Plane),
+    BoxAabb(BoxAABB),
+    Light(Light),
+    SpotLight(SpotLight),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct LightIntensity {
     pub r: f32,
     pub g: f32,
@@ -39,15 +295,229 @@ impl LightIntensity {
     }
 }
 
-#[derive(Debug)]
+impl From<Vec3> for LightIntensity {
+    fn from(v: Vec3) -> Self {
+        LightIntensity {
+            r: v.x,
+            g: v.y,
+            b: v.z,
+        }
+    }
+}
+
+impl From<LightIntensity> for Vec3 {
+    fn from(intensity: LightIntensity) -> Self {
+        Vec3 {
+            x: intensity.r,
+            y: intensity.g,
+            z: intensity.b,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Light {
     pub pos: Vec3,
     pub diffuse_intensity: LightIntensity,
     pub specular_intensity: LightIntensity,
+    /// Whether objects block this light. Fill lights often shouldn't cast
+    /// shadows.
+    #[serde(default = "default_casts_shadows")]
+    pub casts_shadows: bool,
+}
+
+fn default_casts_shadows() -> bool {
+    true
+}
+
+impl Light {
+    /// Check whether the light has no intensity at all, meaning it doesn't
+    /// light up anything.
+    pub fn is_dark(&self) -> bool {
+        let is_zero = |i: LightIntensity| i.r <= 0.0 && i.g <= 0.0 && i.b <= 0.0;
+        is_zero(self.diffuse_intensity) && is_zero(self.specular_intensity)
+    }
+}
+
+/// A light that only shines within a cone around `direction`. Points within
+/// `inner_angle` (in degrees) of the cone's axis get the full intensity, which
+/// falls off smoothly to zero at `outer_angle`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SpotLight {
+    #[serde(flatten)]
+    pub light: Light,
+    pub direction: Vec3,
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl SpotLight {
+    /// Get the fraction (0 to 1) of the light's intensity that reaches
+    /// `point`.
+    pub fn cone_factor(&self, point: Vec3) -> f32 {
+        let cos_angle = (point - self.light.pos)
+            .normalized()
+            .dot(self.direction.normalized());
+        let cos_inner = self.inner_angle.to_radians().cos();
+        let cos_outer = self.outer_angle.to_radians().cos();
+        if cos_angle >= cos_inner {
+            return 1.0;
+        }
+        if cos_angle <= cos_outer {
+            return 0.0;
+        }
+        let t = (cos_angle - cos_outer) / (cos_inner - cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+/// Transformation from an object's local space into world space: a scale
+/// along each of the axes, followed by a translation. The scale must not be
+/// zero along any axis, and a negative scale mirrors the object.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: Vec3::default(),
+            scale: Vec3 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+        }
+    }
+}
+
+impl Transform {
+    /// Transform a point from local space into world space.
+    pub fn apply_to_point(&self, point: Vec3) -> Vec3 {
+        point.mul_componentwise(self.scale) + self.translation
+    }
+    /// Transform a point from world space into local space.
+    pub fn inverse_point(&self, point: Vec3) -> Vec3 {
+        self.inverse_direction(point - self.translation)
+    }
+    /// Transform a direction from world space into local space.
+    pub fn inverse_direction(&self, direction: Vec3) -> Vec3 {
+        Vec3 {
+            x: direction.x / self.scale.x,
+            y: direction.y / self.scale.y,
+            z: direction.z / self.scale.z,
+        }
+    }
+    /// Transform a surface normal from local space into world space. Normals
+    /// are transformed by the inverse (transposed) scale, so they stay
+    /// perpendicular to the surface.
+    pub fn apply_to_normal(&self, normal: Vec3) -> Vec3 {
+        self.inverse_direction(normal).normalized()
+    }
+    /// Get the determinant of the linear part of the transform. It is
+    /// negative when the transform mirrors the object.
+    pub fn determinant(&self) -> f32 {
+        self.scale.x * self.scale.y * self.scale.z
+    }
+    /// Transform a triangle face from local space into world space. Mirroring
+    /// flips the winding of the vertices, so for a negative determinant two of
+    /// them are swapped to keep the face normal pointing the same way as
+    /// `apply_to_normal` would.
+    pub fn apply_to_face(&self, face: TriangleFace) -> TriangleFace {
+        let (v0, v1, v2) = face;
+        let (v0, v1, v2) = (
+            self.apply_to_point(v0),
+            self.apply_to_point(v1),
+            self.apply_to_point(v2),
+        );
+        if self.determinant() < 0.0 {
+            (v0, v2, v1)
+        } else {
+            (v0, v1, v2)
+        }
+    }
+}
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl BoundingBox {
+    /// Create a bounding box containing a single point.
+    pub fn from_point(point: Vec3) -> BoundingBox {
+        BoundingBox {
+            min: point,
+            max: point,
+        }
+    }
+    /// Grow the bounding box so it contains a point.
+    pub fn grow(&mut self, point: Vec3) {
+        self.min = Vec3 {
+            x: self.min.x.min(point.x),
+            y: self.min.y.min(point.y),
+            z: self.min.z.min(point.z),
+        };
+        self.max = Vec3 {
+            x: self.max.x.max(point.x),
+            y: self.max.y.max(point.y),
+            z: self.max.z.max(point.z),
+        };
+    }
+    /// Grow the bounding box so it contains another bounding box.
+    pub fn merge(&mut self, other: &BoundingBox) {
+        self.grow(other.min);
+        self.grow(other.max);
+    }
+}
+
+/// A sphere that contains a whole object, to quickly rule out rays that
+/// can't hit it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Check whether a ray, going forward from its origin, passes through the
+    /// sphere.
+    pub fn is_hit_by(&self, origin: Vec3, direction: Vec3) -> bool {
+        let to_center = self.center - origin;
+        let radius_sq = self.radius * self.radius;
+        if to_center.dot(to_center) <= radius_sq {
+            return true;
+        }
+        let along = to_center.dot(direction);
+        if along < 0.0 {
+            return false;
+        }
+        // The squared distance between the center and the ray's line.
+        to_center.dot(to_center) - along * along / direction.dot(direction) <= radius_sq
+    }
+}
+
+/// A copy of a shared mesh (from `World::meshes`) placed in the world using
+/// its own transform, so repeated geometry only has to be stored once.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Instance {
+    /// Index of the mesh in `World::meshes`.
+    pub mesh_id: usize,
+    #[serde(default)]
+    pub transform: Transform,
+    /// Material used instead of the mesh's own material.
+    #[serde(default)]
+    pub material: Option<Material>,
 }
 
 pub type TriangleFaceIndices = (usize, usize, usize);
 pub type TriangleFace = (Vec3, Vec3, Vec3);
+/// Barycentric weights of a point on a triangle face, one for each vertex.
+pub type Barycentric = (f32, f32, f32);
 
 pub fn get_triangle_normal(triangle_face: TriangleFace) -> Vec3 {
     let (v0, v1, v2) = triangle_face;
@@ -57,20 +527,200 @@ pub fn get_triangle_normal(triangle_face: TriangleFace) -> Vec3 {
     v0v1.cross_product(v0v2)
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct VertexObject {
     pub pos: Vec3,
+    #[serde(default)]
     pub vertices: Vec<Vec3>,
+    #[serde(default)]
     pub faces: Vec<TriangleFaceIndices>,
     pub material: Material,
+    /// Optional second material that `material` is blended into using the
+    /// per-vertex weights in `vertex_material_weights`.
+    #[serde(default)]
+    pub secondary_material: Option<Material>,
+    /// Per-vertex blend weights, indexed like `vertices`. A weight of 0.0 uses
+    /// only `material`, a weight of 1.0 only `secondary_material`.
+    #[serde(default)]
+    pub vertex_material_weights: Vec<f32>,
+    /// Per-vertex baked ambient occlusion, indexed like `vertices`. When
+    /// present, it overrides the material's `ao_constant`.
+    #[serde(default)]
+    pub vertex_ambient_occlusion: Vec<f32>,
+    /// Per-vertex normals, indexed like `vertices`. When present, the normal
+    /// is interpolated across each face, so curved surfaces shade smoothly
+    /// instead of showing their faces.
+    #[serde(default)]
+    pub vertex_normals: Vec<Vec3>,
+    /// Per-vertex texture coordinates, indexed like `vertices`. (0, 0) is the
+    /// bottom left and (1, 1) the top right of the texture.
+    #[serde(default)]
+    pub vertex_uvs: Vec<(f32, f32)>,
+    /// Materials that individual faces use instead of `material`, e.g. the
+    /// materials of an .obj file's material library.
+    #[serde(default)]
+    pub materials: Vec<Material>,
+    /// Per-face index into `materials`, indexed like `faces`. Faces without
+    /// one use `material`.
+    #[serde(default)]
+    pub face_materials: Vec<Option<usize>>,
+    /// Path to a Wavefront .obj file to take the vertices and faces from,
+    /// instead of listing them. It is relative to the scene file's directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub obj: Option<PathBuf>,
 }
 
 impl VertexObject {
     /// Get an iterator over the object's faces. Vertices are given relative to
     /// the object's position.
-    pub fn iter_faces(&self) -> FacesIterator {
+    pub fn iter_faces(&self) -> FacesIterator<'_> {
         FacesIterator::from_vertex_object(self)
     }
+
+    /// Get the object's faces with its position added to every vertex, i.e.
+    /// in world space.
+    pub fn positioned_faces(&self) -> Vec<TriangleFace> {
+        self.iter_faces()
+            .map(|(v0, v1, v2)| (v0 + self.pos, v1 + self.pos, v2 + self.pos))
+            .collect()
+    }
+
+    /// Get the material at a point on a face, given by the barycentric
+    /// coordinates of that point. Starts from the face's own material, or else
+    /// `material`, which is blended with the secondary material if the object
+    /// has one and a (complete set of) vertex weights.
+    pub fn material_at(&self, face_index: usize, barycentric: Barycentric) -> Material {
+        let face_material = self
+            .face_materials
+            .get(face_index)
+            .copied()
+            .flatten()
+            .and_then(|index| self.materials.get(index));
+        let base = *face_material.unwrap_or(&self.material);
+        let mut material = match self.secondary_material {
+            Some(secondary) if self.vertex_material_weights.len() == self.vertices.len() => {
+                let weight =
+                    self.interpolate(&self.vertex_material_weights, face_index, barycentric);
+                base.blend(&secondary, weight)
+            }
+            _ => base,
+        };
+        if self.vertex_ambient_occlusion.len() == self.vertices.len() {
+            material.ao_constant =
+                Some(self.interpolate(&self.vertex_ambient_occlusion, face_index, barycentric));
+        }
+        material
+    }
+
+    /// Get the interpolated vertex normal at a point on a face, or `None` if
+    /// the object has no vertex normals.
+    pub fn normal_at(&self, face_index: usize, barycentric: Barycentric) -> Option<Vec3> {
+        if self.vertex_normals.len() != self.vertices.len() {
+            return None;
+        }
+        let (i0, i1, i2) = self.faces[face_index];
+        let (b0, b1, b2) = barycentric;
+        let normals = &self.vertex_normals;
+        Some(normals[i0] * b0 + normals[i1] * b1 + normals[i2] * b2)
+    }
+
+    /// Get the interpolated texture coordinates at a point on a face, or
+    /// `None` if the object has no texture coordinates.
+    pub fn uv_at(&self, face_index: usize, barycentric: Barycentric) -> Option<(f32, f32)> {
+        if self.vertex_uvs.len() != self.vertices.len() {
+            return None;
+        }
+        let (i0, i1, i2) = self.faces[face_index];
+        let (b0, b1, b2) = barycentric;
+        let uvs = &self.vertex_uvs;
+        Some((
+            uvs[i0].0 * b0 + uvs[i1].0 * b1 + uvs[i2].0 * b2,
+            uvs[i0].1 * b0 + uvs[i1].1 * b1 + uvs[i2].1 * b2,
+        ))
+    }
+
+    /// Interpolate a per-vertex value at a point on a face.
+    fn interpolate(&self, values: &[f32], face_index: usize, barycentric: Barycentric) -> f32 {
+        let (i0, i1, i2) = self.faces[face_index];
+        let (b0, b1, b2) = barycentric;
+        values[i0] * b0 + values[i1] * b1 + values[i2] * b2
+    }
+
+    /// Get the bounding box of the object in world space, or `None` if it has
+    /// no vertices.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let mut vertices = self.vertices.iter().map(|v| *v + self.pos);
+        let mut bounding_box = BoundingBox::from_point(vertices.next()?);
+        for vertex in vertices {
+            bounding_box.grow(vertex);
+        }
+        Some(bounding_box)
+    }
+
+    /// Get a sphere in world space around all vertices of the object, or
+    /// `None` if it has no vertices. It is centered on the bounding box, so it
+    /// isn't the smallest possible sphere, but it is cheap to compute.
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        let bounding_box = self.bounding_box()?;
+        let center = (bounding_box.min + bounding_box.max) * 0.5;
+        let radius = self
+            .vertices
+            .iter()
+            .map(|v| (*v + self.pos - center).len())
+            .fold(0.0, f32::max);
+        Some(BoundingSphere { center, radius })
+    }
+
+    /// Get the indices of faces that refer to vertices that don't exist.
+    pub fn invalid_faces(&self) -> Vec<usize> {
+        let vertex_count = self.vertices.len();
+        self.faces
+            .iter()
+            .enumerate()
+            .filter(|(_, (i0, i1, i2))| {
+                *i0 >= vertex_count || *i1 >= vertex_count || *i2 >= vertex_count
+            })
+            .map(|(face_index, _)| face_index)
+            .collect()
+    }
+
+    /// Get the indices of (valid) faces that have no area, because their
+    /// vertices coincide or lie on a single line.
+    pub fn degenerate_faces(&self) -> Vec<usize> {
+        self.faces
+            .iter()
+            .enumerate()
+            .filter(|(_, (i0, i1, i2))| {
+                match (
+                    self.vertices.get(*i0),
+                    self.vertices.get(*i1),
+                    self.vertices.get(*i2),
+                ) {
+                    (Some(v0), Some(v1), Some(v2)) => {
+                        get_triangle_normal((*v0, *v1, *v2)).len() == 0.0
+                    }
+                    _ => false,
+                }
+            })
+            .map(|(face_index, _)| face_index)
+            .collect()
+    }
+
+    /// Compute a normal for every vertex by averaging the normals of the faces
+    /// that share it.
+    pub fn compute_vertex_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::default(); self.vertices.len()];
+        for (face, (i0, i1, i2)) in self.iter_faces().zip(&self.faces) {
+            let normal = get_triangle_normal(face).normalized();
+            for i in [i0, i1, i2] {
+                normals[*i] += normal;
+            }
+        }
+        normals
+            .into_iter()
+            .map(|n| if n.len() > 0.0 { n.normalized() } else { n })
+            .collect()
+    }
 }
 
 /// An iterator type used to iterate over the faces of a VertexObject.
@@ -107,32 +757,225 @@ impl<'a> Iterator for FacesIterator<'a> {
     }
 }
 
-pub trait Object {
-    fn pos(&self) -> Vec3;
-    fn set_pos(&mut self, pos: Vec3);
-    fn material(&self) -> Material;
+impl Ball {
+    /// Get the bounding box of the ball.
+    pub fn bounding_box(&self) -> BoundingBox {
+        let offset = Vec3 {
+            x: self.rad,
+            y: self.rad,
+            z: self.rad,
+        };
+        BoundingBox {
+            min: self.pos - offset,
+            max: self.pos + offset,
+        }
+    }
 }
 
-impl Object for VertexObject {
-    fn pos(&self) -> Vec3 {
-        self.pos
-    }
-    fn set_pos(&mut self, pos: Vec3) {
-        self.pos = pos;
-    }
-    fn material(&self) -> Material {
-        self.material
-    }
+#[test]
+fn test_material_presets() {
+    let glass = Material::glass(1.5);
+    assert_eq!(glass.refractive_index, 1.5);
+    // 4% of the light is reflected head-on, the rest passes through.
+    assert!((glass.reflectivity - 0.04).abs() < 1e-6);
+    assert!((glass.reflectivity + glass.transparency - 1.0).abs() < 1e-6);
+    // Without bending the light, nothing is reflected.
+    assert_eq!(Material::glass(1.0).reflectivity, 0.0);
+
+    let gold = RGBA8::new(255, 200, 60, 255);
+    let polished = Material::metal(gold, 0.0);
+    let brushed = Material::metal(gold, 1.0);
+    assert_eq!(polished.specular_color, Some(gold));
+    assert_eq!(polished.transparency, 0.0);
+    assert!(polished.reflectivity > brushed.reflectivity);
+    assert!(polished.shine > brushed.shine);
+    assert_eq!(brushed.reflectivity, 0.0);
+    // Roughness outside of 0..1 is clamped.
+    assert_eq!(Material::metal(gold, 2.0).reflectivity, 0.0);
+    assert!(!brushed.is_invisible() && !glass.is_invisible());
 }
 
-impl Object for Ball {
-    fn pos(&self) -> Vec3 {
-        self.pos
-    }
-    fn set_pos(&mut self, pos: Vec3) {
-        self.pos = pos;
-    }
-    fn material(&self) -> Material {
-        self.material
+#[test]
+fn test_vertex_material_blend() {
+    use crate::vector::vec3;
+
+    let red = Material {
+        ambient_constant: RGBA8::new(255, 0, 0, 255),
+        ..Default::default()
+    };
+    let blue = Material {
+        ambient_constant: RGBA8::new(0, 0, 255, 255),
+        ..Default::default()
+    };
+    // A quad that fades from red on the left to blue on the right.
+    let mut quad = VertexObject {
+        vertices: vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 1.0),
+            vec3(0.0, 0.0, 1.0),
+        ],
+        faces: vec![(0, 1, 2), (0, 2, 3)],
+        material: red,
+        vertex_material_weights: vec![0.0, 1.0, 1.0, 0.0],
+        ..Default::default()
+    };
+    // Without a secondary material, the weights are ignored.
+    let m = quad.material_at(0, (0.0, 1.0, 0.0));
+    assert_eq!(m.ambient_constant, red.ambient_constant);
+
+    quad.secondary_material = Some(blue);
+    assert_eq!(
+        quad.material_at(0, (1.0, 0.0, 0.0)).ambient_constant,
+        red.ambient_constant
+    );
+    assert_eq!(
+        quad.material_at(0, (0.0, 1.0, 0.0)).ambient_constant,
+        blue.ambient_constant
+    );
+    // Halfway across the quad the two colors are mixed evenly.
+    let m = quad.material_at(1, (0.5, 0.5, 0.0));
+    assert_eq!(m.ambient_constant, RGBA8::new(128, 0, 128, 255));
+}
+
+#[test]
+fn test_grid_pattern() {
+    use crate::vector::vec3;
+
+    let background = RGBA8::new(255, 255, 255, 255);
+    let minor_color = RGBA8::new(128, 128, 128, 255);
+    let major_color = RGBA8::new(0, 0, 0, 255);
+    let material = Material {
+        ambient_constant: background,
+        pattern: MaterialPattern::Grid {
+            spacing: 1.0,
+            line_width: 0.1,
+            major_every: 5,
+            minor_color,
+            major_color,
+        },
+        ..Default::default()
+    };
+    // Only the x and z coordinates matter.
+    assert_eq!(material.color_at(vec3(0.5, 0.0, 0.5)), background);
+    assert_eq!(material.color_at(vec3(0.5, 3.0, 0.5)), background);
+    assert_eq!(material.color_at(vec3(2.02, 0.0, 0.5)), minor_color);
+    assert_eq!(material.color_at(vec3(0.5, 0.0, -3.98)), minor_color);
+    assert_eq!(material.color_at(vec3(-5.03, 0.0, 0.5)), major_color);
+    assert_eq!(material.color_at(vec3(0.5, 0.0, 10.0)), major_color);
+}
+
+#[test]
+fn test_checkerboard_pattern() {
+    use crate::vector::vec3;
+
+    let color_a = RGBA8::new(255, 255, 255, 255);
+    let color_b = RGBA8::new(0, 0, 0, 255);
+    let material = Material {
+        pattern: MaterialPattern::Checkerboard {
+            color_a,
+            color_b,
+            scale: 2.0,
+        },
+        ..Default::default()
+    };
+    assert_eq!(material.color_at(vec3(0.5, 0.0, 0.5)), color_a);
+    assert_eq!(material.color_at(vec3(1.9, 5.0, 1.9)), color_a);
+    assert_eq!(material.color_at(vec3(2.5, 0.0, 0.5)), color_b);
+    assert_eq!(material.color_at(vec3(0.5, 0.0, 2.5)), color_b);
+    assert_eq!(material.color_at(vec3(2.5, 0.0, 2.5)), color_a);
+    // The tiles continue past the origin.
+    assert_eq!(material.color_at(vec3(-0.5, 0.0, 0.5)), color_b);
+    assert_eq!(material.color_at(vec3(-0.5, 0.0, -0.5)), color_a);
+}
+
+#[test]
+fn test_mirrored_face_normal() {
+    use crate::vector::vec3;
+
+    // A face in the x = 1 plane, facing away from the origin.
+    let face = (
+        vec3(1.0, 0.0, 0.0),
+        vec3(1.0, 1.0, 0.0),
+        vec3(1.0, 0.0, 1.0),
+    );
+    let normal = get_triangle_normal(face).normalized();
+    assert_eq!(normal, vec3(1.0, 0.0, 0.0));
+
+    // Mirrored in the x = 0 plane, the face should still face away from the
+    // origin, which is now along the negative x-axis.
+    let mirror = Transform {
+        translation: Vec3::default(),
+        scale: vec3(-1.0, 1.0, 1.0),
+    };
+    assert!(mirror.determinant() < 0.0);
+    let expected = vec3(-1.0, 0.0, 0.0);
+    assert_eq!(mirror.apply_to_normal(normal), expected);
+    let mirrored_face = mirror.apply_to_face(face);
+    assert_eq!(get_triangle_normal(mirrored_face).normalized(), expected);
+
+    // Without mirroring the winding is kept.
+    let scale = Transform {
+        translation: vec3(0.0, 2.0, 0.0),
+        scale: vec3(2.0, 1.0, 1.0),
+    };
+    assert_eq!(
+        get_triangle_normal(scale.apply_to_face(face)).normalized(),
+        normal
+    );
+}
+
+#[test]
+fn test_spot_light_cone() {
+    use crate::vector::vec3;
+    let intensity = LightIntensity::new(100.0, 100.0, 100.0);
+    let spot_light = SpotLight {
+        light: Light {
+            pos: vec3(0.0, 0.0, 0.0),
+            diffuse_intensity: intensity,
+            specular_intensity: intensity,
+            casts_shadows: true,
+        },
+        direction: vec3(0.0, -2.0, 0.0),
+        inner_angle: 20.0,
+        outer_angle: 30.0,
+    };
+    let at_angle = |degrees: f32| {
+        let radians = degrees.to_radians();
+        vec3(radians.sin(), -radians.cos(), 0.0) * 5.0
+    };
+    assert_eq!(spot_light.cone_factor(at_angle(0.0)), 1.0);
+    assert_eq!(spot_light.cone_factor(at_angle(19.9)), 1.0);
+    let factor = spot_light.cone_factor(at_angle(25.0));
+    assert!(factor > 0.0 && factor < 1.0);
+    assert_eq!(spot_light.cone_factor(at_angle(30.1)), 0.0);
+    assert_eq!(spot_light.cone_factor(at_angle(90.0)), 0.0);
+}
+
+#[test]
+fn test_bounding_sphere() {
+    use crate::vector::vec3;
+
+    let object = VertexObject {
+        pos: vec3(10.0, 0.0, 0.0),
+        vertices: vec![
+            vec3(-1.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 2.0, 0.0),
+            vec3(0.0, 0.0, 1.0),
+        ],
+        faces: vec![(0, 1, 2), (0, 1, 3)],
+        ..Default::default()
+    };
+    let sphere = object.bounding_sphere().unwrap();
+    for vertex in &object.vertices {
+        assert!((*vertex + object.pos - sphere.center).len() <= sphere.radius + 1e-6);
     }
+    let origin = vec3(10.0, 1.0, 10.0);
+    assert!(sphere.is_hit_by(origin, vec3(0.0, 0.0, -1.0)));
+    // The sphere is behind the ray.
+    assert!(!sphere.is_hit_by(origin, vec3(0.0, 0.0, 1.0)));
+    assert!(!sphere.is_hit_by(origin, vec3(1.0, 0.0, -1.0)));
+    // A ray starting inside of the sphere always hits it.
+    assert!(sphere.is_hit_by(sphere.center, vec3(0.0, 0.0, 1.0)));
 }
@@ -0,0 +1,412 @@
+use crate::camera::Camera;
+use crate::objects::TriangleFace;
+use crate::renderer::FrameLayout;
+use crate::vector::Vec3;
+
+use rgb::*;
+
+/// The width and height in pixels of a character drawn by `draw_text`.
+pub const GLYPH_WIDTH: i32 = 3;
+pub const GLYPH_HEIGHT: i32 = 5;
+
+/// Lines in 3D are cut off this far in front of the camera, so nothing
+/// behind the camera gets projected onto the screen.
+const NEAR_CLIP_DISTANCE: f32 = 0.01;
+
+/// The space in pixels between an object and the rectangle drawn around it by
+/// `draw_bounding_rect`.
+const BOUNDING_RECT_MARGIN: i32 = 2;
+
+/// A 2D drawing layer on top of a rendered frame, for things like a HUD, a
+/// crosshair or highlighting the selected object. Coordinates are in pixels
+/// from the top left corner and may lie outside of the frame; anything
+/// outside of it is clipped.
+pub struct Overlay<'a> {
+    frame: &'a mut [u8],
+    layout: FrameLayout,
+}
+
+impl<'a> Overlay<'a> {
+    pub fn new(frame: &'a mut [u8], layout: FrameLayout) -> Overlay<'a> {
+        assert_eq!(frame.len(), layout.buffer_len());
+        Overlay { frame, layout }
+    }
+
+    /// Set a single pixel, if it lies inside the frame.
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: RGBA8) {
+        if x < 0 || y < 0 || x >= self.layout.width as i32 || y >= self.layout.height as i32 {
+            return;
+        }
+        let offset = self.layout.pixel_offset(x as u32, y as u32);
+        self.frame[offset..offset + 4].copy_from_slice(color.as_slice());
+    }
+
+    /// Draw a line from (x0, y0) to (x1, y1), including both end points.
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: RGBA8) {
+        // Bresenham's line algorithm, for lines in all directions.
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let mut error = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+        loop {
+            self.set_pixel(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            if 2 * error >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if 2 * error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Draw the outline of a rectangle with its top left corner at (x, y).
+    pub fn draw_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: RGBA8) {
+        if width <= 0 || height <= 0 {
+            return;
+        }
+        let (right, bottom) = (x + width - 1, y + height - 1);
+        self.draw_line(x, y, right, y, color);
+        self.draw_line(x, bottom, right, bottom, color);
+        self.draw_line(x, y, x, bottom, color);
+        self.draw_line(right, y, right, bottom, color);
+    }
+
+    /// Fill a rectangle with its top left corner at (x, y).
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: RGBA8) {
+        // Clip first, so huge rectangles don't loop over pixels that are
+        // never drawn.
+        let x_start = x.max(0);
+        let y_start = y.max(0);
+        let x_end = (x + width).min(self.layout.width as i32);
+        let y_end = (y + height).min(self.layout.height as i32);
+        for py in y_start..y_end {
+            for px in x_start..x_end {
+                self.set_pixel(px, py, color);
+            }
+        }
+    }
+
+    /// Draw a line between two points in world space, as seen by a camera.
+    /// The part of the line behind the camera is left out.
+    pub fn draw_line_3d(&mut self, camera: &Camera, a: Vec3, b: Vec3, color: RGBA8) {
+        // Cut the line off at the near plane.
+        let forward = camera.get_view_direction();
+        let depth_a = (a - camera.pos) * forward - NEAR_CLIP_DISTANCE;
+        let depth_b = (b - camera.pos) * forward - NEAR_CLIP_DISTANCE;
+        let (a, b) = match (depth_a >= 0.0, depth_b >= 0.0) {
+            (true, true) => (a, b),
+            (false, false) => return,
+            (true, false) => (a, a + (b - a) * (depth_a / (depth_a - depth_b))),
+            (false, true) => (b + (a - b) * (depth_b / (depth_b - depth_a)), b),
+        };
+        let (Some(a), Some(b)) = (camera.world_to_screen(a), camera.world_to_screen(b)) else {
+            return;
+        };
+        // Points close to the near plane can end up very far outside of the
+        // frame, so clip the line to the frame before drawing it pixel by
+        // pixel.
+        let size = (self.layout.width as f32, self.layout.height as f32);
+        if let Some(((x0, y0), (x1, y1))) = clip_line_to_rect(a, b, size) {
+            let to_pixel = |c: f32| c.floor() as i32;
+            self.draw_line(
+                to_pixel(x0),
+                to_pixel(y0),
+                to_pixel(x1),
+                to_pixel(y1),
+                color,
+            );
+        }
+    }
+
+    /// Draw the edges of triangle faces given in world space, as seen by a
+    /// camera.
+    pub fn draw_wireframe(&mut self, camera: &Camera, faces: &[TriangleFace], color: RGBA8) {
+        for &(v0, v1, v2) in faces {
+            self.draw_line_3d(camera, v0, v1, color);
+            self.draw_line_3d(camera, v1, v2, color);
+            self.draw_line_3d(camera, v2, v0, color);
+        }
+    }
+
+    /// Draw a line of text with its top left corner at (x, y), using a small
+    /// built-in bitmap font. Letters are drawn in upper case, and characters
+    /// the font doesn't have are left blank.
+    pub fn draw_text(&mut self, x: i32, y: i32, text: &str, color: RGBA8) {
+        let mut glyph_x = x;
+        for c in text.chars() {
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> column) != 0 {
+                        self.set_pixel(glyph_x + column, y + row as i32, color);
+                    }
+                }
+            }
+            glyph_x += GLYPH_WIDTH + 1;
+        }
+    }
+
+    /// Draw a line of text on a filled background that leaves one pixel free
+    /// around the text, so it can be read on top of any part of the frame.
+    pub fn draw_label(&mut self, x: i32, y: i32, text: &str, color: RGBA8, background: RGBA8) {
+        let width = text.chars().count() as i32 * (GLYPH_WIDTH + 1) + 1;
+        self.fill_rect(x, y, width, GLYPH_HEIGHT + 2, background);
+        self.draw_text(x + 1, y + 1, text, color);
+    }
+
+    /// Draw a rectangle around triangle faces given in world space, as seen by
+    /// a camera. Vertices behind the camera are left out.
+    pub fn draw_bounding_rect(&mut self, camera: &Camera, faces: &[TriangleFace], color: RGBA8) {
+        let (mut left, mut top) = (f32::INFINITY, f32::INFINITY);
+        let (mut right, mut bottom) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        let vertices = faces.iter().flat_map(|&(v0, v1, v2)| [v0, v1, v2]);
+        for (x, y) in vertices.filter_map(|v| camera.world_to_screen(v)) {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+        }
+        if left > right {
+            return;
+        }
+        // Keep the edges just outside of the frame, so edges that aren't
+        // visible aren't drawn pixel by pixel.
+        let (width, height) = (self.layout.width as i32, self.layout.height as i32);
+        let to_pixel = |c: f32, margin: i32, size: i32| (c.floor() as i32 + margin).clamp(-1, size);
+        let (x0, y0) = (
+            to_pixel(left, -BOUNDING_RECT_MARGIN, width),
+            to_pixel(top, -BOUNDING_RECT_MARGIN, height),
+        );
+        let (x1, y1) = (
+            to_pixel(right, BOUNDING_RECT_MARGIN, width),
+            to_pixel(bottom, BOUNDING_RECT_MARGIN, height),
+        );
+        self.draw_rect(x0, y0, x1 - x0 + 1, y1 - y0 + 1, color);
+    }
+}
+
+/// Clip the line from `a` to `b` to the rectangle from (0, 0) to `size`,
+/// using the Liang-Barsky algorithm. Returns `None` if the line lies
+/// completely outside of the rectangle.
+fn clip_line_to_rect(
+    a: (f32, f32),
+    b: (f32, f32),
+    size: (f32, f32),
+) -> Option<((f32, f32), (f32, f32))> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut t_start, mut t_end) = (0.0f32, 1.0f32);
+    // For each edge: the change in distance to the edge along the line, and
+    // the distance of `a` to the edge (negative when outside).
+    for (p, q) in [
+        (-dx, a.0),
+        (dx, size.0 - a.0),
+        (-dy, a.1),
+        (dy, size.1 - a.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t_start = t_start.max(t);
+            } else {
+                t_end = t_end.min(t);
+            }
+        }
+    }
+    if t_start > t_end {
+        return None;
+    }
+    let at = |t: f32| (a.0 + dx * t, a.1 + dy * t);
+    Some((at(t_start), at(t_end)))
+}
+
+/// Get the rows of a character in the bitmap font, from top to bottom. The
+/// three lowest bits of a row are its pixels, the highest bit being the
+/// leftmost pixel.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT as usize] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        _ => [0; GLYPH_HEIGHT as usize],
+    }
+}
+
+#[cfg(test)]
+/// The layout of a small frame buffer, for use in tests.
+fn test_layout(width: u32, height: u32) -> FrameLayout {
+    FrameLayout {
+        width,
+        height,
+        bytes_per_pixel: 4,
+        stride: width as usize * 4,
+    }
+}
+
+#[test]
+fn test_draw_line() {
+    let layout = test_layout(8, 8);
+    let mut frame = vec![0; layout.buffer_len()];
+    let white = RGBA8::new(255, 255, 255, 255);
+    let mut overlay = Overlay::new(&mut frame, layout);
+    overlay.draw_line(1, 1, 5, 5, white);
+    let is_set = |frame: &[u8], x, y| frame[layout.pixel_offset(x, y)] == 255;
+    for i in 1..=5 {
+        assert!(is_set(&frame, i, i));
+    }
+    let set_count = frame.chunks(4).filter(|p| p[0] == 255).count();
+    assert_eq!(set_count, 5);
+
+    // Lines partly or completely outside of the frame are clipped.
+    let mut frame = vec![0; layout.buffer_len()];
+    let mut overlay = Overlay::new(&mut frame, layout);
+    overlay.draw_line(-4, 2, 20, 2, white);
+    overlay.draw_line(-10, -10, -10, 20, white);
+    overlay.fill_rect(6, 6, 100, 100, white);
+    assert!((0..8).all(|x| is_set(&frame, x, 2)));
+    assert!(is_set(&frame, 7, 7) && !is_set(&frame, 5, 5));
+    let set_count = frame.chunks(4).filter(|p| p[0] == 255).count();
+    assert_eq!(set_count, 8 + 4);
+}
+
+#[test]
+fn test_draw_text() {
+    let layout = test_layout(16, 8);
+    let mut frame = vec![0; layout.buffer_len()];
+    let mut overlay = Overlay::new(&mut frame, layout);
+    overlay.draw_text(0, 0, "l1", RGBA8::new(255, 0, 0, 255));
+    let is_set = |x, y| frame[layout.pixel_offset(x, y)] == 255;
+    // The "L" is a vertical bar with a foot, the "1" starts one column after
+    // it.
+    assert!((0..5).all(|y| is_set(0, y)));
+    assert!(is_set(2, 4) && !is_set(2, 3));
+    assert!(is_set(5, 0) && !is_set(4, 0));
+    assert!((0..16).all(|x| !is_set(x, 5)));
+
+    // A label fills the background around the text.
+    let mut overlay = Overlay::new(&mut frame, layout);
+    let background = RGBA8::new(0, 0, 255, 255);
+    overlay.draw_label(0, 0, "l", RGBA8::new(255, 0, 0, 255), background);
+    let is_background = |x, y| frame[layout.pixel_offset(x, y) + 2] == 255;
+    assert!(is_background(0, 0) && is_background(4, 6));
+    assert!(!is_background(5, 0) && !is_background(0, 7));
+    assert!(frame[layout.pixel_offset(1, 1)] == 255);
+}
+
+#[test]
+fn test_draw_line_behind_camera() {
+    use crate::util::Resolution;
+    use crate::vector::vec3;
+
+    let camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 16, h: 16 },
+    )
+    .unwrap();
+    let layout = test_layout(16, 16);
+    let mut frame = vec![0; layout.buffer_len()];
+    let mut overlay = Overlay::new(&mut frame, layout);
+    let white = RGBA8::new(255, 255, 255, 255);
+    // A line completely behind the camera isn't drawn.
+    overlay.draw_line_3d(&camera, vec3(-1.0, 0.0, 1.0), vec3(1.0, 0.0, 1.0), white);
+    assert!(frame.iter().all(|&c| c == 0));
+
+    // A vertical line going from in front of the camera to behind it is cut
+    // off, and runs from the center of the image to the bottom.
+    let mut overlay = Overlay::new(&mut frame, layout);
+    overlay.draw_line_3d(&camera, vec3(0.0, 0.0, -4.0), vec3(0.0, -1.0, 1.0), white);
+    let set: Vec<(u32, u32)> = (0..16)
+        .flat_map(|y| (0..16).map(move |x| (x, y)))
+        .filter(|&(x, y)| frame[layout.pixel_offset(x, y)] == 255)
+        .collect();
+    assert_eq!(set, (8..16).map(|y| (8, y)).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_draw_bounding_rect() {
+    use crate::util::Resolution;
+    use crate::vector::vec3;
+
+    let camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 16, h: 16 },
+    )
+    .unwrap();
+    let layout = test_layout(16, 16);
+    let mut frame = vec![0; layout.buffer_len()];
+    let mut overlay = Overlay::new(&mut frame, layout);
+    // A triangle two pixels wide and high in the center of the image, with a
+    // vertex behind the camera that is left out.
+    let face = (
+        vec3(-0.5, -0.5, -4.0),
+        vec3(0.5, 0.5, -4.0),
+        vec3(0.0, 0.0, 4.0),
+    );
+    overlay.draw_bounding_rect(&camera, &[face], RGBA8::new(255, 255, 255, 255));
+    let set: Vec<(u32, u32)> = (0..16)
+        .flat_map(|y| (0..16).map(move |x| (x, y)))
+        .filter(|&(x, y)| frame[layout.pixel_offset(x, y)] == 255)
+        .collect();
+    // The outline of the 7 by 7 rectangle from (5, 5) to (11, 11).
+    assert_eq!(set.len(), 24);
+    assert!(set.contains(&(5, 5)) && set.contains(&(11, 11)));
+    assert!(set
+        .iter()
+        .all(|&(x, y)| x == 5 || x == 11 || y == 5 || y == 11));
+}
@@ -1,54 +1,368 @@
+use std::borrow::Cow;
 use std::cmp::{max, min};
+use std::error::Error;
+use std::f32::consts::PI;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-use crate::camera::Camera;
+use crate::camera::{Camera, ProjectionModel};
+use crate::color::Color;
 use crate::objects::*;
-use crate::util::RealRange;
-use crate::vector::Vec3;
-use crate::World;
+use crate::sampling::{PixelSampler, SamplingConfig};
+use crate::texture::{Cubemap, TextureFilter};
+use crate::util::{
+    load_object_from_file_json, save_frame_as_png, save_frame_as_ppm, save_object_as_file_json,
+    RealRange, Resolution,
+};
+use crate::vector::{vec3, Vec3};
+use crate::world::{ObjectHandle, World};
+
+use serde::{Deserialize, Serialize};
 
 use num_cpus;
 use rayon::prelude::*;
 use rgb::*;
 
 const PERPENDICULARITY_EPSILON: f32 = 0.001;
+/// How far shadow rays start off the surface, so a surface doesn't shadow
+/// itself because of rounding errors.
+const SHADOW_RAY_OFFSET: f32 = 0.001;
+/// The t values at which camera rays can hit objects. The direction of a
+/// camera ray points from the camera to the image plane, so hits in front of
+/// the image plane (at t < 1) are clipped.
+const CAMERA_RAY_RANGE: RealRange = RealRange::LargerEqual(1.0);
+/// The number of rows rendered between checks of the time budget.
+const BUDGET_BAND_ROWS: u32 = 16;
+/// How far away objects can be and still occlude the ambient light when
+/// ambient occlusion is baked for a scene.
+pub const AMBIENT_OCCLUSION_DISTANCE: f32 = 2.0;
 
-#[derive(Debug, Clone, Copy)]
+/// Renders a `World` as seen by a `Camera` into a frame buffer.
+///
+/// `render_world` only takes `&self`, and the renderer, world and camera are
+/// shared between all worker threads while rendering. The `Renderer` should
+/// therefore only hold configuration: any state that changes during a frame
+/// (buffers, statistics, caches) has to be passed to the render functions
+/// explicitly, so each multithreading method stays free of data races and
+/// produces the same image.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
 pub struct Renderer {
-    pub grayscale: bool,
+    /// Post filters, applied in order to every pixel of the final image.
+    pub filters: Vec<Filter>,
     pub multithreading_method: MultithreadingMethod,
+    /// The number of threads to render with. The Rayon and tiled
+    /// multithreading methods use a thread pool of their own with this many
+    /// threads, and the Crossbeam method spawns this many threads. Without a
+    /// thread count they use Rayon's global pool, or a thread for every core.
+    pub thread_count: Option<usize>,
+    /// Normalize the specular highlight so that a higher `shine` makes the
+    /// highlight smaller and brighter, instead of just smaller, keeping the
+    /// total reflected light the same.
+    pub energy_conserving_specular: bool,
+    /// Draw balls. Turning this off hides all balls, but they still cast
+    /// shadows, which helps to debug overlap between balls and triangles.
+    pub render_balls: bool,
+    /// Draw triangles, both of vertex objects and instances. Like
+    /// `render_balls`, this only affects what the camera sees.
+    pub render_triangles: bool,
+    /// Draw planes, like `render_balls`.
+    pub render_planes: bool,
+    /// Draw boxes, like `render_balls`.
+    pub render_boxes: bool,
+    pub render_mode: RenderMode,
+    /// The t values shown as black and white in the `Depth` render mode.
+    pub depth_near: f32,
+    pub depth_far: f32,
+    /// The maximum time in milliseconds `render_world_within_budget` may
+    /// spend on a frame. Without a budget, every frame is rendered completely.
+    pub time_budget_ms: Option<u64>,
+    /// How many times a ray may bounce off reflective surfaces.
+    pub max_bounce_depth: u8,
+    /// The seed and sample counts of the stochastic features. Renders with
+    /// identical renderers are byte-identical, whatever multithreading
+    /// method is used.
+    pub sampling: SamplingConfig,
+    /// Cast a single ray per pixel first, and only cast the `sampling.aa`
+    /// anti-aliasing samples (jittered) in pixels whose luma differs by more
+    /// than this threshold (0 to 255) from a neighbouring pixel. This saves
+    /// most samples in flat regions, while still smoothing the edges.
+    pub adaptive_aa_threshold: Option<f32>,
+    /// Find the triangles of vertex objects hit by a ray with the world's
+    /// bounding volume hierarchy, instead of testing every triangle. Both
+    /// give the same image, so this is only useful for benchmarking.
+    pub use_bvh: bool,
+    /// The gamma the output is encoded with, so mid-tones don't look too dark
+    /// on sRGB displays. A gamma of 1.0 leaves the colors unchanged.
+    pub gamma: f32,
+    /// Compress bright colors with Reinhard tone mapping, instead of clipping
+    /// them, so highlights near lights keep their detail. This also darkens
+    /// the image: a channel at 255 ends up at half brightness.
+    pub tone_mapping: bool,
+    /// How the textures of materials are sampled.
+    pub texture_filter: TextureFilter,
+    /// How the skybox is sampled, for camera rays as well as reflected and
+    /// refracted rays. Bilinear filtering keeps the
+    /// magnified pixels of a low resolution skybox from showing up as blocks.
+    pub environment_filter: TextureFilter,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Renderer {
+            filters: Vec::new(),
+            multithreading_method: MultithreadingMethod::Rayon,
+            thread_count: None,
+            energy_conserving_specular: false,
+            render_balls: true,
+            render_triangles: true,
+            render_planes: true,
+            render_boxes: true,
+            render_mode: RenderMode::default(),
+            depth_near: 1.0,
+            depth_far: 20.0,
+            time_budget_ms: None,
+            max_bounce_depth: 3,
+            sampling: SamplingConfig::default(),
+            adaptive_aa_threshold: None,
+            use_bvh: true,
+            gamma: 2.2,
+            tone_mapping: false,
+            texture_filter: TextureFilter::Nearest,
+            environment_filter: TextureFilter::Bilinear,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum MultithreadingMethod {
     None,
     Rayon,
     Crossbeam,
+    /// Render square tiles of `tile_size` by `tile_size` pixels in parallel,
+    /// so that neighbouring rays, which tend to hit the same objects, are
+    /// traced together.
+    Tiled {
+        tile_size: u32,
+    },
+}
+
+/// Which lighting terms make up the rendered image. The debug modes show a
+/// single term, to see exactly where e.g. the highlights of a material fall,
+/// or the geometry itself without any lighting.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum RenderMode {
+    /// Ambient, diffuse and specular lighting combined.
+    #[default]
+    Shaded,
+    DiffuseOnly,
+    SpecularOnly,
+    /// The t value of the nearest hit, in grayscale from black at
+    /// `depth_near` to white at `depth_far`. For the planar projection this
+    /// is the depth along the view direction.
+    Depth,
+    /// The surface normal at the nearest hit, with x, y and z mapped from
+    /// -1..1 to the red, green and blue channels.
+    Normals,
+}
+
+impl RenderMode {
+    /// Whether the mode shows the geometry instead of a lit color. These
+    /// modes skip tone mapping and the post filters, so the output values
+    /// can be read back exactly.
+    fn shows_geometry(self) -> bool {
+        matches!(self, RenderMode::Depth | RenderMode::Normals)
+    }
+}
+
+/// A post filter on the colors of the final image.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Replace colors by their luma (Rec. 601), i.e. how bright they look.
+    Grayscale,
+    Invert,
+    /// Give colors the brown tint of old photographs.
+    Sepia,
+    /// Multiply every channel by a factor.
+    Brightness(f32),
+    /// Scale the distance of every channel to mid-gray by a factor.
+    Contrast(f32),
+}
+
+impl Filter {
+    /// Apply the filter to a color. Alpha is left alone.
+    pub fn apply(self, color: RGBA8) -> RGBA8 {
+        let [r, g, b] = [color.r, color.g, color.b].map(|c| c as f32);
+        let channels = match self {
+            Filter::Grayscale => {
+                let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+                [luma, luma, luma]
+            }
+            Filter::Invert => [255.0 - r, 255.0 - g, 255.0 - b],
+            Filter::Sepia => [
+                0.393 * r + 0.769 * g + 0.189 * b,
+                0.349 * r + 0.686 * g + 0.168 * b,
+                0.272 * r + 0.534 * g + 0.131 * b,
+            ],
+            Filter::Brightness(factor) => [r, g, b].map(|c| c * factor),
+            Filter::Contrast(factor) => [r, g, b].map(|c| (c - 127.5) * factor + 127.5),
+        };
+        let [r, g, b] = channels.map(|c| c.round().clamp(0.0, 255.0) as u8);
+        RGBA8::new(r, g, b, color.a)
+    }
+}
+
+/// The memory layout of the frame buffers the renderer writes to: rows of
+/// pixels from top to bottom, each row from left to right, with 4 bytes per
+/// pixel in RGBA order. Alpha is always 255. This matches e.g. the
+/// `Rgba8UnormSrgb` texture format, so the buffer can be uploaded to a GPU
+/// texture or image widget as-is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameLayout {
+    pub width: u32,
+    pub height: u32,
+    pub bytes_per_pixel: usize,
+    /// The number of bytes between the starts of two consecutive rows.
+    pub stride: usize,
+}
+
+impl FrameLayout {
+    /// The total length of a frame buffer in bytes.
+    pub fn buffer_len(&self) -> usize {
+        self.stride * self.height as usize
+    }
+
+    /// The index in the frame buffer of the first byte of the pixel at (x, y).
+    pub fn pixel_offset(&self, x: u32, y: u32) -> usize {
+        y as usize * self.stride + x as usize * self.bytes_per_pixel
+    }
+}
+
+/// Whether a frame was rendered completely, or stopped because it ran out of
+/// time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderStatus {
+    Complete,
+    Incomplete,
+}
+
+/// The number of rays cast to render (part of) a frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RayStats {
+    /// One ray per anti-aliasing sample of every pixel.
+    pub camera_rays: u64,
+    /// The reflected and refracted rays.
+    pub secondary_rays: u64,
 }
 
 impl Renderer {
-    /// Draw the `World` state to the frame buffer.
+    /// Save the renderer configuration as a json preset file.
+    pub fn save_preset<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        save_object_as_file_json(self, path)
+    }
+
+    /// Load a renderer configuration from a json preset file. Settings that
+    /// are missing from the preset get their default value.
+    pub fn load_preset<P: AsRef<Path>>(path: P) -> Result<Renderer, Box<dyn Error>> {
+        load_object_from_file_json(path)
+    }
+
+    /// Get the layout of the frame buffer for rendering with a camera.
+    pub fn frame_layout(&self, camera: &Camera) -> FrameLayout {
+        let bytes_per_pixel = 4;
+        FrameLayout {
+            width: camera.resolution.w,
+            height: camera.resolution.h,
+            bytes_per_pixel,
+            stride: camera.resolution.w as usize * bytes_per_pixel,
+        }
+    }
+
+    /// Draw the `World` state to the frame buffer, which must have the layout
+    /// given by `frame_layout`.
     pub fn render_world(&self, world: &World, camera: &Camera, frame_buffer: &mut [u8]) {
+        assert_eq!(
+            frame_buffer.len(),
+            self.frame_layout(camera).buffer_len(),
+            "The frame buffer doesn't match the camera's resolution."
+        );
+        self.in_thread_pool(|| self.render_pixels(world, camera, frame_buffer, 0));
+    }
+
+    /// Draw the `World` state to the frame buffer, in bands of rows starting
+    /// at `next_row`, until the `time_budget_ms` runs out. Rows that weren't
+    /// reached keep their content from the previous frame, and `next_row` is
+    /// set to the first of them so the next call resumes from there.
+    pub fn render_world_within_budget(
+        &self,
+        world: &World,
+        camera: &Camera,
+        frame_buffer: &mut [u8],
+        next_row: &mut u32,
+    ) -> RenderStatus {
+        let Some(time_budget_ms) = self.time_budget_ms else {
+            self.render_world(world, camera, frame_buffer);
+            *next_row = 0;
+            return RenderStatus::Complete;
+        };
+        let start = Instant::now();
+        let row_size = self.frame_layout(camera).stride;
+        // All bands are rendered on the same thread pool.
+        self.in_thread_pool(|| {
+            while *next_row < camera.resolution.h {
+                let band_end = min(*next_row + BUDGET_BAND_ROWS, camera.resolution.h);
+                let band =
+                    &mut frame_buffer[*next_row as usize * row_size..band_end as usize * row_size];
+                let first_pixel = *next_row as usize * camera.resolution.w as usize;
+                self.render_pixels(world, camera, band, first_pixel);
+                *next_row = band_end;
+                if *next_row < camera.resolution.h
+                    && start.elapsed() >= Duration::from_millis(time_budget_ms)
+                {
+                    return RenderStatus::Incomplete;
+                }
+            }
+            *next_row = 0;
+            RenderStatus::Complete
+        })
+    }
+
+    /// Render the pixels in `frame_buffer`, which starts at the pixel with
+    /// index `first_pixel` of the full frame.
+    fn render_pixels(
+        &self,
+        world: &World,
+        camera: &Camera,
+        frame_buffer: &mut [u8],
+        first_pixel: usize,
+    ) {
+        if let Some(threshold) = self.adaptive_aa_threshold {
+            self.render_pixels_adaptive(world, camera, frame_buffer, first_pixel, threshold);
+            return;
+        }
         match self.multithreading_method {
             MultithreadingMethod::None => {
                 for (i, pixel) in frame_buffer.chunks_exact_mut(4).enumerate() {
-                    self.render_pixel(i, pixel, camera, world);
+                    self.render_pixel(first_pixel + i, pixel, camera, world);
                 }
             }
             MultithreadingMethod::Rayon => {
                 frame_buffer
                     .par_chunks_exact_mut(4)
                     .enumerate()
-                    .map(|(i, pixel)| self.render_pixel(i, pixel, camera, world))
+                    .map(|(i, pixel)| self.render_pixel(first_pixel + i, pixel, camera, world))
                     .collect::<()>();
             }
             MultithreadingMethod::Crossbeam => {
                 // Multithreaded!
                 // Each pixel takes up 4 bytes
-                let cpu_count = num_cpus::get();
+                let thread_count = self.thread_count.unwrap_or_else(num_cpus::get);
                 let pixel_count = frame_buffer.len() / 4;
-                let pixels_per_thread = pixel_count / cpu_count;
+                // Make sure every thread gets at least one pixel, even for
+                // tiny frames.
+                let pixels_per_thread = max(pixel_count / thread_count.max(1), 1);
                 crossbeam::scope(|s| {
-                    let mut offset: usize = 0;
+                    let mut offset: usize = first_pixel;
                     // The length of the array is 4 times the amount of pixels, so we need
                     // add a factor of 4.
                     for chunk in frame_buffer.chunks_mut(4 * pixels_per_thread) {
@@ -64,442 +378,3084 @@ impl Renderer {
                 })
                 .unwrap();
             }
+            MultithreadingMethod::Tiled { tile_size } => {
+                self.render_tiles(world, camera, frame_buffer, first_pixel, tile_size);
+            }
         }
     }
 
-    fn render_pixel(&self, pixel_index: usize, pixel: &mut [u8], camera: &Camera, world: &World) {
-        let pixel_ray_direction = Self::calculate_pixel_ray(camera, pixel_index);
-
-        let closest_ball =
-            self.get_nearest_intersecting_ball(&world.balls, camera.pos, pixel_ray_direction);
-        let closest_triangle = self.get_nearest_intersecting_triangle(
-            &world.vertex_objects,
-            camera.pos,
-            pixel_ray_direction,
-        );
-        let mut hit = true;
-        let mut ball_closer = false;
-        match (closest_ball, closest_triangle) {
-            (Some((_, pos_hit_ball)), Some((_, _, pos_hit_triangle))) => {
-                hit = true;
-                let distance_ball = (camera.pos - pos_hit_ball).len();
-                let distance_triangle = (camera.pos - pos_hit_triangle).len();
-                ball_closer = distance_ball <= distance_triangle;
-            }
-            (Some(_), None) => ball_closer = true,
-            (None, Some(_)) => ball_closer = false,
-            (None, None) => hit = false,
+    /// Render the pixels in `frame_buffer` like `render_pixels`, with a single
+    /// ray per pixel, and then render the pixels at edges again with jittered
+    /// anti-aliasing samples. Edges are only detected within `frame_buffer`.
+    fn render_pixels_adaptive(
+        &self,
+        world: &World,
+        camera: &Camera,
+        frame_buffer: &mut [u8],
+        first_pixel: usize,
+        threshold: f32,
+    ) {
+        let first_pass = Renderer {
+            sampling: SamplingConfig {
+                aa: 1,
+                ..self.sampling
+            },
+            adaptive_aa_threshold: None,
+            ..self.clone()
+        };
+        first_pass.render_pixels(world, camera, frame_buffer, first_pixel);
+
+        let edges = find_edge_pixels(frame_buffer, camera.resolution.w as usize, threshold);
+        let resample = |&i: &usize| {
+            let pixel_index = first_pixel + i;
+            let offsets = self.supersample_offsets(pixel_index, camera.resolution.w);
+            let mut pixel = [0; 4];
+            self.render_samples(pixel_index, &mut pixel, camera, world, offsets);
+            (i, pixel)
+        };
+        let resampled: Vec<(usize, [u8; 4])> = match self.multithreading_method {
+            MultithreadingMethod::None => edges.iter().map(resample).collect(),
+            _ => edges.par_iter().map(resample).collect(),
+        };
+        for (i, pixel) in resampled {
+            frame_buffer[4 * i..4 * i + 4].copy_from_slice(&pixel);
+        }
+    }
+
+    /// Run `f` on a Rayon thread pool with `thread_count` threads, so the
+    /// parallel iterators in it render on that pool, or on the global pool
+    /// when no thread count is set. The pool is built for every call, so it
+    /// should wrap the rendering of a whole frame.
+    fn in_thread_pool<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        let Some(thread_count) = self.thread_count.filter(|_| self.uses_rayon()) else {
+            return f();
+        };
+        match rayon::ThreadPoolBuilder::new()
+            .num_threads(thread_count)
+            .build()
+        {
+            Ok(pool) => pool.install(f),
+            Err(e) => {
+                eprintln!(
+                    "Failed to create a thread pool, using the global pool: {}",
+                    e
+                );
+                f()
+            }
+        }
+    }
+
+    /// Whether rendering uses Rayon's parallel iterators, which only the
+    /// Crossbeam method without adaptive anti-aliasing and rendering on a
+    /// single thread don't.
+    fn uses_rayon(&self) -> bool {
+        match self.multithreading_method {
+            MultithreadingMethod::None => false,
+            MultithreadingMethod::Crossbeam => self.adaptive_aa_threshold.is_some(),
+            MultithreadingMethod::Rayon | MultithreadingMethod::Tiled { .. } => true,
+        }
+    }
+
+    /// Render the rows in `frame_buffer`, which starts at the pixel with index
+    /// `first_pixel` of the full frame, as square tiles on the Rayon thread
+    /// pool. Every tile is rendered into its own buffer and copied into the
+    /// frame buffer afterwards.
+    fn render_tiles(
+        &self,
+        world: &World,
+        camera: &Camera,
+        frame_buffer: &mut [u8],
+        first_pixel: usize,
+        tile_size: u32,
+    ) {
+        let width = camera.resolution.w as usize;
+        let height = frame_buffer.len() / 4 / width;
+        let tile_size = max(tile_size, 1) as usize;
+        let tiles: Vec<(usize, usize)> = (0..height)
+            .step_by(tile_size)
+            .flat_map(|y| (0..width).step_by(tile_size).map(move |x| (x, y)))
+            .collect();
+        let rendered_tiles: Vec<Vec<u8>> = tiles
+            .par_iter()
+            .map(|&(x, y)| {
+                let tile_width = min(tile_size, width - x);
+                let tile_height = min(tile_size, height - y);
+                let mut tile = vec![0; tile_width * tile_height * 4];
+                for (i, pixel) in tile.chunks_exact_mut(4).enumerate() {
+                    let (tile_x, tile_y) = (i % tile_width, i / tile_width);
+                    let pixel_index = first_pixel + (y + tile_y) * width + x + tile_x;
+                    self.render_pixel(pixel_index, pixel, camera, world);
+                }
+                tile
+            })
+            .collect();
+        for (&(x, y), tile) in tiles.iter().zip(&rendered_tiles) {
+            let tile_width = min(tile_size, width - x);
+            for (tile_y, row) in tile.chunks_exact(tile_width * 4).enumerate() {
+                let start = ((y + tile_y) * width + x) * 4;
+                frame_buffer[start..start + row.len()].copy_from_slice(row);
+            }
+        }
+    }
+
+    /// Render the `World` into a newly allocated RGBA frame buffer at the
+    /// camera's resolution, without the need for a window.
+    pub fn render_to_vec(&self, world: &World, camera: &Camera) -> Vec<u8> {
+        let mut frame_buffer = vec![0; self.frame_layout(camera).buffer_len()];
+        self.render_world(world, camera, &mut frame_buffer);
+        frame_buffer
+    }
+
+    /// Render the `World` at the camera's resolution and save it as a png
+    /// file. The image matches the frame shown on screen.
+    pub fn render_to_png<P: AsRef<Path>>(
+        &self,
+        world: &World,
+        camera: &Camera,
+        path: P,
+    ) -> Result<(), Box<dyn Error>> {
+        let frame_buffer = self.render_to_vec(world, camera);
+        save_frame_as_png(&frame_buffer, camera.resolution, path)
+    }
+
+    /// Render the `World` at the camera's resolution and save it as a binary
+    /// ppm file, which doesn't need an image library.
+    pub fn render_to_ppm<P: AsRef<Path>>(
+        &self,
+        world: &World,
+        camera: &Camera,
+        path: P,
+    ) -> Result<(), Box<dyn Error>> {
+        let frame_buffer = self.render_to_vec(world, camera);
+        save_frame_as_ppm(&frame_buffer, camera.resolution, path)
+    }
+
+    /// Render the six faces of a cubemap as seen from `position`, in the
+    /// order +X, -X, +Y, -Y, +Z, -Z. Every face is a square RGBA frame buffer
+    /// of `face_size` by `face_size` pixels, rendered with a 90 degree field of
+    /// view, oriented as given by `Cubemap::face_orientations`.
+    pub fn render_cubemap(&self, world: &World, position: Vec3, face_size: u32) -> [Vec<u8>; 6] {
+        let resolution = Resolution {
+            w: face_size,
+            h: face_size,
         };
+        // Unwrap is safe, because 90 degrees is a valid field of view.
+        let mut camera = Camera::new(position, vec3(0.0, 0.0, -1.0), 90.0, resolution).unwrap();
+        Cubemap::face_orientations().map(|(direction, up)| {
+            camera.set_orientation(direction, up);
+            self.render_to_vec(world, &camera)
+        })
+    }
+
+    /// Render a single pixel. Colors are processed in a fixed order:
+    ///
+    /// 1. shade: every sample is shaded into a linear, unclamped color,
+    /// 2. average: the samples of a pixel are averaged, still in linear space,
+    /// 3. tone map / gamma: the average is mapped to the displayable range,
+    /// 4. filters: post filters like grayscale run once on the final color.
+    ///
+    /// Nonlinear steps have to come after averaging, otherwise the result
+    /// depends on the number of samples per pixel.
+    fn render_pixel(&self, pixel_index: usize, pixel: &mut [u8], camera: &Camera, world: &World) {
+        let offsets = self.supersample_offsets(pixel_index, camera.resolution.w);
+        self.render_samples(pixel_index, pixel, camera, world, offsets);
+    }
 
-        let mut rgba = if hit {
-            if ball_closer {
-                let (ball, pos_hit_ball) = closest_ball.unwrap();
-                let ball_normal = (pos_hit_ball - ball.pos).normalized();
-                self.get_light_color(ball, world, camera, pos_hit_ball, ball_normal)
+    /// Render a single pixel like `render_pixel`, with a ray for every offset
+    /// (in pixels) from the pixel's sample point.
+    fn render_samples(
+        &self,
+        pixel_index: usize,
+        pixel: &mut [u8],
+        camera: &Camera,
+        world: &World,
+        offsets: impl Iterator<Item = (f32, f32)>,
+    ) {
+        let mut color = Color::default();
+        let mut samples = 0;
+        let mut secondary_rays = 0;
+        for offset in offsets {
+            let direction = Self::calculate_subpixel_ray(camera, pixel_index, offset);
+            let sample = if self.render_mode.shows_geometry() {
+                self.geometry_color(world, camera.pos, direction)
             } else {
-                let (vertex_object, face, pos_hit_triangle) = closest_triangle.unwrap();
-                let triangle_normal = get_triangle_normal(face);
-                self.get_light_color(
-                    vertex_object,
+                self.shade(
                     world,
-                    camera,
-                    pos_hit_triangle,
-                    triangle_normal,
+                    camera.pos,
+                    direction,
+                    CAMERA_RAY_RANGE,
+                    0,
+                    &mut secondary_rays,
                 )
-            }
+            };
+            color +=
+                sample.unwrap_or_else(|| self.background(world, camera, pixel_index, direction));
+            samples += 1;
+        }
+        let color = color * (1.0 / samples as f32);
+        let rgba = if self.render_mode.shows_geometry() {
+            color.to_rgba8()
         } else {
-            world.color
+            self.apply_filters(self.tone_map(color))
         };
-
-        self.apply_filters(&mut rgba);
-
         pixel.copy_from_slice(rgba.as_slice());
     }
 
-    /// Calculate the pixel ray (i.e. the ray shooting out of the camera in a
-    /// particular direction) for the i-th pixel.
-    fn calculate_pixel_ray(camera: &Camera, i: usize) -> Vec3 {
-        let alpha = (i % camera.resolution.w as usize) as f32 / camera.resolution.w as f32;
-        let beta = (i / camera.resolution.w as usize) as f32 / camera.resolution.h as f32;
+    /// Count the rays cast to render the pixel with index `pixel_index`,
+    /// without adaptive anti-aliasing.
+    pub fn pixel_ray_stats(&self, world: &World, camera: &Camera, pixel_index: usize) -> RayStats {
+        let mut stats = RayStats::default();
+        for offset in self.supersample_offsets(pixel_index, camera.resolution.w) {
+            stats.camera_rays += 1;
+            if !self.render_mode.shows_geometry() {
+                let direction = Self::calculate_subpixel_ray(camera, pixel_index, offset);
+                self.shade(
+                    world,
+                    camera.pos,
+                    direction,
+                    CAMERA_RAY_RANGE,
+                    0,
+                    &mut stats.secondary_rays,
+                );
+            }
+        }
+        stats
+    }
+
+    /// Count the rays cast to render a whole frame, like `pixel_ray_stats`.
+    pub fn ray_stats(&self, world: &World, camera: &Camera) -> RayStats {
+        let pixel_count = (camera.resolution.w * camera.resolution.h) as usize;
+        self.in_thread_pool(|| {
+            (0..pixel_count)
+                .into_par_iter()
+                .map(|i| self.pixel_ray_stats(world, camera, i))
+                .reduce(RayStats::default, |a, b| RayStats {
+                    camera_rays: a.camera_rays + b.camera_rays,
+                    secondary_rays: a.secondary_rays + b.secondary_rays,
+                })
+        })
+    }
 
-        let hi = camera.image_plane.top_left * (1.0 - alpha) + camera.image_plane.top_right * alpha;
-        let lo = camera.image_plane.bottom_left * (1.0 - alpha)
-            + camera.image_plane.bottom_right * alpha;
-        let pixel_vec = hi * (1.0 - beta) + lo * beta;
+    /// Get the offsets (in pixels) of the `sampling.aa` anti-aliasing rays of
+    /// a pixel in a frame `width` pixels wide, spread over the pixel's
+    /// footprint by its `PixelSampler`. A single sample has no offset.
+    fn supersample_offsets(
+        &self,
+        pixel_index: usize,
+        width: u32,
+    ) -> impl Iterator<Item = (f32, f32)> {
+        let samples = self.sampling.aa.max(1);
+        let sampler = PixelSampler::new(
+            (pixel_index % width as usize) as u32,
+            (pixel_index / width as usize) as u32,
+            self.sampling.seed,
+        );
+        (0..samples).map(move |k| {
+            if samples == 1 {
+                return (0.0, 0.0);
+            }
+            let (u, v) = sampler.sample(k);
+            (u - 0.5, v - 0.5)
+        })
+    }
 
-        pixel_vec - camera.pos
+    /// Get the color of the background behind a camera ray through a pixel:
+    /// the world's background image stretched over the whole frame if it has
+    /// one, or its surroundings in the ray's direction. With fog, the
+    /// background is infinitely far away and only the fog is seen.
+    fn background(
+        &self,
+        world: &World,
+        camera: &Camera,
+        pixel_index: usize,
+        direction: Vec3,
+    ) -> Color {
+        if let Some(fog) = world.fog {
+            return Color::from(fog.color);
+        }
+        match &world.background_image {
+            Some(image) => {
+                let x = (pixel_index % camera.resolution.w as usize) as f32 + 0.5;
+                let y = (pixel_index / camera.resolution.w as usize) as f32 + 0.5;
+                Color::from(image.sample(
+                    x / camera.resolution.w as f32,
+                    y / camera.resolution.h as f32,
+                ))
+            }
+            None => self.surroundings(world, direction),
+        }
     }
 
-    fn apply_filters(&self, rgba: &mut RGBA8) {
-        if self.grayscale {
-            let avg = rgba.r / 3 + rgba.g / 3 + rgba.b / 3;
-            rgba.r = avg;
-            rgba.g = avg;
-            rgba.b = avg;
+    /// Get the color of the world's skybox, or else its background, in a
+    /// direction.
+    fn surroundings(&self, world: &World, direction: Vec3) -> Color {
+        match &world.skybox {
+            Some(skybox) => Color::from(skybox.sample(direction, self.environment_filter)),
+            None => world.background.color_in_direction(direction),
         }
     }
 
-    fn find_ray_triangle_intersection<'a>(
+    /// Get the linear color seen along a ray, or `None` if the ray doesn't hit
+    /// anything within `t_range`. `depth` is the number of times the ray has
+    /// been reflected already.
+    fn shade(
+        &self,
+        world: &World,
+        origin: Vec3,
+        direction: Vec3,
+        t_range: RealRange,
+        depth: u8,
+        secondary_rays: &mut u64,
+    ) -> Option<Color> {
+        let (_, hit) = self.get_nearest_hit(world, origin, direction, t_range, |handle| {
+            self.is_rendered(handle)
+        })?;
+        let color = self.shade_hit(world, origin, direction, hit, depth, secondary_rays);
+        Some(match world.fog {
+            Some(fog) => {
+                let amount = fog.amount((hit.point - origin).len());
+                color * (1.0 - amount) + Color::from(fog.color) * amount
+            }
+            None => color,
+        })
+    }
+
+    /// Get the linear color of a surface hit by a ray, including its
+    /// reflections and refractions. The reflected and refracted rays are
+    /// counted in `secondary_rays`.
+    fn shade_hit(
         &self,
-        triangle_pos: Vec3,
-        triangle: TriangleFace,
+        world: &World,
         origin: Vec3,
         direction: Vec3,
-        t_allowed_range: RealRange,
-    ) -> Option<f32> {
-        // Normalize the direction vector so that the returned t value is
-        // representative of the distance to the intersection.
+        hit: Hit,
+        depth: u8,
+        secondary_rays: &mut u64,
+    ) -> Color {
+        let Hit {
+            point: pos_hit,
+            normal,
+            outward_normal,
+            material,
+            ..
+        } = hit;
+        let mut color = self.get_light_color(material, world, origin, pos_hit, normal);
+        // Reflections are enabled per object by its reflectivity (or
+        // transparency), so surfaces without either are as cheap as in a
+        // world without reflections.
+        let casts_secondary_rays = material.reflectivity > 0.0 || material.transparency > 0.0;
+        if depth >= self.max_bounce_depth || !casts_secondary_rays {
+            return color;
+        }
+
+        // Look at the side of the surface the ray came from.
         let direction = direction.normalized();
+        let mut normal = outward_normal.normalized();
+        let entering = normal.dot(direction) < 0.0;
+        if !entering {
+            normal = -normal;
+        }
+        let reflected = direction.reflect(normal).normalized();
+        if material.transparency > 0.0 {
+            let eta = if entering {
+                1.0 / material.refractive_index
+            } else {
+                material.refractive_index
+            };
+            let transmitted = match direction.refract(normal, eta) {
+                Some(refracted) => self.trace(
+                    world,
+                    pos_hit - normal * SHADOW_RAY_OFFSET,
+                    refracted,
+                    depth,
+                    secondary_rays,
+                ),
+                // Total internal reflection: the light that would have passed
+                // through is reflected instead.
+                None => self.trace(
+                    world,
+                    pos_hit + normal * SHADOW_RAY_OFFSET,
+                    reflected,
+                    depth,
+                    secondary_rays,
+                ),
+            };
+            color = color * (1.0 - material.transparency) + transmitted * material.transparency;
+        }
+        if material.reflectivity > 0.0 {
+            let reflected_color = self.trace(
+                world,
+                pos_hit + normal * SHADOW_RAY_OFFSET,
+                reflected,
+                depth,
+                secondary_rays,
+            );
+            color = color * (1.0 - material.reflectivity) + reflected_color * material.reflectivity;
+        }
+        color
+    }
 
-        let (v0_relative, v1_relative, v2_relative) = triangle;
-        // Get the real coordinates (adjusted for the object position)
-        let v0 = v0_relative + triangle_pos;
-        let v1 = v1_relative + triangle_pos;
-        let v2 = v2_relative + triangle_pos;
+    /// Get the color the `Depth` or `Normals` render mode shows for a camera
+    /// ray, or `None` if the ray doesn't hit anything.
+    fn geometry_color(&self, world: &World, origin: Vec3, direction: Vec3) -> Option<Color> {
+        let (_, hit) =
+            self.get_nearest_hit(world, origin, direction, CAMERA_RAY_RANGE, |handle| {
+                self.is_rendered(handle)
+            })?;
+        if self.render_mode == RenderMode::Normals {
+            let n = hit.normal.normalized();
+            let channel = |c: f32| (c + 1.0) * 127.5;
+            return Some(Color::new(channel(n.x), channel(n.y), channel(n.z)));
+        }
+        let depth = (hit.t - self.depth_near) / (self.depth_far - self.depth_near);
+        let gray = depth.clamp(0.0, 1.0) * 255.0;
+        Some(Color::new(gray, gray, gray))
+    }
 
-        // Get the normal:
-        let n = get_triangle_normal((v0, v1, v2));
+    /// Get the color seen along a secondary (reflected or refracted) ray that
+    /// starts at a point on a surface, falling back to the world's
+    /// surroundings (or the fog color). The ray is counted in
+    /// `secondary_rays`, along with the rays it causes.
+    fn trace(
+        &self,
+        world: &World,
+        from: Vec3,
+        direction: Vec3,
+        depth: u8,
+        secondary_rays: &mut u64,
+    ) -> Color {
+        *secondary_rays += 1;
+        self.shade(
+            world,
+            from,
+            direction,
+            RealRange::LargerEqual(0.0),
+            depth + 1,
+            secondary_rays,
+        )
+        .unwrap_or_else(|| match world.fog {
+            Some(fog) => Color::from(fog.color),
+            None => self.surroundings(world, direction),
+        })
+    }
 
-        // Find intersections:
+    /// Get the object that is visible at the pixel at (x, y), if any. Hidden
+    /// objects can't be picked.
+    pub fn pick(&self, world: &World, camera: &Camera, x: u32, y: u32) -> Option<ObjectHandle> {
+        let pixel_index = (y * camera.resolution.w + x) as usize;
+        let direction = Self::calculate_pixel_ray(camera, pixel_index);
+        self.get_nearest_hit(world, camera.pos, direction, CAMERA_RAY_RANGE, |handle| {
+            self.is_rendered(handle)
+        })
+        .map(|(handle, _)| handle)
+    }
 
-        // First check if the ray and the plane are not parallel. We do
-        // this by calculating the dotproduct of the normal N and the
-        // direction vector. If this is (close to) 0, it means that the
-        // direction is perpendicular to the normal, and thus parallel
-        // to the plane.
-        if (n * direction).abs() < PERPENDICULARITY_EPSILON {
-            return None;
-        }
+    /// Calculate the pixel ray (i.e. the ray shooting out of the camera in a
+    /// particular direction) for the i-th pixel.
+    fn calculate_pixel_ray(camera: &Camera, i: usize) -> Vec3 {
+        Self::calculate_subpixel_ray(camera, i, (0.0, 0.0))
+    }
 
-        // Calculate d in the plane equation
-        // (in linear form: ax + by + cz + d = 0)
-        let d = n * v0 * -1.0;
-        let t = -(n * origin + d) / (n * direction);
-        // Check if the triangle is behind the camera's ImagePlane
-        if !t_allowed_range.contains(t) {
-            return None;
-        }
-        // Check if the intersection between the ray and the plane is
-        // actually inside the triangle.
-        let p = origin + direction * t;
-        // i is the inward-facing vector
-        let mut i: Vec3;
+    /// Like `calculate_pixel_ray`, but for a ray that is moved by `offset`
+    /// pixels (on top of the camera's jitter) within the pixel's footprint.
+    fn calculate_subpixel_ray(camera: &Camera, i: usize, offset: (f32, f32)) -> Vec3 {
+        let dx = camera.subpixel_offset.0 + offset.0;
+        let dy = camera.subpixel_offset.1 + offset.1;
+        let alpha = ((i % camera.resolution.w as usize) as f32 + dx) / camera.resolution.w as f32;
+        let beta = ((i / camera.resolution.w as usize) as f32 + dy) / camera.resolution.h as f32;
 
-        // First edge:
+        match camera.projection_model {
+            ProjectionModel::Planar => {
+                let hi = camera.image_plane.top_left * (1.0 - alpha)
+                    + camera.image_plane.top_right * alpha;
+                let lo = camera.image_plane.bottom_left * (1.0 - alpha)
+                    + camera.image_plane.bottom_right * alpha;
+                let pixel_vec = hi * (1.0 - beta) + lo * beta;
 
-        let edge0 = v1 - v0;
-        i = n.cross_product(edge0);
-        let v0p = p - v0;
-        if i * v0p < 0.0 {
-            return None;
+                pixel_vec - camera.pos
+            }
+            ProjectionModel::Spherical => {
+                // Get the camera's orientation from the image plane.
+                let plane = camera.image_plane;
+                let forward = camera.get_view_direction();
+                let right = (plane.top_right - plane.top_left).normalized();
+                let up = (plane.top_left - plane.bottom_left).normalized();
+
+                // The angles (in radians) of the pixel relative to the center
+                // of the image, such that every pixel spans the same angle.
+                let x = (alpha - 0.5) * camera.get_field_of_view_horizontal();
+                let y = (0.5 - beta) * camera.get_field_of_view_vertical();
+                let angle = (x * x + y * y).sqrt();
+                if angle == 0.0 {
+                    return forward;
+                }
+                forward * angle.cos() + (right * (x / angle) + up * (y / angle)) * angle.sin()
+            }
         }
-        // Second edge:
-        let edge1 = v2 - v1;
-        i = n.cross_product(edge1);
-        let v1p = p - v1;
-        if i * v1p < 0.0 {
-            return None;
+    }
+
+    /// Map a linear color to the displayable range of 0 to 255 per channel,
+    /// either with Reinhard tone mapping (c / (1 + c), with 255 as 1.0) or by
+    /// clipping overexposed colors.
+    fn tone_map(&self, color: Color) -> Color {
+        if self.tone_mapping {
+            color.map(|c| {
+                let c = c.max(0.0) / 255.0;
+                c / (1.0 + c) * 255.0
+            })
+        } else {
+            color.map(|c| c.clamp(0.0, 255.0))
         }
-        // Third edge:
-        let edge2 = v0 - v2;
-        i = n.cross_product(edge2);
-        let v2p = p - v2;
-        if i * v2p < 0.0 {
-            return None;
+    }
+
+    /// Gamma correct a tone mapped color, convert it to the frame buffer's
+    /// format and apply the post filters.
+    fn apply_filters(&self, mut color: Color) -> RGBA8 {
+        if self.gamma != 1.0 {
+            color = color.map(|c| (c / 255.0).powf(1.0 / self.gamma) * 255.0);
         }
-        // We've found an intersection!
-        Some(t)
+        self.filters
+            .iter()
+            .fold(color.to_rgba8(), |color, filter| filter.apply(color))
+    }
+
+    /// Pair every vertex object in the world with its faces and bounding
+    /// sphere in world space.
+    fn vertex_objects_with_faces(
+        world: &World,
+    ) -> impl Iterator<
+        Item = (
+            &VertexObject,
+            Cow<'_, [TriangleFace]>,
+            Option<BoundingSphere>,
+        ),
+    > {
+        world
+            .vertex_objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| {
+                (
+                    object,
+                    world.vertex_object_faces(index),
+                    world.vertex_object_bounding_sphere(index),
+                )
+            })
     }
 
-    fn find_ray_ball_intersection<'a>(
+    /// Get the triangle face nearest to the origin with a t value within
+    /// `t_range`. The faces of each object must already be offset by the
+    /// object's position. Objects whose bounding sphere the ray misses are
+    /// skipped without testing their faces.
+    fn get_nearest_intersecting_triangle<'a, 'b>(
         &self,
-        ball: &Ball,
+        objects: impl IntoIterator<
+            Item = (
+                &'a VertexObject,
+                Cow<'b, [TriangleFace]>,
+                Option<BoundingSphere>,
+            ),
+        >,
         origin: Vec3,
         direction: Vec3,
-        t_allowed_range: RealRange,
-    ) -> Option<f32> {
-        let center_adj = origin - ball.pos;
-        // Apply the quadratic equation:
-        let a: f32 = {
-            let dir_len = direction.len();
-            dir_len * dir_len
-        };
-        let b: f32 = center_adj * direction * 2.0;
-        let c: f32 = center_adj.len() * center_adj.len() - ball.rad * ball.rad;
-        let d: f32 = b * b - 4.0 * a * c;
-        match d {
-            x if x < 0.0 => {
-                // No intersections.
-                return None;
-            }
-            x if x == 0.0 => {
-                // The ray intersects the ball in only one place, meaning it
-                // actually only "touches" the ball.
-                let t = -b / 2.0 * a;
-
-                if t_allowed_range.contains(t) {
-                    return Some(t);
-                } else {
-                    return None;
-                }
+        t_range: RealRange,
+    ) -> Option<FaceHit<'a>> {
+        let mut t_min = f32::MAX;
+        let mut result = None;
+        for (object_index, (object, faces, bounding_sphere)) in objects.into_iter().enumerate() {
+            if bounding_sphere.is_some_and(|sphere| !sphere.is_hit_by(origin, direction)) {
+                continue;
             }
-            x if x > 0.0 => {
-                // Two ray intersects the ball in two places (one at the front,
-                // one at the back).
-                let t1 = (-b + d.sqrt()) / (2.0 * a);
-                let t2 = (-b - d.sqrt()) / (2.0 * a);
-                if t_allowed_range.contains(t1) || t_allowed_range.contains(t2) {
-                    // Take the smallest t value.
-                    let t = t1.min(t2);
-                    return Some(t);
-                } else {
-                    return None;
+            for (face_index, &face) in faces.iter().enumerate() {
+                if let Some((t, barycentric)) =
+                    Self::intersect_triangle(face, origin, direction, t_range)
+                        .filter(|&(t, _)| t < t_min)
+                {
+                    t_min = t;
+                    result = Some(FaceHit {
+                        object,
+                        object_index,
+                        face,
+                        face_index,
+                        t,
+                        point: origin + direction * t,
+                        barycentric,
+                    });
                 }
             }
-            _ => {
-                return None;
+        }
+        result
+    }
+
+    /// Get the nearest vertex object hit by a ray, together with its handle.
+    /// The world's bounding volume hierarchy is used when `use_bvh` is set
+    /// and it is up to date.
+    fn get_nearest_intersecting_vertex_object(
+        &self,
+        world: &World,
+        origin: Vec3,
+        direction: Vec3,
+        t_range: RealRange,
+    ) -> Option<(ObjectHandle, Hit)> {
+        let face_hit = match world.triangle_bvh().filter(|_| self.use_bvh) {
+            Some(bvh) => {
+                let intersect = |face| Self::intersect_triangle(face, origin, direction, t_range);
+                let (triangle, _) =
+                    bvh.nearest(origin, direction, t_range.lower_bound(), |triangle| {
+                        intersect(triangle.face).map(|(t, _)| t)
+                    })?;
+                let (t, barycentric) = intersect(triangle.face)?;
+                FaceHit {
+                    object: &world.vertex_objects[triangle.object_index],
+                    object_index: triangle.object_index,
+                    face: triangle.face,
+                    face_index: triangle.face_index,
+                    t,
+                    point: origin + direction * t,
+                    barycentric,
+                }
             }
+            None => self.get_nearest_intersecting_triangle(
+                Self::vertex_objects_with_faces(world),
+                origin,
+                direction,
+                t_range,
+            )?,
+        };
+        Some((
+            ObjectHandle::VertexObject(face_hit.object_index),
+            face_hit.to_hit(world, None, self.texture_filter),
+        ))
+    }
+
+    /// Intersect a ray with a single triangle face, using the Möller-Trumbore
+    /// algorithm. Only hits with a t value (in units of `direction`) within
+    /// `t_range` count. Returns the t value and the barycentric coordinates
+    /// of the hit.
+    fn intersect_triangle(
+        face: TriangleFace,
+        origin: Vec3,
+        direction: Vec3,
+        t_range: RealRange,
+    ) -> Option<(f32, Barycentric)> {
+        let (v0, v1, v2) = face;
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+        let p = direction.cross_product(edge2);
+        // The determinant is (minus) the dot product of the direction and
+        // the triangle's normal, so if it is (close to) 0 the ray runs
+        // parallel to the triangle.
+        let determinant = edge1.dot(p);
+        if determinant.abs() < PERPENDICULARITY_EPSILON {
+            return None;
         }
+        let inverse_determinant = 1.0 / determinant;
+        let v0_origin = origin - v0;
+        let u = v0_origin.dot(p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = v0_origin.cross_product(edge1);
+        let v = direction.dot(q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(q) * inverse_determinant;
+        if !t_range.contains(t) {
+            return None;
+        }
+        Some((t, (1.0 - u - v, u, v)))
     }
 
-    // TODO: add a "t value constraint" argument
-    /// Get the triangle face nearest to the origin
-    fn get_nearest_intersecting_triangle<'a>(
+    /// Get the nearest instance hit by a ray, together with its handle. The
+    /// hit is given in world space.
+    fn get_nearest_intersecting_instance(
         &self,
-        objects: &'a [VertexObject],
+        world: &World,
         origin: Vec3,
         direction: Vec3,
-    ) -> Option<(&'a VertexObject, TriangleFace, Vec3)> {
+        t_range: RealRange,
+    ) -> Option<(ObjectHandle, Hit)> {
         let mut t_min = f32::MAX;
         let mut result = None;
-        for object in objects {
-            for face in object.iter_faces() {
-                let (v0_relative, v1_relative, v2_relative) = face;
-                // Get the real coordinates (adjusted for the object position)
-                let v0 = v0_relative + object.pos;
-                let v1 = v1_relative + object.pos;
-                let v2 = v2_relative + object.pos;
-
-                // Get the normal:
-                let n = get_triangle_normal((v0, v1, v2));
-
-                // Find intersections:
-
-                // First check if the ray and the plane are not parallel. We do
-                // this by calculating the dotproduct of the normal N and the
-                // direction vector. If this is (close to) 0, it means that the
-                // direction is perpendicular to the normal, and thus parallel
-                // to the plane.
-                if (n * direction).abs() < 0.001 {
-                    continue;
-                }
-
-                // Calculate d in the plane equation
-                // (in linear form: ax + by + cz + d = 0)
-                let d = n * v0 * -1.0;
-                let t = -(n * origin + d) / (n * direction);
-                // Check if the triangle is behind the camera's ImagePlane
-                if t < 1.0 {
-                    continue;
-                }
-                if t < t_min {
-                    // Check if the intersection between the ray and the plane is
-                    // actually inside the triangle.
-                    let p = origin + direction * t;
-                    // i is the inward-facing vector
-                    let mut i: Vec3;
-
-                    // First edge:
-
-                    let edge0 = v1 - v0;
-                    i = n.cross_product(edge0);
-                    let v0p = p - v0;
-                    if i * v0p < 0.0 {
-                        continue;
-                    }
-                    // Second edge:
-                    let edge1 = v2 - v1;
-                    i = n.cross_product(edge1);
-                    let v1p = p - v1;
-                    if i * v1p < 0.0 {
-                        continue;
-                    }
-                    // Third edge:
-                    let edge2 = v0 - v2;
-                    i = n.cross_product(edge2);
-                    let v2p = p - v2;
-                    if i * v2p < 0.0 {
-                        continue;
-                    }
-                    // We've found an intersection!
-                    t_min = t;
-                    result = Some((object, face, p));
+        for (index, instance) in world.instances.iter().enumerate() {
+            let mesh = match world.meshes.get(instance.mesh_id) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            // Intersect the shared mesh in the instance's local space. The
+            // direction isn't normalized, so the t values (and `t_range`)
+            // are the same in both spaces.
+            let local_origin = instance.transform.inverse_point(origin);
+            let local_direction = instance.transform.inverse_direction(direction);
+            if let Some(face_hit) = self.get_nearest_intersecting_triangle(
+                [(
+                    mesh,
+                    world.mesh_faces(instance.mesh_id),
+                    world.mesh_bounding_sphere(instance.mesh_id),
+                )],
+                local_origin,
+                local_direction,
+                t_range,
+            ) {
+                if face_hit.t < t_min {
+                    t_min = face_hit.t;
+                    let local_hit = face_hit.to_hit(world, instance.material, self.texture_filter);
+                    let normal = instance.transform.apply_to_normal(local_hit.normal);
+                    let hit = Hit {
+                        point: instance.transform.apply_to_point(local_hit.point),
+                        normal,
+                        outward_normal: normal,
+                        ..local_hit
+                    };
+                    result = Some((ObjectHandle::Instance(index), hit));
                 }
             }
         }
         result
     }
 
-    // TODO: add a "t value constraint" argument
-    fn get_nearest_intersecting_ball<'a>(
+    /// Get the nearest hit of a ray on any object in the world, together with
+    /// the handle of the object that was hit. Only objects for which
+    /// `include` returns true are considered. On a tie, balls, boxes and
+    /// planes win over triangles.
+    fn get_nearest_hit(
         &self,
-        balls: &'a [Ball],
+        world: &World,
         origin: Vec3,
         direction: Vec3,
-    ) -> Option<(&'a Ball, Vec3)> {
-        let mut result_ball = None;
+        t_range: RealRange,
+        include: impl Fn(ObjectHandle) -> bool,
+    ) -> Option<(ObjectHandle, Hit)> {
+        let primitives = world
+            .primitives()
+            .filter(|(handle, _)| include(*handle))
+            .filter_map(|(handle, primitive)| {
+                Some((handle, primitive.intersect(origin, direction, t_range)?))
+            });
+        let vertex_object = self
+            .get_nearest_intersecting_vertex_object(world, origin, direction, t_range)
+            .filter(|(handle, _)| include(*handle));
+        let instance = self
+            .get_nearest_intersecting_instance(world, origin, direction, t_range)
+            .filter(|(handle, _)| include(*handle));
+        primitives
+            .chain(vertex_object)
+            .chain(instance)
+            .min_by(|(_, a), (_, b)| a.t.total_cmp(&b.t))
+    }
 
-        let mut t_min: f32 = f32::MAX;
-        for ball in balls {
-            let center_adj = origin - ball.pos;
+    /// Check whether an object is drawn, according to `render_balls`,
+    /// `render_triangles`, `render_planes` and `render_boxes`.
+    fn is_rendered(&self, handle: ObjectHandle) -> bool {
+        match handle {
+            ObjectHandle::Ball(_) => self.render_balls,
+            ObjectHandle::VertexObject(_) | ObjectHandle::Instance(_) => self.render_triangles,
+            ObjectHandle::Plane(_) => self.render_planes,
+            ObjectHandle::Box(_) => self.render_boxes,
+        }
+    }
 
-            // Apply the quadratic equation:
-            let a: f32 = {
-                let dir_len = direction.len();
-                dir_len * dir_len
-            };
-            let b: f32 = center_adj * direction * 2.0;
-            let c: f32 = center_adj.len() * center_adj.len() - ball.rad * ball.rad;
-            let d: f32 = b * b - 4.0 * a * c;
-            match d {
-                x if x < 0.0 => {
-                    // No intersections, move onto the next ball.
-                }
-                x if x == 0.0 => {
-                    let t = -b / 2.0 * a;
-                    // t = 1 is exactly on the image plane, so any values t < 1
-                    // are intersections that are in front of the plane instead
-                    // of behind it
-                    if t < t_min && t >= 1.0 {
-                        t_min = t;
-                        result_ball = Some(ball);
-                    }
+    /// Check whether any object lies between a point and a light, where
+    /// `to_light` is the vector from the point to the light.
+    fn is_in_shadow(&self, world: &World, origin: Vec3, to_light: Vec3) -> bool {
+        self.is_occluded(
+            world,
+            origin,
+            to_light,
+            RealRange::Open(0.0, to_light.len()),
+        )
+    }
+
+    /// Check whether a ray hits any object in the world at a distance within
+    /// the allowed range.
+    fn is_occluded(
+        &self,
+        world: &World,
+        origin: Vec3,
+        direction: Vec3,
+        t_allowed_range: RealRange,
+    ) -> bool {
+        let direction = direction.normalized();
+        if world.primitives().any(|(_, primitive)| {
+            primitive
+                .intersect(origin, direction, t_allowed_range)
+                .is_some()
+        }) {
+            return true;
+        }
+        let occludes = |triangle| {
+            Self::intersect_triangle(triangle, origin, direction, t_allowed_range).map(|(t, _)| t)
+        };
+        match world.triangle_bvh().filter(|_| self.use_bvh) {
+            Some(bvh) => {
+                if bvh
+                    .nearest(origin, direction, 0.0, |triangle| occludes(triangle.face))
+                    .is_some()
+                {
+                    return true;
                 }
-                x if x > 0.0 => {
-                    let t1 = (-b + d.sqrt()) / (2.0 * a);
-                    let t2 = (-b - d.sqrt()) / (2.0 * a);
-                    if (t1 < t_min && t1 >= 1.0) || (t2 < t_min && t2 >= 1.0) {
-                        // Take the smallest t value.
-                        let t = t1.min(t2);
-                        if t >= 1.0 {
-                            t_min = t;
-                            result_ball = Some(ball);
+            }
+            None => {
+                for index in 0..world.vertex_objects.len() {
+                    for &triangle in world.vertex_object_faces(index).iter() {
+                        if occludes(triangle).is_some() {
+                            return true;
                         }
                     }
                 }
-                _ => {}
             }
         }
-        if let Some(ball) = result_ball {
-            let p = origin + direction * t_min;
-            Some((ball, p))
-        } else {
-            None
-        }
-    }
-
-    fn is_in_shadow(&self, world: &World, origin: Vec3, direction: Vec3) -> bool {
-        let direction = direction.normalized();
-        let t_allowed_range = RealRange::LargerThan(0.0001);
-        for ball in &world.balls {
-            // The point must be outside the sphere, so t > ball_radius (if
-            // the object is indeed a ball).
-            if let Some(_t) =
-                self.find_ray_ball_intersection(&ball, origin, direction, t_allowed_range)
-            {
-                return true;
+        for instance in &world.instances {
+            if instance.mesh_id >= world.meshes.len() {
+                continue;
             }
-        }
-        for vertex_object in &world.vertex_objects {
-            for triangle in vertex_object.iter_faces() {
-                if let Some(_t) = self.find_ray_triangle_intersection(
-                    vertex_object.pos,
+            let local_origin = instance.transform.inverse_point(origin);
+            let local_direction = instance.transform.inverse_direction(direction);
+            for &triangle in world.mesh_faces(instance.mesh_id).iter() {
+                if let Some((t, _)) = Self::intersect_triangle(
                     triangle,
-                    origin,
-                    direction,
-                    t_allowed_range,
+                    local_origin,
+                    local_direction,
+                    RealRange::LargerThan(0.0),
                 ) {
-                    return true;
+                    // The local t value isn't a world space distance, so
+                    // transform the hit back before checking the range.
+                    let local_hit = local_origin + local_direction * t;
+                    let hit = instance.transform.apply_to_point(local_hit);
+                    if t_allowed_range.contains((hit - origin).len()) {
+                        return true;
+                    }
                 }
             }
         }
         false
     }
 
-    fn get_light_color<T: Object>(
+    /// Estimate the fraction of the hemisphere around `normal` that is not
+    /// occluded by any object within `max_distance` of `point`, ranging from
+    /// 0.0 (fully occluded) to 1.0 (fully open). A fixed, cosine-weighted set
+    /// of sample directions is used, so the result is deterministic.
+    pub fn ambient_occlusion(
         &self,
-        object: &T,
         world: &World,
-        camera: &Camera,
-        hit_location: Vec3,
-        surface_normal: Vec3,
-    ) -> RGBA8 {
-        let surface_normal = surface_normal.normalized();
+        point: Vec3,
+        normal: Vec3,
+        samples: usize,
+        max_distance: f32,
+    ) -> f32 {
+        if samples == 0 {
+            return 1.0;
+        }
+        let normal = normal.normalized();
+        // Build an orthonormal basis around the normal.
+        let helper = if normal.x.abs() < 0.9 {
+            vec3(1.0, 0.0, 0.0)
+        } else {
+            vec3(0.0, 1.0, 0.0)
+        };
+        let tangent = normal.cross_product(helper).normalized();
+        let bitangent = normal.cross_product(tangent);
+
+        let golden_angle = PI * (3.0 - 5f32.sqrt());
+        let t_allowed_range = RealRange::Open(0.0001, max_distance);
+        let mut unoccluded = 0;
+        for k in 0..samples {
+            // Points spread evenly over the unit disk, projected up onto the
+            // hemisphere, give cosine-weighted directions.
+            let u = (k as f32 + 0.5) / samples as f32;
+            let r = u.sqrt();
+            let phi = k as f32 * golden_angle;
+            let direction =
+                tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * (1.0 - u).sqrt();
+            if !self.is_occluded(world, point, direction, t_allowed_range) {
+                unoccluded += 1;
+            }
+        }
+        unoccluded as f32 / samples as f32
+    }
+
+    /// Bake ambient occlusion into the vertices of every vertex object in the
+    /// world, so that it doesn't need to be computed during rendering. The
+    /// result is stored in `VertexObject::vertex_ambient_occlusion`, and has
+    /// to be baked again when the scene changes. The number of samples per
+    /// vertex is `sampling.ao`.
+    pub fn bake_ambient_occlusion(&self, world: &mut World, max_distance: f32) {
+        let samples = self.sampling.ao as usize;
+        let baked: Vec<Vec<f32>> = world
+            .vertex_objects
+            .iter()
+            .map(|object| {
+                object
+                    .vertices
+                    .iter()
+                    .zip(object.compute_vertex_normals())
+                    .map(|(vertex, normal)| {
+                        let point = *vertex + object.pos;
+                        self.ambient_occlusion(world, point, normal, samples, max_distance)
+                    })
+                    .collect()
+            })
+            .collect();
+        for (object, ambient_occlusion) in world.vertex_objects.iter_mut().zip(baked) {
+            object.vertex_ambient_occlusion = ambient_occlusion;
+        }
+    }
+
+    /// Get the (Phong) specular factor, given the cosine of the angle between
+    /// the reflected light and the view direction.
+    fn specular_factor(&self, dot_product_view: f32, shine: f32) -> f32 {
+        let factor = dot_product_view.powf(shine);
+        if self.energy_conserving_specular {
+            // Normalization factor of the Phong lobe (including the cosine
+            // term), which makes the lobe integrate to 1 over the hemisphere.
+            factor * (shine + 2.0) / (2.0 * PI)
+        } else {
+            factor
+        }
+    }
+
+    /// Get the color of a point lit by the world's lights, as seen from `eye`.
+    fn get_light_color(
+        &self,
+        material: Material,
+        world: &World,
+        eye: Vec3,
+        hit_location: Vec3,
+        surface_normal: Vec3,
+    ) -> Color {
+        let surface_normal = surface_normal.normalized();
 
-        let ambient_r = object.material().ambient_constant.r as usize; // * self.color.r as usize;
-        let ambient_g = object.material().ambient_constant.g as usize; // * self.color.g as usize;
-        let ambient_b = object.material().ambient_constant.b as usize; // * self.color.b as usize;
+        let color = material.color_at(hit_location);
+        let ambient_occlusion = material.ao_constant.unwrap_or(1.0);
+        let ambient = Color::from(color) * Color::from(world.ambient_light) * ambient_occlusion;
 
-        let mut diffuse_r: usize = 0;
-        let mut diffuse_g: usize = 0;
-        let mut diffuse_b: usize = 0;
+        // The reflectance per channel, from 0.0 to 1.0.
+        let diffuse_color = Color::from(material.diffuse_color.unwrap_or(color)) * (1.0 / 255.0);
+        let specular_color = material
+            .specular_color
+            .map_or(Color::new(1.0, 1.0, 1.0), |c| {
+                Color::from(c) * (1.0 / 255.0)
+            });
 
-        let mut specular_r: usize = 0;
-        let mut specular_g: usize = 0;
-        let mut specular_b: usize = 0;
+        let mut diffuse = Color::BLACK;
+        let mut specular = Color::BLACK;
 
-        for light in &world.lights {
-            if !self.is_in_shadow(world, hit_location, light.pos - object.pos()) {
-                let p_to_light_normal = (light.pos - object.pos()).normalized();
-                let dot_product = p_to_light_normal * surface_normal;
+        let point_lights = world.lights.iter().map(|light| (light, 1.0));
+        let spot_lights = world
+            .spot_lights
+            .iter()
+            .map(|spot_light| (&spot_light.light, spot_light.cone_factor(hit_location)));
+        for (light, strength) in point_lights.chain(spot_lights) {
+            if strength <= 0.0 {
+                continue;
+            }
+            let shadow_ray_origin = hit_location + surface_normal * SHADOW_RAY_OFFSET;
+            if !light.casts_shadows
+                || !self.is_in_shadow(world, shadow_ray_origin, light.pos - shadow_ray_origin)
+            {
+                let p_to_light_normal = (light.pos - hit_location).normalized();
+                let dot_product = p_to_light_normal.dot(surface_normal);
                 if dot_product > 0.0 {
-                    let distance_to_light = (light.pos - object.pos()).len();
+                    let distance_to_light = (light.pos - hit_location).len();
                     let d_sq = distance_to_light * distance_to_light;
                     // Diffuse:
-                    diffuse_r += (dot_product
-                        * object.material().diffuse_constant
-                        * light.diffuse_intensity.r
-                        / d_sq) as usize;
-                    diffuse_g += (dot_product
-                        * object.material().diffuse_constant
-                        * light.diffuse_intensity.g
-                        / d_sq) as usize;
-                    diffuse_b += (dot_product
-                        * object.material().diffuse_constant
-                        * light.diffuse_intensity.b
-                        / d_sq) as usize;
+                    diffuse += Color::from(light.diffuse_intensity)
+                        * diffuse_color
+                        * (strength * dot_product * material.diffuse_constant / d_sq);
 
                     // Specular:
-                    let reflectance_vector =
-                        ((surface_normal * 2.0 * dot_product) - p_to_light_normal).normalized();
-                    let view_vector = (camera.pos - object.pos()).normalized();
-                    let dot_product_view = reflectance_vector * view_vector;
-                    let specular_factor = dot_product_view.powf(object.material().shine);
+                    let reflectance_vector = (-p_to_light_normal).reflect(surface_normal);
+                    let view_vector = (eye - hit_location).normalized();
+                    let dot_product_view = reflectance_vector.dot(view_vector);
+                    let specular_factor = self.specular_factor(dot_product_view, material.shine);
                     if dot_product_view >= 0.0 {
-                        specular_r += (light.specular_intensity.r
-                            * object.material().specular_constant
-                            * specular_factor
-                            / d_sq) as usize;
-                        specular_g += (light.specular_intensity.g
-                            * object.material().specular_constant
-                            * specular_factor
-                            / d_sq) as usize;
-                        specular_b += (light.specular_intensity.b
-                            * object.material().specular_constant
-                            * specular_factor
-                            / d_sq) as usize;
+                        specular += Color::from(light.specular_intensity)
+                            * specular_color
+                            * (strength * material.specular_constant * specular_factor / d_sq);
                     }
                 }
             }
         }
 
-        let r = ambient_r / 3 + diffuse_r / 3 + specular_r / 3;
-        let g = ambient_g / 3 + diffuse_g / 3 + specular_g / 3;
-        let b = ambient_b / 3 + diffuse_b / 3 + specular_b / 3;
+        match self.render_mode {
+            RenderMode::Shaded | RenderMode::Depth | RenderMode::Normals => {
+                ambient + diffuse + specular
+            }
+            RenderMode::DiffuseOnly => diffuse,
+            RenderMode::SpecularOnly => specular,
+        }
+    }
+}
+
+/// Where a ray hits an object.
+#[derive(Clone, Copy)]
+pub struct Hit {
+    /// The ray's t value at the hit, in units of the ray's direction.
+    pub t: f32,
+    pub point: Vec3,
+    /// The surface normal used for lighting. It points into the object when
+    /// looking at the inside of a ball.
+    pub normal: Vec3,
+    /// The surface normal pointing out of the object, to tell whether a ray
+    /// enters or leaves it.
+    pub outward_normal: Vec3,
+    pub material: Material,
+}
+
+/// Where a ray hits a triangle face of a vertex object, before the normal and
+/// material at the hit are looked up.
+struct FaceHit<'a> {
+    object: &'a VertexObject,
+    /// The index of `object` among the objects that were searched.
+    object_index: usize,
+    face: TriangleFace,
+    face_index: usize,
+    t: f32,
+    point: Vec3,
+    barycentric: Barycentric,
+}
+
+impl FaceHit<'_> {
+    /// Look up the (interpolated) normal and the (textured) material at the
+    /// hit. `material` replaces the object's own material, if given.
+    fn to_hit(&self, world: &World, material: Option<Material>, filter: TextureFilter) -> Hit {
+        let (object, face_index, barycentric) = (self.object, self.face_index, self.barycentric);
+        let normal = object
+            .normal_at(face_index, barycentric)
+            .unwrap_or_else(|| get_triangle_normal(self.face));
+        let material = material.unwrap_or_else(|| object.material_at(face_index, barycentric));
+        Hit {
+            t: self.t,
+            point: self.point,
+            normal,
+            outward_normal: normal,
+            material: textured(
+                world,
+                material,
+                object.uv_at(face_index, barycentric),
+                filter,
+            ),
+        }
+    }
+}
+
+/// Shapes that a ray can be intersected with on their own.
+pub trait Intersect {
+    /// Get the nearest hit of a ray with a t value within `t_range`.
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_range: RealRange) -> Option<Hit>;
+}
+
+impl Intersect for Primitive<'_> {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_range: RealRange) -> Option<Hit> {
+        match self {
+            Primitive::Ball(ball) => ball.intersect(origin, direction, t_range),
+            Primitive::Box(boxaabb) => boxaabb.intersect(origin, direction, t_range),
+            Primitive::Plane(plane) => plane.intersect(origin, direction, t_range),
+        }
+    }
+}
+
+impl Intersect for Ball {
+    /// The normal points outward, unless the ray starts inside the ball, in
+    /// which case it points inward, against the ray.
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_range: RealRange) -> Option<Hit> {
+        let center_adj = origin - self.pos;
+        // Apply the quadratic equation:
+        let a: f32 = direction.dot(direction);
+        let b: f32 = 2.0 * center_adj.dot(direction);
+        let c: f32 = center_adj.dot(center_adj) - self.rad * self.rad;
+        let d: f32 = b * b - 4.0 * a * c;
+        let t = match d {
+            x if x < 0.0 => return None,
+            x if x == 0.0 => -b / (2.0 * a),
+            _ => {
+                let t1 = (-b + d.sqrt()) / (2.0 * a);
+                let t2 = (-b - d.sqrt()) / (2.0 * a);
+                // Take the smallest valid t value. When the ray starts inside
+                // the ball, only the far intersection (t1) is valid.
+                if t_range.contains(t2) {
+                    t2
+                } else {
+                    t1
+                }
+            }
+        };
+        if !t_range.contains(t) {
+            return None;
+        }
+        let point = origin + direction * t;
+        let outward_normal = (point - self.pos).normalized();
+        let normal = if center_adj.len() < self.rad {
+            -outward_normal
+        } else {
+            outward_normal
+        };
+        Some(Hit {
+            t,
+            point,
+            normal,
+            outward_normal,
+            material: self.material,
+        })
+    }
+}
+
+impl Intersect for Plane {
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_range: RealRange) -> Option<Hit> {
+        let normal = self.normal.normalized();
+        let denominator = normal.dot(direction);
+        if denominator.abs() < PERPENDICULARITY_EPSILON {
+            return None;
+        }
+        let t = (self.point - origin).dot(normal) / denominator;
+        if !t_range.contains(t) {
+            return None;
+        }
+        Some(Hit {
+            t,
+            point: origin + direction * t,
+            normal: self.normal,
+            outward_normal: self.normal,
+            material: self.material,
+        })
+    }
+}
+
+impl Intersect for BoxAABB {
+    /// Uses the slab method. The normal is the outward normal of the side
+    /// that was hit: where the ray enters the box, or where it leaves the box
+    /// when it enters before `t_range`.
+    fn intersect(&self, origin: Vec3, direction: Vec3, t_range: RealRange) -> Option<Hit> {
+        let axes = [
+            (
+                origin.x,
+                direction.x,
+                self.min.x,
+                self.max.x,
+                vec3(1.0, 0.0, 0.0),
+            ),
+            (
+                origin.y,
+                direction.y,
+                self.min.y,
+                self.max.y,
+                vec3(0.0, 1.0, 0.0),
+            ),
+            (
+                origin.z,
+                direction.z,
+                self.min.z,
+                self.max.z,
+                vec3(0.0, 0.0, 1.0),
+            ),
+        ];
+        let (mut t_enter, mut enter_normal) = (f32::NEG_INFINITY, Vec3::default());
+        let (mut t_exit, mut exit_normal) = (f32::INFINITY, Vec3::default());
+        for (o, d, min, max, axis) in axes {
+            if d == 0.0 {
+                // Parallel to the slab: the ray is either always or never
+                // between its sides.
+                if o < min || o > max {
+                    return None;
+                }
+                continue;
+            }
+            // The ray enters the slab through the side facing against it.
+            let (near, far) = if d > 0.0 { (min, max) } else { (max, min) };
+            let t_near = (near - o) / d;
+            let t_far = (far - o) / d;
+            if t_near > t_enter {
+                t_enter = t_near;
+                enter_normal = axis * -d.signum();
+            }
+            if t_far < t_exit {
+                t_exit = t_far;
+                exit_normal = axis * d.signum();
+            }
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+        let (t, normal) = if t_range.contains(t_enter) {
+            (t_enter, enter_normal)
+        } else if t_range.contains(t_exit) {
+            (t_exit, exit_normal)
+        } else {
+            return None;
+        };
+        Some(Hit {
+            t,
+            point: origin + direction * t,
+            normal,
+            outward_normal: normal,
+            material: self.material,
+        })
+    }
+}
+
+/// Use the color of the material's texture at (u, v) as its diffuse color, if
+/// it has a texture and the hit has texture coordinates.
+fn textured(
+    world: &World,
+    mut material: Material,
+    uv: Option<(f32, f32)>,
+    filter: TextureFilter,
+) -> Material {
+    if let Some(color) = world.texture_color(material.texture, uv, filter) {
+        material.diffuse_color = Some(color);
+    }
+    material
+}
+
+/// Get the indices of the pixels in an RGBA frame buffer, with rows of `width`
+/// pixels, whose luma differs by more than `threshold` from the pixel to
+/// their right or below them. Both pixels of such a pair are included.
+fn find_edge_pixels(frame_buffer: &[u8], width: usize, threshold: f32) -> Vec<usize> {
+    let luma: Vec<f32> = frame_buffer
+        .chunks_exact(4)
+        .map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32)
+        .collect();
+    let mut is_edge = vec![false; luma.len()];
+    for i in 0..luma.len() {
+        let right = (i % width + 1 < width).then_some(i + 1);
+        let below = Some(i + width).filter(|&j| j < luma.len());
+        for j in right.into_iter().chain(below) {
+            if (luma[i] - luma[j]).abs() > threshold {
+                is_edge[i] = true;
+                is_edge[j] = true;
+            }
+        }
+    }
+    (0..luma.len()).filter(|&i| is_edge[i]).collect()
+}
+
+#[test]
+fn test_spherical_projection_is_equidistant() {
+    let fov_deg = 170.0;
+    let mut camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        fov_deg,
+        Resolution { w: 8, h: 4 },
+    )
+    .unwrap();
+    let view_direction = camera.get_view_direction();
+    let angle_to_view = |ray: Vec3| ray.normalized().dot(view_direction).acos();
+    // The pixel a quarter of the way into the middle row.
+    let pixel_index = 2 * 8 + 2;
+    let expected_angle = fov_deg.to_radians() / 4.0;
+
+    // The planar projection stretches the edges, so this pixel is much further
+    // to the side than a quarter of the field of view.
+    let planar_angle = angle_to_view(Renderer::calculate_pixel_ray(&camera, pixel_index));
+    assert!(planar_angle > expected_angle * 1.5);
+
+    camera.projection_model = ProjectionModel::Spherical;
+    let spherical_angle = angle_to_view(Renderer::calculate_pixel_ray(&camera, pixel_index));
+    assert!((spherical_angle - expected_angle).abs() < 1e-4);
+}
+
+#[test]
+fn test_baked_ambient_occlusion() {
+    let mut world = World::new();
+    // A floor quad with a ball resting just above one of its corners.
+    world.vertex_objects.push(VertexObject {
+        vertices: vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(0.0, 0.0, 4.0),
+            vec3(4.0, 0.0, 4.0),
+            vec3(4.0, 0.0, 0.0),
+        ],
+        faces: vec![(0, 1, 2), (0, 2, 3)],
+        ..Default::default()
+    });
+    world.balls.push(Ball {
+        pos: vec3(0.0, 1.0, 0.0),
+        rad: 0.9,
+        material: Material::default(),
+    });
+    let samples = 256;
+    let renderer = Renderer {
+        sampling: SamplingConfig {
+            ao: samples as u32,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let max_distance = 2.0;
+    renderer.bake_ambient_occlusion(&mut world, max_distance);
+    let baked = &world.vertex_objects[0].vertex_ambient_occlusion;
+    assert_eq!(baked.len(), 4);
+    // The corner under the ball is occluded, the opposite corner isn't.
+    assert!(baked[0] < 0.5);
+    assert_eq!(baked[2], 1.0);
+
+    // The baked values match the live result for the same points.
+    let up = vec3(0.0, 1.0, 0.0);
+    for (vertex, baked) in world.vertex_objects[0].vertices.iter().zip(baked) {
+        let live = renderer.ambient_occlusion(&world, *vertex, up, samples, max_distance);
+        assert!((live - baked).abs() < 0.01);
+    }
+
+    // Interpolating the baked values gives the ambient occlusion for a hit.
+    let material = world.vertex_objects[0].material_at(0, (0.5, 0.0, 0.5));
+    let expected = (baked[0] + baked[2]) / 2.0;
+    assert!((material.ao_constant.unwrap() - expected).abs() < 1e-6);
+}
+
+#[cfg(test)]
+/// The background color of the worlds in tests.
+const TEST_BACKGROUND: RGBA8 = RGBA8::new(10, 20, 30, 255);
+
+#[cfg(test)]
+/// A small scene with a floor, a ball and a light, for use in tests.
+fn test_scene() -> (World, Camera) {
+    let mut world = World::new();
+    world.background = crate::world::Background::Solid(TEST_BACKGROUND);
+    world.vertex_objects.push(VertexObject {
+        vertices: vec![
+            vec3(-10.0, 0.0, -10.0),
+            vec3(-10.0, 0.0, 10.0),
+            vec3(10.0, 0.0, 10.0),
+            vec3(10.0, 0.0, -10.0),
+        ],
+        faces: vec![(0, 1, 2), (0, 2, 3)],
+        material: Material {
+            ambient_constant: RGBA8::new(255, 255, 255, 255),
+            diffuse_constant: 35.0,
+            specular_constant: 10.0,
+            shine: 7.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    world.balls.push(Ball {
+        pos: vec3(0.0, 1.0, -3.0),
+        rad: 1.0,
+        material: Material {
+            ambient_constant: RGBA8::new(255, 0, 0, 255),
+            diffuse_constant: 50.0,
+            specular_constant: 20.0,
+            shine: 7.0,
+            ..Default::default()
+        },
+    });
+    let intensity = LightIntensity::new(120.0, 120.0, 120.0);
+    world.lights.push(Light {
+        pos: vec3(2.0, 4.0, 0.0),
+        diffuse_intensity: intensity,
+        specular_intensity: intensity,
+        casts_shadows: true,
+    });
+    let camera = Camera::new(
+        vec3(0.0, 1.5, 2.0),
+        vec3(0.0, -0.2, -1.0),
+        90.0,
+        Resolution { w: 64, h: 48 },
+    )
+    .unwrap();
+    (world, camera)
+}
+
+#[test]
+fn test_render_to_vec() {
+    let mut world = World::new();
+    world.background = crate::world::Background::Solid(TEST_BACKGROUND);
+    world.balls.push(Ball {
+        pos: vec3(0.0, 0.0, -5.0),
+        rad: 1.0,
+        material: Material {
+            ambient_constant: RGBA8::new(255, 0, 0, 255),
+            ..Default::default()
+        },
+    });
+    let camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 40, h: 30 },
+    )
+    .unwrap();
+    let renderer = Renderer {
+        gamma: 1.0,
+        ..Default::default()
+    };
+    let frame = renderer.render_to_vec(&world, &camera);
+    assert_eq!(frame.len(), 40 * 30 * 4);
+
+    let layout = renderer.frame_layout(&camera);
+    let pixel_at = |x, y| {
+        let i = layout.pixel_offset(x, y);
+        RGBA8::new(frame[i], frame[i + 1], frame[i + 2], frame[i + 3])
+    };
+    assert_ne!(pixel_at(20, 15), TEST_BACKGROUND);
+    assert_eq!(pixel_at(0, 0), TEST_BACKGROUND);
+}
+
+#[test]
+fn test_multithreading_methods_render_identically() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Renderer>();
+    assert_send_sync::<World>();
+    assert_send_sync::<Camera>();
+
+    let (mut world, camera) = test_scene();
+    world.balls[0].material.reflectivity = 0.5;
+    let render = |multithreading_method| {
+        let renderer = Renderer {
+            multithreading_method,
+            sampling: SamplingConfig {
+                seed: 7,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        renderer.render_to_vec(&world, &camera)
+    };
+    let reference = render(MultithreadingMethod::None);
+    assert_eq!(reference, render(MultithreadingMethod::Rayon));
+    assert_eq!(reference, render(MultithreadingMethod::Crossbeam));
+    // Tiles that don't divide the 64x48 frame evenly, and single pixel tiles.
+    assert_eq!(
+        reference,
+        render(MultithreadingMethod::Tiled { tile_size: 10 })
+    );
+    assert_eq!(
+        reference,
+        render(MultithreadingMethod::Tiled { tile_size: 1 })
+    );
+}
+
+#[test]
+fn test_find_edge_pixels() {
+    // A 3x2 frame, black but for a white pixel in the top right.
+    let mut frame = [0; 3 * 2 * 4];
+    frame[8..12].copy_from_slice(&[255, 255, 255, 255]);
+    assert_eq!(find_edge_pixels(&frame, 3, 10.0), vec![1, 2, 5]);
+    assert!(find_edge_pixels(&frame, 3, 255.0).is_empty());
+}
+
+#[test]
+fn test_adaptive_anti_aliasing() {
+    let (world, camera) = test_scene();
+    let sampling = SamplingConfig {
+        seed: 7,
+        aa: 4,
+        ..Default::default()
+    };
+    let single_sample = Renderer {
+        sampling: SamplingConfig { aa: 1, ..sampling },
+        ..Default::default()
+    }
+    .render_to_vec(&world, &camera);
+    let render = |multithreading_method| {
+        Renderer {
+            sampling,
+            adaptive_aa_threshold: Some(10.0),
+            multithreading_method,
+            ..Default::default()
+        }
+        .render_to_vec(&world, &camera)
+    };
+    let adaptive = render(MultithreadingMethod::Rayon);
+    assert_eq!(adaptive, render(MultithreadingMethod::None));
+
+    // Only the edges are sampled again, and they are smoothed.
+    let edges = find_edge_pixels(&single_sample, 64, 10.0);
+    assert!(!edges.is_empty() && edges.len() < 64 * 48 / 4);
+    for (i, (a, b)) in adaptive
+        .chunks_exact(4)
+        .zip(single_sample.chunks_exact(4))
+        .enumerate()
+    {
+        if !edges.contains(&i) {
+            assert_eq!(a, b);
+        }
+    }
+    assert!(edges
+        .iter()
+        .any(|&i| adaptive[4 * i..4 * i + 4] != single_sample[4 * i..4 * i + 4]));
+}
+
+#[test]
+fn test_only_reflective_pixels_cast_secondary_rays() {
+    let (mut world, camera) = test_scene();
+    world.balls[0].material.reflectivity = 0.5;
+    let renderer = Renderer::default();
+    let (mut reflective_pixels, mut secondary_rays) = (0, 0);
+    let pixel_count = (camera.resolution.w * camera.resolution.h) as usize;
+    for i in 0..pixel_count {
+        let stats = renderer.pixel_ray_stats(&world, &camera, i);
+        assert_eq!(stats.camera_rays, 1);
+        let (x, y) = (
+            i as u32 % camera.resolution.w,
+            i as u32 / camera.resolution.w,
+        );
+        if renderer.pick(&world, &camera, x, y) == Some(ObjectHandle::Ball(0)) {
+            reflective_pixels += 1;
+            assert!(stats.secondary_rays > 0);
+        } else {
+            assert_eq!(stats.secondary_rays, 0);
+        }
+        secondary_rays += stats.secondary_rays;
+    }
+    assert!(reflective_pixels > 0);
+    let stats = renderer.ray_stats(&world, &camera);
+    assert_eq!(stats.camera_rays, pixel_count as u64);
+    assert_eq!(stats.secondary_rays, secondary_rays);
+}
+
+#[test]
+fn test_thread_count() {
+    let renderer = Renderer {
+        thread_count: Some(3),
+        ..Default::default()
+    };
+    assert_eq!(renderer.in_thread_pool(rayon::current_num_threads), 3);
+
+    let (world, camera) = test_scene();
+    let reference = Renderer::default().render_to_vec(&world, &camera);
+    assert_eq!(renderer.render_to_vec(&world, &camera), reference);
+
+    // Rendering in bands within a time budget gives the same image, on the
+    // pool as well as on the Crossbeam method's threads.
+    for multithreading_method in [MultithreadingMethod::Rayon, MultithreadingMethod::Crossbeam] {
+        let renderer = Renderer {
+            multithreading_method,
+            time_budget_ms: Some(u64::MAX),
+            ..renderer.clone()
+        };
+        let mut frame = vec![0; renderer.frame_layout(&camera).buffer_len()];
+        let mut next_row = 0;
+        renderer.render_world_within_budget(&world, &camera, &mut frame, &mut next_row);
+        assert_eq!(frame, reference);
+    }
+}
+
+#[test]
+fn test_phong_light_color() {
+    let mut world = World::new();
+    let intensity = LightIntensity::new(100.0, 100.0, 100.0);
+    world.lights.push(Light {
+        pos: vec3(0.0, 3.0, 0.0),
+        diffuse_intensity: intensity,
+        specular_intensity: intensity,
+        casts_shadows: true,
+    });
+    let mut material = Material {
+        ambient_constant: RGBA8::new(20, 40, 60, 255),
+        diffuse_constant: 2.0,
+        specular_constant: 4.0,
+        diffuse_color: Some(RGBA8::new(255, 255, 255, 255)),
+        shine: 7.0,
+        ..Default::default()
+    };
+    let renderer = Renderer::default();
+    // The top of a ball, with the light 2 units straight above it and the eye
+    // in line with the reflected light, so both cosines are 1:
+    // ambient + 100 * 2 / 2^2 (diffuse) + 100 * 4 / 2^2 (specular).
+    let top = vec3(0.0, 1.0, 0.0);
+    let eye = vec3(0.0, 5.0, 0.0);
+    let color = |material| renderer.get_light_color(material, &world, eye, top, top);
+    assert_eq!(color(material), Color::new(170.0, 190.0, 210.0));
+
+    // Overexposed colors are clamped to 255.
+    material.ambient_constant = RGBA8::new(200, 40, 60, 255);
+    assert_eq!(
+        renderer.tone_map(color(material)),
+        Color::new(255.0, 190.0, 210.0)
+    );
+}
+
+#[test]
+fn test_colored_diffuse_and_specular() {
+    let mut world = World::new();
+    world.ambient_light = LightIntensity::new(0.0, 0.0, 0.0);
+    let intensity = LightIntensity::new(100.0, 100.0, 100.0);
+    world.lights.push(Light {
+        pos: vec3(0.0, 3.0, 0.0),
+        diffuse_intensity: intensity,
+        specular_intensity: intensity,
+        casts_shadows: true,
+    });
+    let renderer = Renderer::default();
+    let top = vec3(0.0, 1.0, 0.0);
+    let color = |material| renderer.get_light_color(material, &world, top * 5.0, top, top);
+
+    // Without a diffuse color, a green material reflects white light as green.
+    let green = Material {
+        ambient_constant: RGBA8::new(0, 255, 0, 255),
+        diffuse_constant: 2.0,
+        specular_constant: 0.0,
+        ..Default::default()
+    };
+    assert_eq!(color(green), Color::new(0.0, 50.0, 0.0));
+
+    // Both colors tint the light per channel.
+    let tinted = Material {
+        diffuse_color: Some(RGBA8::new(255, 0, 0, 255)),
+        specular_constant: 4.0,
+        specular_color: Some(RGBA8::new(0, 0, 255, 255)),
+        ..green
+    };
+    assert_eq!(color(tinted), Color::new(50.0, 0.0, 100.0));
+}
+
+#[test]
+fn test_world_ambient_light() {
+    let mut world = World::new();
+    let material = Material {
+        ambient_constant: RGBA8::new(20, 40, 60, 255),
+        ..Default::default()
+    };
+    let renderer = Renderer::default();
+    let top = vec3(0.0, 1.0, 0.0);
+    let mut color = |ambient_light| {
+        world.ambient_light = ambient_light;
+        renderer.get_light_color(material, &world, top * 5.0, top, top)
+    };
+    assert_eq!(
+        color(LightIntensity::new(1.0, 1.0, 1.0)),
+        Color::new(20.0, 40.0, 60.0)
+    );
+    assert_eq!(
+        color(LightIntensity::new(0.5, 0.5, 2.0)),
+        Color::new(10.0, 20.0, 120.0)
+    );
+    assert_eq!(color(LightIntensity::new(0.0, 0.0, 0.0)), Color::BLACK);
+}
 
-        let r = min(r, 255) as u8;
-        let g = min(g, 255) as u8;
-        let b = min(b, 255) as u8;
-        RGBA8 { r, g, b, a: 255 }
+#[test]
+fn test_fog() {
+    // Two unlit red balls, one close to the camera and one far away, in
+    // white fog.
+    let mut world = World::new();
+    for pos in [vec3(-1.0, 0.0, -3.0), vec3(10.0, 0.0, -30.0)] {
+        world.balls.push(Ball {
+            pos,
+            rad: 1.0,
+            material: Material {
+                ambient_constant: RGBA8::new(255, 0, 0, 255),
+                ..Default::default()
+            },
+        });
+    }
+    world.fog = Some(crate::world::Fog {
+        color: RGBA8::new(255, 255, 255, 255),
+        density: 0.05,
+    });
+    let renderer = Renderer::default();
+    let origin = vec3(0.0, 0.0, 0.0);
+    let shade = |target: Vec3| {
+        renderer
+            .shade(
+                &world,
+                origin,
+                (target - origin).normalized(),
+                CAMERA_RAY_RANGE,
+                0,
+                &mut 0,
+            )
+            .unwrap()
+    };
+    let near = shade(world.balls[0].pos);
+    let far = shade(world.balls[1].pos);
+    // The fog turns the red towards white, more so far away.
+    assert!(near.g() > 0.0);
+    assert!(far.g() > near.g());
+    let distance = (world.balls[0].pos - origin).len() - 1.0;
+    let expected = 255.0 * world.fog.unwrap().amount(distance);
+    assert!((near.g() - expected).abs() < 1e-2);
+
+    // The background disappears in the fog.
+    let camera = Camera::new(origin, vec3(0.0, 1.0, 0.0), 90.0, Resolution { w: 4, h: 4 }).unwrap();
+    let frame = renderer.render_to_vec(&world, &camera);
+    assert!(frame.iter().all(|&c| c == 255));
+}
+
+#[test]
+fn test_skybox_reflection() {
+    // A skybox with a different color on every face, and a mirror ball in
+    // front of the camera.
+    let colors = [
+        RGBA8::new(255, 0, 0, 255),
+        RGBA8::new(0, 255, 0, 255),
+        RGBA8::new(0, 0, 255, 255),
+        RGBA8::new(255, 255, 0, 255),
+        RGBA8::new(0, 255, 255, 255),
+        RGBA8::new(255, 0, 255, 255),
+    ];
+    let mut world = World::new();
+    world.skybox = Some(Cubemap {
+        faces: colors.map(|color| crate::texture::Texture {
+            width: 1,
+            height: 1,
+            pixels: vec![color],
+        }),
+    });
+    world.balls.push(Ball {
+        pos: vec3(0.0, 0.0, -5.0),
+        rad: 1.0,
+        material: Material {
+            reflectivity: 1.0,
+            ..Default::default()
+        },
+    });
+    let camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        40.0,
+        Resolution { w: 41, h: 31 },
+    )
+    .unwrap();
+    let renderer = Renderer {
+        gamma: 1.0,
+        ..Default::default()
+    };
+    let frame = renderer.render_to_vec(&world, &camera);
+    let layout = renderer.frame_layout(&camera);
+    let pixel_at = |x, y| {
+        let i = layout.pixel_offset(x, y);
+        RGBA8::new(frame[i], frame[i + 1], frame[i + 2], frame[i + 3])
+    };
+    // Around the ball, the camera sees the -Z face. The middle of the ball
+    // reflects the sky behind the camera, the +Z face.
+    assert_eq!(pixel_at(0, 0), colors[5]);
+    assert_eq!(pixel_at(20, 15), colors[4]);
+}
+
+#[test]
+fn test_bilinear_skybox() {
+    // Every face is black on the left and white on the right, so nearest
+    // sampling only gives those two colors.
+    let face = crate::texture::Texture {
+        width: 2,
+        height: 1,
+        pixels: vec![RGBA8::new(0, 0, 0, 255), RGBA8::new(255, 255, 255, 255)],
+    };
+    let mut world = World::new();
+    world.skybox = Some(Cubemap {
+        faces: [(); 6].map(|_| face.clone()),
+    });
+    let camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        60.0,
+        Resolution { w: 16, h: 1 },
+    )
+    .unwrap();
+    let is_blended = |renderer: &Renderer| {
+        let frame = renderer.render_to_vec(&world, &camera);
+        frame.iter().any(|&c| c != 0 && c != 255)
+    };
+    let nearest = Renderer {
+        environment_filter: TextureFilter::Nearest,
+        ..Default::default()
+    };
+    assert!(!is_blended(&nearest));
+    assert!(is_blended(&Renderer::default()));
+
+    // Reflected rays look up the same filtered surroundings. Straight ahead
+    // lies halfway between the two pixels of the -Z face.
+    let direction = vec3(0.0, 0.0, -1.0);
+    assert_eq!(
+        nearest.surroundings(&world, direction),
+        Color::new(255.0, 255.0, 255.0)
+    );
+    let blended = Renderer::default().surroundings(&world, direction);
+    assert_eq!(blended, Color::from(RGBA8::new(128, 128, 128, 255)));
+}
+
+#[test]
+fn test_spot_light_cutoff() {
+    let mut world = World::new();
+    let intensity = LightIntensity::new(120.0, 120.0, 120.0);
+    world.spot_lights.push(SpotLight {
+        light: Light {
+            pos: vec3(0.0, 4.0, 0.0),
+            diffuse_intensity: intensity,
+            specular_intensity: intensity,
+            casts_shadows: true,
+        },
+        direction: vec3(0.0, -1.0, 0.0),
+        inner_angle: 10.0,
+        outer_angle: 20.0,
+    });
+    let material = Material {
+        diffuse_constant: 50.0,
+        ..Default::default()
+    };
+    let renderer = Renderer {
+        render_mode: RenderMode::DiffuseOnly,
+        ..Default::default()
+    };
+    let eye = vec3(0.0, 4.0, 4.0);
+    let up = vec3(0.0, 1.0, 0.0);
+    let lit = |x: f32| renderer.get_light_color(material, &world, eye, vec3(x, 0.0, 0.0), up);
+
+    // Straight below the light, a point on the floor is fully lit.
+    assert_ne!(lit(0.0), Color::BLACK);
+    // Just outside of the outer cone, at tan(20.5 deg) * 4 from the center,
+    // the light doesn't contribute anything.
+    let outside = 20.5_f32.to_radians().tan() * 4.0;
+    assert_eq!(lit(outside), Color::BLACK);
+}
+
+#[test]
+fn test_specular_only_render_mode() {
+    let mut world = World::new();
+    let intensity = LightIntensity::new(120.0, 120.0, 120.0);
+    world.lights.push(Light {
+        pos: vec3(1.0, 2.0, 0.0),
+        diffuse_intensity: intensity,
+        specular_intensity: intensity,
+        casts_shadows: true,
+    });
+    let ball = Ball {
+        pos: vec3(0.0, 0.0, 0.0),
+        rad: 1.0,
+        material: Material {
+            ambient_constant: RGBA8::new(255, 0, 0, 255),
+            diffuse_constant: 50.0,
+            specular_constant: 20.0,
+            shine: 7.0,
+            ..Default::default()
+        },
+    };
+    let color = |render_mode: RenderMode, camera_pos: Vec3| {
+        let renderer = Renderer {
+            render_mode,
+            ..Default::default()
+        };
+        let camera = Camera::new(
+            camera_pos,
+            ball.pos - camera_pos,
+            90.0,
+            Resolution { w: 4, h: 4 },
+        )
+        .unwrap();
+        let top = vec3(0.0, 1.0, 0.0);
+        renderer.get_light_color(ball.material, &world, camera.pos, top, top)
+    };
+    let black = Color::BLACK;
+    // The light reflects away from a camera on the same side as the light.
+    let same_side = vec3(5.0, 0.5, 0.0);
+    assert_eq!(color(RenderMode::SpecularOnly, same_side), black);
+    assert_ne!(color(RenderMode::DiffuseOnly, same_side), black);
+    // On the other side, the camera sees the highlight, but no ambient light.
+    let other_side = vec3(-5.0, 5.0, 0.0);
+    let specular = color(RenderMode::SpecularOnly, other_side);
+    assert!(specular.r() > 0.0 && specular.r() == specular.g());
+    let shaded = color(RenderMode::Shaded, other_side);
+    assert!(shaded.r() > specular.r() + color(RenderMode::DiffuseOnly, other_side).r());
+}
+
+#[test]
+fn test_depth_render_mode() {
+    let mut world = World::new();
+    world.background = crate::world::Background::Solid(TEST_BACKGROUND);
+    world.balls.push(Ball {
+        pos: vec3(0.0, 0.0, -5.0),
+        rad: 1.0,
+        material: Material::default(),
+    });
+    // An odd resolution, so the middle pixel looks straight ahead.
+    let camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 41, h: 31 },
+    )
+    .unwrap();
+    let renderer = Renderer {
+        render_mode: RenderMode::Depth,
+        depth_near: 2.0,
+        depth_far: 6.0,
+        ..Default::default()
+    };
+    let frame = renderer.render_to_vec(&world, &camera);
+    let layout = renderer.frame_layout(&camera);
+    let pixel_at = |x, y| {
+        let i = layout.pixel_offset(x, y);
+        RGBA8::new(frame[i], frame[i + 1], frame[i + 2], frame[i + 3])
+    };
+    // The front of the ball is at t = 4, halfway between near and far.
+    let center = pixel_at(20, 15);
+    assert!((127..=128).contains(&center.r), "{:?}", center);
+    assert!(center.r == center.g && center.g == center.b);
+    // Misses keep the background color, without gamma correction.
+    assert_eq!(pixel_at(0, 0), TEST_BACKGROUND);
+}
+
+#[test]
+fn test_normals_render_mode() {
+    // A quad facing +z, straight in front of the camera.
+    let mut world = World::new();
+    world.vertex_objects.push(VertexObject {
+        pos: vec3(0.0, 0.0, -3.0),
+        vertices: vec![
+            vec3(-2.0, -2.0, 0.0),
+            vec3(2.0, -2.0, 0.0),
+            vec3(2.0, 2.0, 0.0),
+            vec3(-2.0, 2.0, 0.0),
+        ],
+        faces: vec![(0, 1, 2), (0, 2, 3)],
+        ..Default::default()
+    });
+    world.cache_faces();
+    let camera = Camera::new(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 41, h: 31 },
+    )
+    .unwrap();
+    let renderer = Renderer {
+        render_mode: RenderMode::Normals,
+        ..Default::default()
+    };
+    let frame = renderer.render_to_vec(&world, &camera);
+    let i = renderer.frame_layout(&camera).pixel_offset(10, 10);
+    let close = |value: u8, expected: u8| (value as i32 - expected as i32).abs() <= 1;
+    assert!(close(frame[i], 128) && close(frame[i + 1], 128) && frame[i + 2] == 255);
+}
+
+#[test]
+fn test_tangent_ray_hits_ball_surface() {
+    let ball = Ball {
+        pos: vec3(0.0, 0.0, 0.0),
+        rad: 1.0,
+        material: Material::default(),
+    };
+    // A ray that just touches the ball at (1, 0, 0). The direction isn't
+    // normalized, so the quadratic's leading coefficient isn't 1.
+    let hit = ball
+        .intersect(vec3(1.0, 0.0, 5.0), vec3(0.0, 0.0, -2.0), CAMERA_RAY_RANGE)
+        .unwrap();
+    assert!((hit.point.len() - 1.0).abs() < 1e-6);
+    assert!((hit.point - vec3(1.0, 0.0, 0.0)).len() < 1e-6);
+}
+
+#[test]
+fn test_ball_hit_normal() {
+    let ball = Ball {
+        pos: vec3(0.0, 0.0, 0.0),
+        rad: 1.0,
+        material: Material::default(),
+    };
+    // From outside, the ray hits the front of the ball.
+    let hit = ball
+        .intersect(vec3(0.0, 0.0, 5.0), vec3(0.0, 0.0, -1.0), CAMERA_RAY_RANGE)
+        .unwrap();
+    assert!((hit.point - vec3(0.0, 0.0, 1.0)).len() < 1e-5);
+    assert!((hit.normal - vec3(0.0, 0.0, 1.0)).len() < 1e-5);
+    assert_eq!(hit.t, 4.0);
+    // From inside, the ray hits the back of the ball, and the normal points
+    // inward, while the outward normal doesn't.
+    let hit = ball
+        .intersect(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, -0.5), CAMERA_RAY_RANGE)
+        .unwrap();
+    assert!((hit.point - vec3(0.0, 0.0, -1.0)).len() < 1e-5);
+    assert!((hit.normal - vec3(0.0, 0.0, 1.0)).len() < 1e-5);
+    assert!((hit.outward_normal - vec3(0.0, 0.0, -1.0)).len() < 1e-5);
+}
+
+#[test]
+fn test_render_time_budget() {
+    let (world, camera) = test_scene();
+    let complete = Renderer::default().render_to_vec(&world, &camera);
+
+    // Without any time, every call renders a single band of rows, and the
+    // next call continues where the previous one stopped.
+    let renderer = Renderer {
+        time_budget_ms: Some(0),
+        ..Default::default()
+    };
+    let mut frame_buffer = vec![0; complete.len()];
+    let mut next_row = 0;
+    let mut calls = 0;
+    loop {
+        calls += 1;
+        let status =
+            renderer.render_world_within_budget(&world, &camera, &mut frame_buffer, &mut next_row);
+        if status == RenderStatus::Complete {
+            break;
+        }
+        assert_eq!(next_row, calls * BUDGET_BAND_ROWS);
+    }
+    assert_eq!(calls, camera.resolution.h.div_ceil(BUDGET_BAND_ROWS));
+    assert_eq!(next_row, 0);
+    assert!(frame_buffer == complete);
+}
+
+#[test]
+fn test_light_without_shadows() {
+    // A ball hangs between a light and the floor below it.
+    let mut world = World::new();
+    world.balls.push(Ball {
+        pos: vec3(0.0, 2.0, 0.0),
+        rad: 0.5,
+        material: Material::default(),
+    });
+    let intensity = LightIntensity::new(120.0, 120.0, 120.0);
+    world.lights.push(Light {
+        pos: vec3(0.0, 4.0, 0.0),
+        diffuse_intensity: intensity,
+        specular_intensity: intensity,
+        casts_shadows: true,
+    });
+    let floor = Ball {
+        pos: vec3(0.0, 0.0, 0.0),
+        rad: 0.0,
+        material: Material::default(),
+    };
+    let camera = Camera::new(
+        vec3(0.0, 1.0, 3.0),
+        vec3(0.0, -1.0, -3.0),
+        90.0,
+        Resolution { w: 4, h: 4 },
+    )
+    .unwrap();
+    let renderer = Renderer::default();
+    let up = vec3(0.0, 1.0, 0.0);
+    let floor_color =
+        |world: &World| renderer.get_light_color(floor.material, world, camera.pos, floor.pos, up);
+
+    let shadowed = floor_color(&world);
+    world.lights[0].casts_shadows = false;
+    let lit = floor_color(&world);
+    assert!(lit.r() > shadowed.r() * 2.0);
+}
+
+#[test]
+fn test_ball_shadow_on_floor() {
+    let (mut world, camera) = test_scene();
+    // Light the ball from straight above, so it casts a shadow on the floor
+    // right below it.
+    world.lights[0].pos = vec3(0.0, 6.0, -3.0);
+    let renderer = Renderer::default();
+    // Half of the direction to the floor point, which is in front of the image
+    // plane.
+    let floor_color = |point: Vec3| {
+        renderer
+            .shade(
+                &world,
+                camera.pos,
+                (point - camera.pos) * 0.5,
+                CAMERA_RAY_RANGE,
+                0,
+                &mut 0,
+            )
+            .unwrap()
+    };
+    let shadowed = floor_color(vec3(0.8, 0.0, -3.2));
+    let lit = floor_color(vec3(-3.0, 0.0, -3.2));
+    assert!(shadowed.r() < lit.r());
+    // In the shadow, only the ambient light is left.
+    assert_eq!(shadowed, Color::new(255.0, 255.0, 255.0));
+}
+
+#[test]
+fn test_reflective_ball() {
+    let (mut world, camera) = test_scene();
+    // A point on the lower front of the red ball, which faces away from the
+    // light, but towards the white floor.
+    let point = vec3(0.0, 0.3, -2.3);
+    let direction = (point - camera.pos) * 0.5;
+    let ball_color = |world: &World, renderer: &Renderer| {
+        renderer
+            .shade(world, camera.pos, direction, CAMERA_RAY_RANGE, 0, &mut 0)
+            .unwrap()
+    };
+    let renderer = Renderer::default();
+    assert_eq!(ball_color(&world, &renderer), Color::new(255.0, 0.0, 0.0));
+
+    // Once the ball is shiny, it mirrors the floor.
+    world.balls[0].material.reflectivity = 0.8;
+    let reflective = ball_color(&world, &renderer);
+    assert!(reflective.g() > 0.0 && reflective.r() > reflective.g());
+    // Without any bounces, there are no reflections.
+    let no_bounces = Renderer {
+        max_bounce_depth: 0,
+        ..Default::default()
+    };
+    assert_eq!(ball_color(&world, &no_bounces).g(), 0.0);
+}
+
+#[test]
+fn test_transparent_ball() {
+    // A glass ball in front of a green ball, without lights, so only the
+    // ambient colors are visible.
+    let mut world = World::new();
+    world.balls.push(Ball {
+        pos: vec3(0.0, 0.0, -3.0),
+        rad: 1.0,
+        material: Material {
+            ambient_constant: RGBA8::new(0, 0, 0, 255),
+            transparency: 1.0,
+            refractive_index: 1.0,
+            ..Default::default()
+        },
+    });
+    world.balls.push(Ball {
+        pos: vec3(0.0, 0.0, -8.0),
+        rad: 2.0,
+        material: Material {
+            ambient_constant: RGBA8::new(0, 255, 0, 255),
+            ..Default::default()
+        },
+    });
+    let renderer = Renderer::default();
+    let color = |world: &World| {
+        renderer
+            .shade(
+                world,
+                vec3(0.0, 0.0, 0.0),
+                vec3(0.0, 0.0, -1.0),
+                CAMERA_RAY_RANGE,
+                0,
+                &mut 0,
+            )
+            .unwrap()
+    };
+    let green = Color::new(0.0, 255.0, 0.0);
+    assert_eq!(color(&world), green);
+
+    // A glass ball acts like a lens. Just inside of its edge, it bends the
+    // ray past the green ball.
+    world.balls[0].material.refractive_index = 1.5;
+    let edge = renderer
+        .shade(
+            &world,
+            vec3(0.9, 0.0, 0.0),
+            vec3(0.0, 0.0, -1.0),
+            CAMERA_RAY_RANGE,
+            0,
+            &mut 0,
+        )
+        .unwrap();
+    assert_ne!(edge, green);
+    world.balls[0].material.refractive_index = 1.0;
+    let edge = renderer
+        .shade(
+            &world,
+            vec3(0.9, 0.0, 0.0),
+            vec3(0.0, 0.0, -1.0),
+            CAMERA_RAY_RANGE,
+            0,
+            &mut 0,
+        )
+        .unwrap();
+    assert_eq!(edge, green);
+}
+
+#[test]
+fn test_box_hit_normal() {
+    let boxaabb = BoxAABB {
+        min: vec3(-0.5, -0.5, -0.5),
+        max: vec3(0.5, 0.5, 0.5),
+        material: Material::default(),
+    };
+    // Down the +x axis, the ray hits the side facing -x.
+    let hit = boxaabb
+        .intersect(
+            vec3(-5.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            RealRange::LargerEqual(0.0),
+        )
+        .unwrap();
+    assert_eq!(hit.point, vec3(-0.5, 0.0, 0.0));
+    assert_eq!(hit.normal, vec3(-1.0, 0.0, 0.0));
+    // From inside, the ray leaves through the opposite side.
+    let hit = boxaabb
+        .intersect(
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            RealRange::LargerEqual(0.0),
+        )
+        .unwrap();
+    assert_eq!(hit.point, vec3(0.5, 0.0, 0.0));
+    assert_eq!(hit.normal, vec3(1.0, 0.0, 0.0));
+    // A ray passing next to the box misses it.
+    assert!(boxaabb
+        .intersect(
+            vec3(-5.0, 1.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            RealRange::LargerEqual(0.0),
+        )
+        .is_none());
+}
+
+#[test]
+fn test_infinite_floor_plane() {
+    let mut world = World::new();
+    world.background = crate::world::Background::Solid(TEST_BACKGROUND);
+    world.planes.push(Plane {
+        point: vec3(0.0, 0.0, 0.0),
+        normal: vec3(0.0, 1.0, 0.0),
+        material: Material::default(),
+    });
+    let intensity = LightIntensity::new(120.0, 120.0, 120.0);
+    world.lights.push(Light {
+        pos: vec3(0.0, 4.0, -4.0),
+        diffuse_intensity: intensity,
+        specular_intensity: intensity,
+        casts_shadows: true,
+    });
+    let camera = Camera::new(
+        vec3(0.0, 1.0, 0.0),
+        vec3(0.0, 0.0, -1.0),
+        90.0,
+        Resolution { w: 32, h: 16 },
+    )
+    .unwrap();
+    // Without gamma correction, the background keeps the world's color.
+    let renderer = Renderer {
+        gamma: 1.0,
+        ..Default::default()
+    };
+    let frame = renderer.render_to_vec(&world, &camera);
+    let is_background = |x: u32, y: u32| {
+        let i = ((y * 32 + x) * 4) as usize;
+        frame[i..i + 3] == [10, 20, 30]
+    };
+    // The floor has no edges: it fills the whole bottom half of the image,
+    // up to the horizon in row 8.
+    for x in 0..32 {
+        assert!((0..=8).all(|y| is_background(x, y)));
+        assert!((9..16).all(|y| !is_background(x, y)));
+    }
+    // The floor right below the light is lit more than further away.
+    let floor_color = |point: Vec3| {
+        renderer
+            .shade(
+                &world,
+                camera.pos,
+                (point - camera.pos) * 0.5,
+                CAMERA_RAY_RANGE,
+                0,
+                &mut 0,
+            )
+            .unwrap()
+    };
+    let below_light = floor_color(vec3(0.0, 0.0, -4.0));
+    let far_away = floor_color(vec3(0.0, 0.0, -40.0));
+    assert!(below_light.r() > far_away.r());
+    assert!(far_away.r() >= 128.0);
+}
+
+#[test]
+fn test_triangle_hit_barycentric() {
+    let renderer = Renderer::default();
+    let object = VertexObject {
+        vertices: vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(3.0, 0.0, 0.0),
+            vec3(0.0, 3.0, 0.0),
+        ],
+        faces: vec![(0, 1, 2)],
+        ..Default::default()
+    };
+    let barycentric_at = |x: f32, y: f32| {
+        let face_hit = renderer
+            .get_nearest_intersecting_triangle(
+                [(&object, Cow::Owned(object.positioned_faces()), None)],
+                vec3(x, y, 5.0),
+                vec3(0.0, 0.0, -1.0),
+                RealRange::LargerEqual(0.0),
+            )
+            .unwrap();
+        assert_eq!(face_hit.face_index, 0);
+        assert!((face_hit.point - vec3(x, y, 0.0)).len() < 1e-5);
+        face_hit.barycentric
+    };
+    let close = |(a0, a1, a2): Barycentric, (b0, b1, b2): Barycentric| {
+        (a0 - b0).abs() < 1e-5 && (a1 - b1).abs() < 1e-5 && (a2 - b2).abs() < 1e-5
+    };
+    // The centroid weighs all vertices equally.
+    let third = 1.0 / 3.0;
+    assert!(close(barycentric_at(1.0, 1.0), (third, third, third)));
+    // Halfway along the edge between the first two vertices.
+    assert!(close(barycentric_at(1.5, 0.0), (0.5, 0.5, 0.0)));
+}
+
+#[cfg(test)]
+/// A sphere of radius 1 around the origin, made of `rings` rings of
+/// `segments` quads. With `smooth`, the vertices get normals pointing away
+/// from the center.
+fn sphere_mesh(rings: usize, segments: usize, smooth: bool) -> VertexObject {
+    let mut vertices = Vec::new();
+    for ring in 0..=rings {
+        let theta = PI * ring as f32 / rings as f32;
+        for segment in 0..segments {
+            let phi = 2.0 * PI * segment as f32 / segments as f32;
+            vertices.push(vec3(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            ));
+        }
+    }
+    let mut faces = Vec::new();
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let next = (segment + 1) % segments;
+            let (a, b) = (ring * segments + segment, ring * segments + next);
+            let (c, d) = (a + segments, b + segments);
+            faces.push((a, b, c));
+            faces.push((b, d, c));
+        }
+    }
+    VertexObject {
+        vertex_normals: if smooth { vertices.clone() } else { Vec::new() },
+        vertices,
+        faces,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_smooth_normals() {
+    let camera = Camera::new(
+        vec3(0.0, 0.0, 4.0),
+        vec3(0.0, 0.0, -1.0),
+        40.0,
+        Resolution { w: 64, h: 64 },
+    )
+    .unwrap();
+    let intensity = LightIntensity::new(100.0, 100.0, 100.0);
+    // Get the largest change in brightness between neighbouring pixels along
+    // a row through the sphere, just above the middle.
+    let largest_step = |smooth| {
+        let mut world = World::new();
+        world.background = crate::world::Background::Solid(RGBA8::new(1, 2, 3, 255));
+        world.vertex_objects.push(sphere_mesh(8, 12, smooth));
+        world.lights.push(Light {
+            pos: vec3(3.0, 0.5, 4.0),
+            diffuse_intensity: intensity,
+            specular_intensity: intensity,
+            casts_shadows: true,
+        });
+        world.cache_faces();
+        let renderer = Renderer {
+            gamma: 1.0,
+            ..Default::default()
+        };
+        let frame = renderer.render_to_vec(&world, &camera);
+        let row: Vec<u8> = (0..64)
+            .map(|x| &frame[(28 * 64 + x) * 4..(28 * 64 + x) * 4 + 3])
+            .filter(|pixel| pixel != &[1, 2, 3])
+            .map(|pixel| pixel[1])
+            .collect();
+        assert!(row.len() > 20);
+        row.windows(2)
+            .map(|pair| pair[0].abs_diff(pair[1]))
+            .max()
+            .unwrap()
+    };
+    let flat = largest_step(false);
+    let smooth = largest_step(true);
+    assert!(smooth < flat, "smooth: {}, flat: {}", smooth, flat);
+}
+
+#[test]
+fn test_bvh_matches_brute_force() {
+    use crate::sampling::Rng;
+
+    // A few objects with randomly placed triangles, all around the origin.
+    let mut rng = Rng::new(7);
+    let mut random = |scale: f32| (rng.next_f32() - 0.5) * scale;
+    let mut world = World::new();
+    for i in 0..4 {
+        let pos = vec3(random(10.0), random(10.0), random(10.0));
+        let mut object = VertexObject {
+            pos,
+            ..Default::default()
+        };
+        for face in 0..50 * (i + 1) {
+            let corner = vec3(random(8.0), random(8.0), random(8.0));
+            for _ in 0..3 {
+                object
+                    .vertices
+                    .push(corner + vec3(random(2.0), random(2.0), random(2.0)));
+            }
+            object.faces.push((3 * face, 3 * face + 1, 3 * face + 2));
+        }
+        world.vertex_objects.push(object);
+    }
+    world.cache_faces();
+    assert_eq!(world.triangle_bvh().unwrap().triangle_count(), 500);
+
+    let with_bvh = Renderer::default();
+    let brute_force = Renderer {
+        use_bvh: false,
+        ..Default::default()
+    };
+    let mut hits = 0;
+    for _ in 0..2000 {
+        let origin = vec3(random(30.0), random(30.0), random(30.0));
+        let direction = vec3(random(2.0), random(2.0), random(2.0)) - origin * 0.05;
+        let nearest = |renderer: &Renderer| {
+            renderer
+                .get_nearest_intersecting_vertex_object(&world, origin, direction, CAMERA_RAY_RANGE)
+                .map(|(handle, hit)| (handle, hit.point))
+        };
+        let expected = nearest(&brute_force);
+        assert_eq!(nearest(&with_bvh), expected);
+        hits += expected.is_some() as usize;
+
+        let to_light = direction * 3.0;
+        assert_eq!(
+            with_bvh.is_in_shadow(&world, origin, to_light),
+            brute_force.is_in_shadow(&world, origin, to_light)
+        );
+    }
+    // Enough rays hit something for the comparison to mean anything.
+    assert!(hits > 200, "only {} rays hit a triangle", hits);
+
+    // Once an object moves, the hierarchy is out of date until the faces are
+    // cached again.
+    world.vertex_objects[0].pos += vec3(1.0, 0.0, 0.0);
+    assert!(world.triangle_bvh().is_none());
+    world.cache_faces();
+    assert!(world.triangle_bvh().is_some());
+}
+
+#[test]
+fn test_preset_round_trip() {
+    let renderer = Renderer {
+        filters: vec![Filter::Grayscale],
+        multithreading_method: MultithreadingMethod::Crossbeam,
+        thread_count: Some(4),
+        energy_conserving_specular: true,
+        render_balls: false,
+        render_triangles: false,
+        render_planes: false,
+        render_boxes: false,
+        render_mode: RenderMode::SpecularOnly,
+        depth_near: 2.0,
+        depth_far: 30.0,
+        time_budget_ms: Some(40),
+        max_bounce_depth: 1,
+        sampling: SamplingConfig {
+            seed: 42,
+            aa: 4,
+            ..Default::default()
+        },
+        adaptive_aa_threshold: Some(12.0),
+        use_bvh: false,
+        gamma: 1.8,
+        tone_mapping: true,
+        texture_filter: TextureFilter::Bilinear,
+        environment_filter: TextureFilter::Nearest,
+    };
+    let path = std::env::temp_dir().join("renderer_test_preset.json");
+    renderer.save_preset(&path).unwrap();
+    let loaded = Renderer::load_preset(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, renderer);
+
+    // The bundled presets are valid.
+    for entry in std::fs::read_dir("res/presets").unwrap() {
+        Renderer::load_preset(entry.unwrap().path()).unwrap();
+    }
+
+    let partial: Renderer = serde_json::from_str(r#"{ "filters": ["Grayscale"] }"#).unwrap();
+    assert_eq!(
+        partial,
+        Renderer {
+            filters: vec![Filter::Grayscale],
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn test_nearest_hit_across_object_types() {
+    // A ball, a triangle, an instance of it and a plane, lined up behind each
+    // other along the negative z axis.
+    let mut world = World::new();
+    let triangle = |z: f32| VertexObject {
+        pos: vec3(0.0, 0.0, z),
+        vertices: vec![
+            vec3(-1.0, -1.0, 0.0),
+            vec3(1.0, -1.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        ],
+        faces: vec![(0, 1, 2)],
+        ..Default::default()
+    };
+    world.balls.push(Ball {
+        pos: vec3(0.0, 0.0, -3.0),
+        rad: 1.0,
+        material: Material::default(),
+    });
+    world.vertex_objects.push(triangle(-5.0));
+    world.meshes.push(triangle(0.0));
+    world.instances.push(Instance {
+        mesh_id: 0,
+        transform: Transform {
+            translation: vec3(0.0, 0.0, -7.0),
+            ..Default::default()
+        },
+        material: None,
+    });
+    world.planes.push(Plane {
+        point: vec3(0.0, 0.0, -10.0),
+        normal: vec3(0.0, 0.0, 1.0),
+        material: Material::default(),
+    });
+    world.cache_faces();
+
+    let renderer = Renderer::default();
+    let hit = |include: &dyn Fn(ObjectHandle) -> bool| {
+        let (handle, hit) = renderer
+            .get_nearest_hit(
+                &world,
+                vec3(0.0, 0.0, 0.0),
+                vec3(0.0, 0.0, -1.0),
+                RealRange::LargerEqual(0.0),
+                include,
+            )
+            .unwrap();
+        assert!((hit.point - vec3(0.0, 0.0, -hit.t)).len() < 1e-4);
+        assert!((hit.normal.normalized() - vec3(0.0, 0.0, 1.0)).len() < 1e-4);
+        (handle, hit.t)
+    };
+    let (handle, t) = hit(&|_| true);
+    assert_eq!(handle, ObjectHandle::Ball(0));
+    assert!((t - 2.0).abs() < 1e-4);
+    let (handle, t) = hit(&|handle| handle != ObjectHandle::Ball(0));
+    assert_eq!(handle, ObjectHandle::VertexObject(0));
+    assert!((t - 5.0).abs() < 1e-4);
+    let (handle, t) =
+        hit(&|handle| matches!(handle, ObjectHandle::Instance(_) | ObjectHandle::Plane(_)));
+    assert_eq!(handle, ObjectHandle::Instance(0));
+    assert!((t - 7.0).abs() < 1e-4);
+    let (handle, t) = hit(&|handle| matches!(handle, ObjectHandle::Plane(_)));
+    assert_eq!(handle, ObjectHandle::Plane(0));
+    assert!((t - 10.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_pick() {
+    let (mut world, camera) = test_scene();
+    let renderer = Renderer::default();
+    assert_eq!(
+        renderer.pick(&world, &camera, 32, 24),
+        Some(ObjectHandle::Ball(0))
+    );
+    assert_eq!(
+        renderer.pick(&world, &camera, 32, 46),
+        Some(ObjectHandle::VertexObject(0))
+    );
+    assert_eq!(renderer.pick(&world, &camera, 0, 0), None);
+
+    let handle = renderer.pick(&world, &camera, 32, 24).unwrap();
+    world.material_mut(handle).unwrap().shine = 42.0;
+    assert_eq!(world.balls[0].material.shine, 42.0);
+
+    // Hidden balls can't be picked, the floor behind the ball is picked instead.
+    let without_balls = Renderer {
+        render_balls: false,
+        ..Default::default()
+    };
+    let behind_ball = without_balls.pick(&world, &camera, 32, 24);
+    assert_eq!(behind_ball, Some(ObjectHandle::VertexObject(0)));
+}
+
+#[test]
+fn test_background_image() {
+    let (mut world, camera) = test_scene();
+    // A 2x2 image, stretched over the 64x48 frame.
+    let colors = [
+        RGBA8::new(255, 0, 0, 255),
+        RGBA8::new(0, 255, 0, 255),
+        RGBA8::new(0, 0, 255, 255),
+        RGBA8::new(0, 0, 0, 255),
+    ];
+    let path = std::env::temp_dir().join("renderer_test_background.png");
+    let bytes: Vec<u8> = colors.iter().flat_map(|c| [c.r, c.g, c.b, c.a]).collect();
+    crate::util::save_frame_as_png(&bytes, Resolution { w: 2, h: 2 }, &path).unwrap();
+    world.background_image = Some(crate::texture::Texture::load(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+
+    let renderer = Renderer::default();
+    let layout = renderer.frame_layout(&camera);
+    let frame = renderer.render_to_vec(&world, &camera);
+    let pixel_at = |x, y| {
+        let i = layout.pixel_offset(x, y);
+        RGBA8::new(frame[i], frame[i + 1], frame[i + 2], frame[i + 3])
+    };
+    // The sky at the top shows the top half of the image.
+    assert_eq!(pixel_at(0, 0), colors[0]);
+    assert_eq!(pixel_at(31, 0), colors[0]);
+    assert_eq!(pixel_at(32, 0), colors[1]);
+    assert_eq!(pixel_at(63, 0), colors[1]);
+    // The objects are drawn over the image.
+    assert_ne!(pixel_at(32, 46), colors[3]);
+}
+
+#[test]
+fn test_textured_quad() {
+    // A 2x2 checker with a different color in each corner.
+    let colors = [
+        RGBA8::new(255, 0, 0, 255),
+        RGBA8::new(0, 255, 0, 255),
+        RGBA8::new(0, 0, 255, 255),
+        RGBA8::new(255, 255, 255, 255),
+    ];
+    let path = std::env::temp_dir().join("renderer_test_texture.png");
+    let bytes: Vec<u8> = colors.iter().flat_map(|c| [c.r, c.g, c.b, c.a]).collect();
+    crate::util::save_frame_as_png(&bytes, Resolution { w: 2, h: 2 }, &path).unwrap();
+    let mut world = World::new();
+    world
+        .textures
+        .push(crate::texture::Texture::load(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+
+    // A quad facing the camera, with the whole texture on it twice in each
+    // direction.
+    world.vertex_objects.push(VertexObject {
+        vertices: vec![
+            vec3(-1.0, -1.0, 0.0),
+            vec3(1.0, -1.0, 0.0),
+            vec3(1.0, 1.0, 0.0),
+            vec3(-1.0, 1.0, 0.0),
+        ],
+        faces: vec![(0, 1, 2), (0, 2, 3)],
+        material: Material {
+            ambient_constant: RGBA8::new(0, 0, 0, 255),
+            specular_constant: 0.0,
+            texture: Some(0),
+            ..Default::default()
+        },
+        vertex_uvs: vec![(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)],
+        ..Default::default()
+    });
+    let intensity = LightIntensity::new(100.0, 100.0, 100.0);
+    world.lights.push(Light {
+        pos: vec3(0.0, 0.0, 3.0),
+        diffuse_intensity: intensity,
+        specular_intensity: intensity,
+        casts_shadows: true,
+    });
+    world.cache_faces();
+    let camera = Camera::new(
+        vec3(0.0, 0.0, 3.0),
+        vec3(0.0, 0.0, -1.0),
+        40.0,
+        Resolution { w: 32, h: 32 },
+    )
+    .unwrap();
+    let renderer = Renderer::default();
+    let layout = renderer.frame_layout(&camera);
+    let frame = renderer.render_to_vec(&world, &camera);
+    let lit = |x, y| {
+        let i = layout.pixel_offset(x, y);
+        [frame[i] > 0, frame[i + 1] > 0, frame[i + 2] > 0]
+    };
+    // The texture repeats, so the top left of the quad shows the top left of
+    // the image, and the next tile starts in the middle.
+    assert_eq!(lit(4, 4), [true, false, false]);
+    assert_eq!(lit(12, 4), [false, true, false]);
+    assert_eq!(lit(4, 12), [false, false, true]);
+    assert_eq!(lit(12, 12), [true, true, true]);
+    assert_eq!(lit(20, 4), [true, false, false]);
+    assert_eq!(lit(28, 28), [true, true, true]);
+}
+
+#[test]
+fn test_render_to_png() {
+    let (world, camera) = test_scene();
+    let renderer = Renderer::default();
+    let path = std::env::temp_dir().join("renderer_test_render_to_png.png");
+    renderer.render_to_png(&world, &camera, &path).unwrap();
+    let image = image::open(&path).unwrap().to_rgba8();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        image.dimensions(),
+        (camera.resolution.w, camera.resolution.h)
+    );
+    assert_eq!(image.into_raw(), renderer.render_to_vec(&world, &camera));
+}
+
+#[test]
+fn test_supersampling_blends_edges() {
+    // Without lights, the ball is a flat color.
+    let (mut world, camera) = test_scene();
+    world.vertex_objects.clear();
+    world.lights.clear();
+    let mut renderer = Renderer::default();
+    let layout = renderer.frame_layout(&camera);
+    let reds = |frame: &[u8]| {
+        frame
+            .iter()
+            .step_by(layout.bytes_per_pixel)
+            .copied()
+            .collect::<Vec<_>>()
+    };
+
+    // Without anti-aliasing, every pixel is either ball or background.
+    let aliased = reds(&renderer.render_to_vec(&world, &camera));
+    let background = aliased[0];
+    let foreground = *aliased.iter().max().unwrap();
+    assert!(aliased.iter().all(|&r| r == background || r == foreground));
+
+    // With four samples, the pixels on the silhouette are a blend of both.
+    renderer.sampling.aa = 4;
+    let smooth = reds(&renderer.render_to_vec(&world, &camera));
+    assert_eq!(smooth[0], background);
+    assert!(smooth.iter().any(|&r| background < r && r < foreground));
+
+    // The samples come from the pixel's sampler, so neighbouring pixels don't
+    // share a pattern.
+    let offsets: Vec<_> = renderer.supersample_offsets(5, 64).collect();
+    let (u, v) = PixelSampler::new(5, 0, renderer.sampling.seed).sample(1);
+    assert_eq!(offsets.len(), 4);
+    assert_eq!(offsets[1], (u - 0.5, v - 0.5));
+    assert_ne!(
+        offsets,
+        renderer.supersample_offsets(6, 64).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_camera_jitter() {
+    let (_, mut camera) = test_scene();
+    let pixel_index = 10 * 64 + 20;
+    let ray = Renderer::calculate_pixel_ray(&camera, pixel_index);
+    camera.jitter(0.0, 0.0);
+    assert_eq!(Renderer::calculate_pixel_ray(&camera, pixel_index), ray);
+
+    camera.jitter(0.25, -0.4);
+    let ray_a = Renderer::calculate_pixel_ray(&camera, pixel_index);
+    camera.jitter(-0.25, 0.4);
+    let ray_b = Renderer::calculate_pixel_ray(&camera, pixel_index);
+    assert!((ray_a - ray).len() > 1e-3);
+    assert!(((ray_a + ray_b) * 0.5 - ray).len() < 1e-5);
+}
+
+#[test]
+fn test_filters_run_after_tone_mapping() {
+    let renderer = Renderer {
+        filters: vec![Filter::Grayscale],
+        gamma: 1.0,
+        ..Default::default()
+    };
+    // An overexposed red is clipped before it is turned into gray, so it
+    // can't brighten the other channels.
+    let rgba = renderer.apply_filters(renderer.tone_map(Color::new(765.0, 0.0, 0.0)));
+    assert_eq!(rgba, RGBA8::new(76, 76, 76, 255));
+}
+
+#[test]
+fn test_reinhard_tone_mapping() {
+    let over_bright = Color::new(255.0, 510.0, 2550.0);
+    // Clipping loses the difference between the over-bright channels.
+    let clipped = Renderer::default().tone_map(over_bright);
+    assert_eq!(clipped, Color::new(255.0, 255.0, 255.0));
+
+    let renderer = Renderer {
+        tone_mapping: true,
+        ..Default::default()
+    };
+    let mapped = renderer.tone_map(over_bright);
+    // 1 / 2, 2 / 3 and 10 / 11 of the full range.
+    assert!((mapped.r() - 127.5).abs() < 1e-3);
+    assert!((mapped.g() - 170.0).abs() < 1e-3);
+    assert!((mapped.b() - 231.818).abs() < 1e-3);
+    // Colors are never pushed out of range, however bright the light.
+    assert!(renderer.tone_map(Color::new(1e9, 0.0, 0.0)).r() <= 255.0);
+}
+
+#[test]
+fn test_gamma_correction() {
+    let corrected = Renderer::default().apply_filters(Color::new(128.0, 128.0, 128.0));
+    // (128 / 255)^(1 / 2.2) * 255 = 186.4
+    assert_eq!(corrected, RGBA8::new(186, 186, 186, 255));
+    // Black and white stay the same.
+    let extremes = Renderer::default().apply_filters(Color::new(0.0, 255.0, 0.0));
+    assert_eq!(extremes, RGBA8::new(0, 255, 0, 255));
+
+    let linear = Renderer {
+        gamma: 1.0,
+        ..Default::default()
+    };
+    let unchanged = linear.apply_filters(Color::new(128.0, 64.0, 32.0));
+    assert_eq!(unchanged, RGBA8::new(128, 64, 32, 255));
+}
+
+#[test]
+fn test_grayscale_luma() {
+    let renderer = Renderer {
+        filters: vec![Filter::Grayscale],
+        gamma: 1.0,
+        ..Default::default()
+    };
+    let gray = |color: Color| renderer.apply_filters(color).r;
+    let red = gray(Color::new(255.0, 0.0, 0.0));
+    let green = gray(Color::new(0.0, 255.0, 0.0));
+    let blue = gray(Color::new(0.0, 0.0, 255.0));
+    assert_eq!((red, green, blue), (76, 150, 29));
+    assert!(green > red && red > blue);
+    assert_eq!(gray(Color::new(255.0, 255.0, 255.0)), 255);
+}
+
+#[test]
+fn test_filters() {
+    let color = RGBA8::new(100, 150, 200, 255);
+    assert_eq!(Filter::Invert.apply(color), RGBA8::new(155, 105, 55, 255));
+    assert_eq!(Filter::Sepia.apply(color), RGBA8::new(192, 171, 134, 255));
+    assert_eq!(
+        Filter::Brightness(2.0).apply(color),
+        RGBA8::new(200, 255, 255, 255)
+    );
+    assert_eq!(
+        Filter::Contrast(0.0).apply(color),
+        RGBA8::new(128, 128, 128, 255)
+    );
+}
+
+#[test]
+fn test_stacked_filters_apply_in_order() {
+    let render = |filters: Vec<Filter>| {
+        let renderer = Renderer {
+            filters,
+            gamma: 1.0,
+            ..Default::default()
+        };
+        renderer.apply_filters(Color::new(100.0, 150.0, 200.0))
+    };
+    let inverted_darker = render(vec![Filter::Invert, Filter::Brightness(0.5)]);
+    let darker_inverted = render(vec![Filter::Brightness(0.5), Filter::Invert]);
+    assert_eq!(inverted_darker, RGBA8::new(78, 53, 28, 255));
+    assert_eq!(darker_inverted, RGBA8::new(205, 180, 155, 255));
+    let brighter_then_darker = render(vec![Filter::Brightness(2.0), Filter::Brightness(0.5)]);
+    assert_eq!(brighter_then_darker, RGBA8::new(100, 128, 128, 255));
+}
+
+#[test]
+fn test_frame_layout() {
+    let (world, camera) = test_scene();
+    let renderer = Renderer::default();
+    let layout = renderer.frame_layout(&camera);
+    assert_eq!(layout.stride, 64 * 4);
+    assert_eq!(layout.buffer_len(), 64 * 48 * 4);
+    let frame = renderer.render_to_vec(&world, &camera);
+    assert_eq!(frame.len(), layout.buffer_len());
+    // The red ball is in the middle, the floor below it isn't red.
+    let ball = layout.pixel_offset(32, 24);
+    assert!(frame[ball] > frame[ball + 1] && frame[ball + 3] == 255);
+    let floor = layout.pixel_offset(32, 46);
+    assert_eq!(frame[floor], frame[floor + 1]);
+}
+
+#[test]
+fn test_render_object_type_flags() {
+    let (mut world, camera) = test_scene();
+    let pixel_at = |frame: &[u8], x: usize, y: usize| {
+        let i = (y * camera.resolution.w as usize + x) * 4;
+        RGBA8::new(frame[i], frame[i + 1], frame[i + 2], frame[i + 3])
+    };
+    // The middle of the image shows the ball, the bottom shows the floor.
+    let (ball_pixel, floor_pixel) = ((32, 24), (32, 46));
+    // Without gamma correction, the background keeps the world's color.
+    let renderer = Renderer {
+        gamma: 1.0,
+        ..Default::default()
+    };
+    let all = renderer.render_to_vec(&world, &camera);
+    let without_balls = Renderer {
+        render_balls: false,
+        ..renderer.clone()
+    }
+    .render_to_vec(&world, &camera);
+    let without_triangles = Renderer {
+        render_triangles: false,
+        ..renderer.clone()
+    }
+    .render_to_vec(&world, &camera);
+
+    // Hiding the balls reveals the floor behind the ball.
+    assert_ne!(
+        pixel_at(&all, ball_pixel.0, ball_pixel.1),
+        pixel_at(&without_balls, ball_pixel.0, ball_pixel.1)
+    );
+    assert_ne!(
+        pixel_at(&without_balls, ball_pixel.0, ball_pixel.1),
+        TEST_BACKGROUND
+    );
+    assert_eq!(
+        pixel_at(&all, floor_pixel.0, floor_pixel.1),
+        pixel_at(&without_balls, floor_pixel.0, floor_pixel.1)
+    );
+    // Hiding the triangles leaves only the ball.
+    assert_eq!(
+        pixel_at(&all, ball_pixel.0, ball_pixel.1),
+        pixel_at(&without_triangles, ball_pixel.0, ball_pixel.1)
+    );
+    assert_eq!(
+        pixel_at(&without_triangles, floor_pixel.0, floor_pixel.1),
+        TEST_BACKGROUND
+    );
+
+    // A wall behind the ball hides the background at the top of the image,
+    // and a box stands next to the ball.
+    let sky_pixel = (32, 1);
+    world.planes.push(Plane {
+        point: vec3(0.0, 0.0, -8.0),
+        normal: vec3(0.0, 0.0, 1.0),
+        material: Material::default(),
+    });
+    world.boxes.push(BoxAABB {
+        min: vec3(-3.0, 0.0, -4.0),
+        max: vec3(-2.0, 1.5, -3.0),
+        material: Material::default(),
+    });
+    let all = renderer.render_to_vec(&world, &camera);
+    let without_planes = Renderer {
+        render_planes: false,
+        ..renderer.clone()
+    }
+    .render_to_vec(&world, &camera);
+    let without_boxes = Renderer {
+        render_boxes: false,
+        ..renderer
+    }
+    .render_to_vec(&world, &camera);
+    assert_ne!(pixel_at(&all, sky_pixel.0, sky_pixel.1), TEST_BACKGROUND);
+    assert_eq!(
+        pixel_at(&without_planes, sky_pixel.0, sky_pixel.1),
+        TEST_BACKGROUND
+    );
+    assert_ne!(all, without_boxes);
+    assert_eq!(
+        pixel_at(&all, sky_pixel.0, sky_pixel.1),
+        pixel_at(&without_boxes, sky_pixel.0, sky_pixel.1)
+    );
+}
+
+#[test]
+fn test_energy_conserving_specular() {
+    // Integrate the specular lobe (times the cosine term) over the hemisphere
+    // around the reflection direction.
+    let reflected_energy = |renderer: &Renderer, shine: f32| {
+        let steps = 10000;
+        let d_theta = (PI / 2.0) / steps as f32;
+        (0..steps)
+            .map(|i| {
+                let theta = (i as f32 + 0.5) * d_theta;
+                let cos = theta.cos();
+                renderer.specular_factor(cos, shine) * cos * 2.0 * PI * theta.sin() * d_theta
+            })
+            .sum::<f32>()
+    };
+    let renderer = Renderer {
+        energy_conserving_specular: true,
+        ..Default::default()
+    };
+    let mut previous_energy = f32::MAX;
+    for shine in [1.0, 5.0, 20.0, 100.0] {
+        let energy = reflected_energy(&renderer, shine);
+        assert!(energy <= previous_energy + 0.01);
+        assert!((energy - 1.0).abs() < 0.01);
+        previous_energy = energy;
+    }
+    // The highlight gets more concentrated, so its peak gets brighter.
+    assert!(renderer.specular_factor(1.0, 100.0) > renderer.specular_factor(1.0, 5.0));
+}
+
+#[test]
+fn test_instances_of_shared_mesh() {
+    let mut world = World::new();
+    world.meshes.push(VertexObject {
+        vertices: vec![
+            vec3(-1.0, -1.0, 0.0),
+            vec3(1.0, -1.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        ],
+        faces: vec![(0, 1, 2)],
+        ..Default::default()
+    });
+    let red = Material {
+        ambient_constant: RGBA8::new(255, 0, 0, 255),
+        ..Default::default()
+    };
+    world.instances.push(Instance {
+        mesh_id: 0,
+        transform: Transform {
+            translation: vec3(-3.0, 0.0, -5.0),
+            ..Default::default()
+        },
+        material: None,
+    });
+    world.instances.push(Instance {
+        mesh_id: 0,
+        transform: Transform {
+            translation: vec3(3.0, 0.0, -10.0),
+            scale: vec3(2.0, 2.0, 2.0),
+        },
+        material: Some(red),
+    });
+
+    let renderer = Renderer::default();
+    let origin = vec3(0.0, 0.0, 0.0);
+    // Use short directions, so the hits are well beyond the "image plane" at
+    // t = 1.
+    let hit = |target: Vec3| {
+        renderer.get_nearest_intersecting_instance(
+            &world,
+            origin,
+            (target - origin) * 0.5,
+            CAMERA_RAY_RANGE,
+        )
+    };
+
+    let (handle, first) = hit(vec3(-3.0, 0.0, -5.0)).unwrap();
+    assert_eq!(handle, ObjectHandle::Instance(0));
+    assert!((first.point - vec3(-3.0, 0.0, -5.0)).len() < 1e-4);
+    assert!((first.normal - vec3(0.0, 0.0, 1.0)).len() < 1e-4);
+
+    // The second instance is scaled up, so it is hit further from its center.
+    let (_, second) = hit(vec3(3.2, 1.5, -10.0)).unwrap();
+    assert!((second.point - vec3(3.2, 1.5, -10.0)).len() < 1e-4);
+    assert_eq!(second.material.ambient_constant, red.ambient_constant);
+
+    // Nothing in between the two instances.
+    assert!(hit(vec3(0.0, 0.0, -7.0)).is_none());
+    // The shared mesh itself is not drawn.
+    assert!(hit(vec3(0.0, 0.0, -1.0)).is_none());
+}
+
+#[test]
+fn test_cubemap_faces_match_at_edges() {
+    let (world, _) = test_scene();
+    let size = 32;
+    let renderer = Renderer {
+        gamma: 1.0,
+        ..Default::default()
+    };
+    let faces = renderer.render_cubemap(&world, vec3(0.0, 1.0, 0.0), size);
+    let pixel = |face: &[u8], x: u32, y: u32| {
+        let i = 4 * (y * size + x) as usize;
+        [face[i], face[i + 1], face[i + 2]]
+    };
+    let assert_similar = |a: [u8; 3], b: [u8; 3]| {
+        for (a, b) in a.iter().zip(b) {
+            assert!((*a as i32 - b as i32).abs() <= 8, "{:?} != {:?}", a, b);
+        }
+    };
+    let (pos_x, neg_y, pos_z) = (&faces[0], &faces[3], &faces[4]);
+    for i in 0..size {
+        // The right edge of +X meets the left edge of +Z.
+        assert_similar(pixel(pos_x, size - 1, i), pixel(pos_z, 0, i));
+        // The bottom edge of +X meets the left edge of -Y, which runs from +Z
+        // (at the top) to -Z.
+        assert_similar(pixel(pos_x, i, size - 1), pixel(neg_y, 0, size - 1 - i));
     }
+    // Looking down shows the floor, looking up only the background.
+    assert_ne!(pixel(neg_y, size / 2, size / 2), [10, 20, 30]);
+    assert_eq!(pixel(&faces[2], size / 2, size / 2), [10, 20, 30]);
 }
@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+
+/// The random seed and sample counts of all stochastic rendering features in
+/// one place, so a render can be reproduced exactly, e.g. for golden image
+/// tests. The samples only depend on the seed and the pixel, so identical
+/// configurations give byte-identical frames with every multithreading
+/// method.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SamplingConfig {
+    /// The seed of all random numbers used while rendering.
+    pub seed: u64,
+    /// Samples per pixel for anti-aliasing, spread over the pixel by a
+    /// `PixelSampler`. A single sample goes through the pixel's center.
+    pub aa: u32,
+    /// Hemisphere samples per vertex when baking ambient occlusion, 0 to
+    /// skip baking it.
+    pub ao: u32,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        SamplingConfig {
+            seed: 0,
+            aa: 1,
+            ao: 0,
+        }
+    }
+}
+
+/// Get the `index`-th element of the Halton sequence for a prime `base`. The
+/// values lie in [0, 1) and fill the interval much more evenly than random
+/// numbers do.
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// A small, fast pseudo random number generator (SplitMix64). It is
+/// deterministic for a given seed, so renders can be reproduced.
+#[derive(Debug, Clone, Copy)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Create a generator that gives the same numbers for the same pixel and
+    /// seed on every frame.
+    pub fn for_pixel(x: u32, y: u32, seed: u64) -> Rng {
+        let pixel = ((x as u64) << 32) | y as u64;
+        Rng::new(pixel ^ seed.wrapping_mul(0xD6E8_FEB8_6659_FD93))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Get a random number in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// A generator of 2D sample offsets in [0, 1) x [0, 1) for a single pixel,
+/// used for the anti-aliasing rays.
+///
+/// The samples follow the Halton sequence (bases 2 and 3), shifted by a
+/// random offset that only depends on the pixel. This keeps the samples well
+/// spread out, avoids the same pattern showing up in every pixel and makes
+/// the jitter for a given pixel and sample index the same on every frame.
+#[derive(Debug, Clone, Copy)]
+pub struct PixelSampler {
+    offset: (f32, f32),
+}
+
+impl PixelSampler {
+    pub fn new(x: u32, y: u32, seed: u64) -> PixelSampler {
+        let mut rng = Rng::for_pixel(x, y, seed);
+        PixelSampler {
+            offset: (rng.next_f32(), rng.next_f32()),
+        }
+    }
+
+    /// Get the `index`-th sample for this pixel.
+    pub fn sample(&self, index: u32) -> (f32, f32) {
+        // Skip the first element, which is always zero.
+        let u = halton(index + 1, 2) + self.offset.0;
+        let v = halton(index + 1, 3) + self.offset.1;
+        (u.fract(), v.fract())
+    }
+}
+
+#[test]
+fn test_halton() {
+    let expected = [0.0, 0.5, 0.25, 0.75, 0.125];
+    for (i, value) in expected.iter().enumerate() {
+        assert_eq!(halton(i as u32, 2), *value);
+    }
+    assert!((halton(2, 3) - 2.0 / 3.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_halton_converges_faster_than_random() {
+    // Estimate the mean of a smooth function over the unit square (exactly
+    // 0.25) in many pixels, and compare the variance of the estimates.
+    let f = |(u, v): (f32, f32)| u * v;
+    let sample_count = 16;
+    let pixel_count = 256;
+    let variance = |estimates: Vec<f32>| {
+        estimates.iter().map(|e| (e - 0.25).powi(2)).sum::<f32>() / estimates.len() as f32
+    };
+
+    let halton_estimates = (0..pixel_count)
+        .map(|p| {
+            let sampler = PixelSampler::new(p, 0, 0);
+            (0..sample_count).map(|i| f(sampler.sample(i))).sum::<f32>() / sample_count as f32
+        })
+        .collect();
+    let random_estimates = (0..pixel_count)
+        .map(|p| {
+            let mut rng = Rng::for_pixel(p, 1, 0);
+            (0..sample_count)
+                .map(|_| f((rng.next_f32(), rng.next_f32())))
+                .sum::<f32>()
+                / sample_count as f32
+        })
+        .collect();
+    assert!(variance(halton_estimates) * 4.0 < variance(random_estimates));
+
+    // The samples are the same for the same pixel and seed on every frame.
+    assert_eq!(
+        PixelSampler::new(3, 7, 1).sample(5),
+        PixelSampler::new(3, 7, 1).sample(5)
+    );
+    assert_ne!(
+        PixelSampler::new(3, 7, 1).sample(5),
+        PixelSampler::new(3, 7, 2).sample(5)
+    );
+}
@@ -0,0 +1,194 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::vector::{vec3, Vec3};
+
+use rgb::*;
+use serde::{Deserialize, Serialize};
+
+/// How a texture is sampled between the centers of its pixels.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum TextureFilter {
+    /// Use the color of the pixel the coordinates fall in, so magnified
+    /// textures look blocky.
+    #[default]
+    Nearest,
+    /// Blend the colors of the four nearest pixel centers, so magnified
+    /// textures look smooth.
+    Bilinear,
+}
+
+/// An image that can be sampled by (u, v) coordinates, loaded from an image
+/// file.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    /// The pixels row by row, from the top left.
+    pub pixels: Vec<RGBA8>,
+}
+
+impl Texture {
+    /// Load a texture from an image file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Texture, Box<dyn Error>> {
+        let image = image::open(path)?.to_rgba8();
+        let (width, height) = image.dimensions();
+        let pixels = image
+            .pixels()
+            .map(|p| RGBA8::new(p[0], p[1], p[2], p[3]))
+            .collect();
+        Ok(Texture {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Get the color of the pixel at (u, v), where (0, 0) is the top left
+    /// corner and (1, 1) the bottom right corner of the image. Coordinates
+    /// outside of that range are clamped to the edge.
+    pub fn sample(&self, u: f32, v: f32) -> RGBA8 {
+        let x = ((u * self.width as f32) as u32).min(self.width - 1);
+        let y = ((v * self.height as f32) as u32).min(self.height - 1);
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Get the color at (u, v) like `sample`, with the given filter. Bilinear
+    /// filtering clamps to the edge pixels as well, so it doesn't blend in
+    /// the opposite side of the image.
+    pub fn sample_filtered(&self, u: f32, v: f32, filter: TextureFilter) -> RGBA8 {
+        if filter == TextureFilter::Nearest {
+            return self.sample(u, v);
+        }
+        // The pixel centers are at (x + 0.5, y + 0.5) in pixel units.
+        let x = (u * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x as u32, y as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x.fract(), y.fract());
+        let pixel = |x: u32, y: u32| self.pixels[(y * self.width + x) as usize];
+        let (top_left, top_right) = (pixel(x0, y0), pixel(x1, y0));
+        let (bottom_left, bottom_right) = (pixel(x0, y1), pixel(x1, y1));
+        let lerp = |a: u8, b: u8, t: f32| a as f32 + (b as f32 - a as f32) * t;
+        let blend = |channel: fn(RGBA8) -> u8| {
+            let top = lerp(channel(top_left), channel(top_right), fx);
+            let bottom = lerp(channel(bottom_left), channel(bottom_right), fx);
+            (top + (bottom - top) * fy).round() as u8
+        };
+        RGBA8::new(
+            blend(|p| p.r),
+            blend(|p| p.g),
+            blend(|p| p.b),
+            blend(|p| p.a),
+        )
+    }
+}
+
+/// Six square images of the surroundings, seen from a single point, in the
+/// order +X, -X, +Y, -Y, +Z, -Z. The faces are oriented like the images of
+/// `Renderer::render_cubemap`, so a rendered cubemap can be used as a skybox.
+#[derive(Debug, Clone)]
+pub struct Cubemap {
+    pub faces: [Texture; 6],
+}
+
+impl Cubemap {
+    /// Load the six faces from image files, in the order +X, -X, +Y, -Y, +Z,
+    /// -Z.
+    pub fn load<P: AsRef<Path>>(paths: &[P; 6]) -> Result<Cubemap, Box<dyn Error>> {
+        let mut faces = Vec::with_capacity(6);
+        for path in paths {
+            faces.push(Texture::load(path)?);
+        }
+        // Unwrap is safe, because there is a face for every path.
+        Ok(Cubemap {
+            faces: faces.try_into().unwrap(),
+        })
+    }
+
+    /// The direction every face looks in, and the direction that is "up" in
+    /// its image. On the side faces "up" is +Y; the top of the +Y face points
+    /// to -Z and the top of the -Y face to +Z.
+    pub fn face_orientations() -> [(Vec3, Vec3); 6] {
+        [
+            (vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0)),
+            (vec3(-1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0)),
+            (vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, -1.0)),
+            (vec3(0.0, -1.0, 0.0), vec3(0.0, 0.0, 1.0)),
+            (vec3(0.0, 0.0, 1.0), vec3(0.0, 1.0, 0.0)),
+            (vec3(0.0, 0.0, -1.0), vec3(0.0, 1.0, 0.0)),
+        ]
+    }
+
+    /// Get the index of the face a direction points at, and the (u, v)
+    /// coordinates on that face, with (0, 0) at the top left of its image.
+    pub fn face_and_uv(direction: Vec3) -> (usize, f32, f32) {
+        let (x, y, z) = (direction.x.abs(), direction.y.abs(), direction.z.abs());
+        let (axis, component) = if x >= y && x >= z {
+            (0, direction.x)
+        } else if y >= z {
+            (1, direction.y)
+        } else {
+            (2, direction.z)
+        };
+        let face = 2 * axis + (component < 0.0) as usize;
+        let (forward, up) = Self::face_orientations()[face];
+        let right = forward.cross_product(up);
+        // Where the direction crosses the face, at distance 1 from the center.
+        let p = direction / (direction * forward);
+        (face, (p * right + 1.0) / 2.0, (1.0 - p * up) / 2.0)
+    }
+
+    /// Get the color seen in a direction, which doesn't need to be normalized.
+    /// Bilinear filtering doesn't blend across the edges between faces.
+    pub fn sample(&self, direction: Vec3, filter: TextureFilter) -> RGBA8 {
+        let (face, u, v) = Self::face_and_uv(direction);
+        self.faces[face].sample_filtered(u, v, filter)
+    }
+}
+
+#[test]
+fn test_cubemap_faces() {
+    for (direction, face) in [
+        (vec3(1.0, 0.0, 0.0), 0),
+        (vec3(0.0, 1.0, 0.0), 2),
+        (vec3(0.0, 0.0, 1.0), 4),
+        (vec3(0.0, 0.0, -3.0), 5),
+    ] {
+        assert_eq!(Cubemap::face_and_uv(direction), (face, 0.5, 0.5));
+    }
+    // On the +Z face, -X is to the right (like looking at +Z with +Y up), and
+    // on the +Y face -Z is at the top.
+    let (face, u, v) = Cubemap::face_and_uv(vec3(-0.5, 0.0, 1.0));
+    assert_eq!((face, u, v), (4, 0.75, 0.5));
+    let (face, u, v) = Cubemap::face_and_uv(vec3(0.0, 1.0, -0.5));
+    assert_eq!((face, u, v), (2, 0.5, 0.25));
+}
+
+#[test]
+fn test_bilinear_sampling() {
+    let texture = Texture {
+        width: 2,
+        height: 2,
+        pixels: vec![
+            RGBA8::new(0, 0, 0, 255),
+            RGBA8::new(200, 0, 0, 255),
+            RGBA8::new(0, 100, 0, 255),
+            RGBA8::new(200, 100, 0, 255),
+        ],
+    };
+    let sample = |u, v| texture.sample_filtered(u, v, TextureFilter::Bilinear);
+    // At the pixel centers and beyond them at the edges, the pixels
+    // themselves are seen.
+    assert_eq!(sample(0.25, 0.25), RGBA8::new(0, 0, 0, 255));
+    assert_eq!(sample(0.0, 1.0), RGBA8::new(0, 100, 0, 255));
+    assert_eq!(sample(1.0, 1.0), RGBA8::new(200, 100, 0, 255));
+    // Between the centers, the neighbouring pixels are blended.
+    assert_eq!(sample(0.5, 0.25), RGBA8::new(100, 0, 0, 255));
+    assert_eq!(sample(0.5, 0.5), RGBA8::new(100, 50, 0, 255));
+    assert_eq!(sample(0.375, 0.75), RGBA8::new(50, 100, 0, 255));
+    // Nearest sampling keeps the hard edge between the pixels.
+    let nearest = |u, v| texture.sample_filtered(u, v, TextureFilter::Nearest);
+    assert_eq!(nearest(0.49, 0.25), RGBA8::new(0, 0, 0, 255));
+    assert_eq!(nearest(0.51, 0.25), RGBA8::new(200, 0, 0, 255));
+}
@@ -1,12 +1,14 @@
 use std::f32::consts::PI;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::errors::*;
+use crate::objects::{Material, ObjectEntry, TriangleFaceIndices, VertexObject};
 use crate::vector::{vec3, Vec3};
 use crate::world::World;
 
+use rgb::*;
 use serde::de::DeserializeOwned;
 use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
 
@@ -53,10 +55,26 @@ impl RealRange {
             RealRange::LargerEqual(a) => x >= a,
         }
     }
+
+    /// Get the lower end of the range, or negative infinity if it has none.
+    /// The value itself may be excluded from the range.
+    pub fn lower_bound(&self) -> f32 {
+        match *self {
+            RealRange::All | RealRange::SmallerThan(_) | RealRange::SmallerEqual(_) => {
+                f32::NEG_INFINITY
+            }
+            RealRange::Closed(a, _)
+            | RealRange::Open(a, _)
+            | RealRange::HalfOpenR(a, _)
+            | RealRange::HalfOpenL(a, _)
+            | RealRange::LargerThan(a)
+            | RealRange::LargerEqual(a) => a,
+        }
+    }
 }
 
 pub fn move_pyramid(world: &mut World, by: Vec3) {
-    if let Some(pyramid) = world.vertex_objects.get_mut(1) {
+    if let Some(pyramid) = world.vertex_object_mut(1) {
         pyramid.pos += by;
     }
 }
@@ -72,6 +90,52 @@ pub fn print_frame_time(frame_time_ms: f32) {
     }
 }
 
+/// Frame times collected over a session, to report on when the renderer
+/// exits.
+#[derive(Debug, Default)]
+pub struct FrameStats {
+    frame_times_ms: Vec<f32>,
+}
+
+impl FrameStats {
+    pub fn record(&mut self, frame_time_ms: f32) {
+        self.frame_times_ms.push(frame_time_ms);
+    }
+
+    /// Get the minimum, maximum, mean and median frame time in milliseconds,
+    /// or `None` if no frames were recorded.
+    pub fn min_max_mean_median(&self) -> Option<(f32, f32, f32, f32)> {
+        let mut sorted = self.frame_times_ms.clone();
+        sorted.sort_by(f32::total_cmp);
+        let count = sorted.len();
+        let min = *sorted.first()?;
+        let max = *sorted.last()?;
+        let mean = sorted.iter().sum::<f32>() / count as f32;
+        let median = if count.is_multiple_of(2) {
+            (sorted[count / 2 - 1] + sorted[count / 2]) / 2.0
+        } else {
+            sorted[count / 2]
+        };
+        Some((min, max, mean, median))
+    }
+
+    /// Get a human readable report of the frame times.
+    pub fn report(&self) -> String {
+        match self.min_max_mean_median() {
+            Some((min, max, mean, median)) => format!(
+                "{} frames: min {:.1} MS, max {:.1} MS, mean {:.1} MS, median {:.1} MS ({:.1} FPS)",
+                self.frame_times_ms.len(),
+                min,
+                max,
+                mean,
+                median,
+                1000.0 / mean
+            ),
+            None => "No frames were rendered.".to_string(),
+        }
+    }
+}
+
 /// Spherical coordinates, where theta represents the angle counter-clockwise
 /// from the positive z-axis and phi is the counter-clockwise rotation from the
 /// positive x-axis.
@@ -105,19 +169,34 @@ impl SphericalCoordinates {
     }
 }
 
-impl From<Vec3> for SphericalCoordinates {
-    fn from(cartesian: Vec3) -> Self {
-        let rad =
-            (cartesian.x * cartesian.x + cartesian.y * cartesian.y + cartesian.z * cartesian.z)
-                .sqrt();
-        let theta = (cartesian.z / rad).acos();
+/// Get the angle between the z axis and a vector with z component `z` and
+/// length `rad`. Rounding errors could push `z / rad` just outside of
+/// [-1, 1], for which `acos` returns NaN, so the ratio is clamped.
+fn polar_angle(z: f32, rad: f32) -> f32 {
+    (z / rad).clamp(-1.0, 1.0).acos()
+}
+
+impl TryFrom<Vec3> for SphericalCoordinates {
+    type Error = SphericalCreationError;
+
+    fn try_from(cartesian: Vec3) -> Result<Self, Self::Error> {
+        if !(cartesian.x.is_finite() && cartesian.y.is_finite() && cartesian.z.is_finite()) {
+            return Err(SphericalCreationError::NonFiniteInput);
+        }
+        // Using `hypot` avoids the squares under- or overflowing for very
+        // small or very large vectors.
+        let rad = cartesian.x.hypot(cartesian.y).hypot(cartesian.z);
+        // The angles of the zero vector are undefined, so just use 0.
+        if rad == 0.0 {
+            return Ok(Self::new(0.0, 0.0, 0.0));
+        }
+        let theta = polar_angle(cartesian.z, rad);
         let mut phi = (cartesian.y).atan2(cartesian.x);
         // Translate [-PI, PI] to [0, 2*PI]
         if phi < 0.0 {
             phi = 2.0 * PI + phi;
         }
         Self::new_strict(rad, theta, phi)
-            .expect("These values can only ever be out of bounds due to a programmer's error.")
     }
 }
 
@@ -129,7 +208,54 @@ fn test_vec_to_sphere_conversion() {
         theta: PI / 2.0,
         phi: PI / 4.0,
     };
-    assert_eq!(s, v.into());
+    assert_eq!(s, v.try_into().unwrap());
+}
+
+#[test]
+fn test_zero_vec_to_sphere_conversion() {
+    let s: SphericalCoordinates = vec3(0.0, 0.0, 0.0).try_into().unwrap();
+    assert_eq!(s, SphericalCoordinates::new(0.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_tiny_vec_to_sphere_conversion() {
+    // The squared length of this vector underflows to 0, which would make the
+    // ratio z / rad infinite (and theta NaN) without care.
+    let s: SphericalCoordinates = vec3(0.0, 0.0, -1e-25).try_into().unwrap();
+    assert!(s.rad > 0.0);
+    assert_eq!(s.theta, PI);
+    let s: SphericalCoordinates = vec3(1e-25, 0.0, 1e-25).try_into().unwrap();
+    assert!((s.theta - PI / 4.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_near_axis_vec_to_sphere_conversion() {
+    // For vectors (almost) along the z axis, z / rad is (almost) exactly 1 or
+    // -1, where rounding must not push theta to NaN.
+    for (v, expected_theta) in [
+        (vec3(0.0, 0.0, 1e-20), 0.0),
+        (vec3(1e-3, 0.0, 1e30), 0.0),
+        (vec3(0.0, 1e-45, -1.0), PI),
+        (vec3(-1e-30, 1e-30, -3e20), PI),
+    ] {
+        let s: SphericalCoordinates = v.try_into().unwrap();
+        assert!(s.theta.is_finite());
+        assert!((s.theta - expected_theta).abs() < 1e-6, "{:?}", v);
+    }
+    // `hypot` never returns less than |z|, but a length computed another
+    // way can be rounded down to just below it.
+    let rad = 1.0 - f32::EPSILON / 2.0;
+    assert!(1.0 / rad > 1.0);
+    assert_eq!(polar_angle(1.0, rad), 0.0);
+    assert_eq!(polar_angle(-1.0, rad), PI);
+}
+
+#[test]
+fn test_non_finite_vec_to_sphere_conversion() {
+    let result: Result<SphericalCoordinates, _> = vec3(f32::NAN, 0.0, 1.0).try_into();
+    assert!(result.is_err());
+    let result: Result<SphericalCoordinates, _> = vec3(0.0, f32::INFINITY, 1.0).try_into();
+    assert!(result.is_err());
 }
 
 /// Write an object into a json file using Serde serialization.
@@ -156,6 +282,306 @@ where
     let result = serde_json::from_str(&s)?;
     Ok(result)
 }
+/// Load a list of objects (balls, vertex objects and lights) from a single
+/// json file.
+pub fn load_objects_from_file_json<P>(
+    path: P,
+) -> Result<Vec<ObjectEntry>, Box<dyn std::error::Error>>
+where
+    P: AsRef<Path>,
+{
+    load_object_from_file_json(path)
+}
+/// A vertex object loaded from a Wavefront .obj file, with what the object
+/// can't hold itself.
+pub struct ObjModel {
+    pub object: VertexObject,
+    /// Paths to the diffuse textures (`map_Kd`) of the object's materials,
+    /// which their `texture` indices refer to.
+    pub textures: Vec<PathBuf>,
+    /// Problems that didn't stop the file from loading.
+    pub warnings: Vec<SceneWarning>,
+}
+
+/// Load a vertex object from the vertex (`v`), texture coordinate (`vt`) and
+/// face (`f`) lines of a Wavefront .obj file. Polygons are split into a fan of
+/// triangles. A vertex has a single texture coordinate, so where faces give a
+/// vertex different ones, the last one is used.
+///
+/// Faces after a `usemtl` line use that material from the material library
+/// of the `mtllib` line, if it can be read; the other faces use the object's
+/// own material. A library that can't be read is a warning. Other lines are
+/// ignored.
+pub fn load_obj_model<P>(path: P) -> Result<ObjModel, Box<dyn std::error::Error>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut s = String::new();
+    File::open(path)?.read_to_string(&mut s)?;
+    let mut vertices = Vec::new();
+    let mut uvs = Vec::new();
+    let mut vertex_uvs = Vec::new();
+    let mut faces: Vec<TriangleFaceIndices> = Vec::new();
+    let mut library_path = None;
+    let mut material_names: Vec<String> = Vec::new();
+    let mut current_material = None;
+    let mut face_materials = Vec::new();
+    for (line_index, line) in s.lines().enumerate() {
+        let line_number = line_index + 1;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let coordinates: Vec<f32> = tokens
+                    .take(3)
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| ObjParseError::InvalidVertex(line_number))?;
+                match coordinates[..] {
+                    [x, y, z] => vertices.push(vec3(x, y, z)),
+                    _ => return Err(ObjParseError::InvalidVertex(line_number).into()),
+                }
+            }
+            Some("vt") => {
+                let coordinates: Vec<f32> = tokens
+                    .take(2)
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| ObjParseError::InvalidVertex(line_number))?;
+                match coordinates[..] {
+                    [u, v] => uvs.push((u, v)),
+                    [u] => uvs.push((u, 0.0)),
+                    _ => return Err(ObjParseError::InvalidVertex(line_number).into()),
+                }
+            }
+            Some("f") => {
+                let mut indices = Vec::new();
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let vertex = parts.next().unwrap_or("");
+                    let index = obj_index(vertex, vertices.len(), line_number)?;
+                    if let Some(uv) = parts.next().filter(|uv| !uv.is_empty()) {
+                        let uv = obj_index(uv, uvs.len(), line_number)?;
+                        vertex_uvs.resize(vertices.len(), (0.0, 0.0));
+                        vertex_uvs[index] = uvs[uv];
+                    }
+                    indices.push(index);
+                }
+                if indices.len() < 3 {
+                    return Err(ObjParseError::InvalidFace(line_number).into());
+                }
+                for i in 1..indices.len() - 1 {
+                    faces.push((indices[0], indices[i], indices[i + 1]));
+                    face_materials.push(current_material);
+                }
+            }
+            Some("mtllib") => library_path = tokens.next().map(PathBuf::from),
+            Some("usemtl") => {
+                let name = tokens.next().unwrap_or("").to_string();
+                current_material = Some(match material_names.iter().position(|n| *n == name) {
+                    Some(index) => index,
+                    None => {
+                        material_names.push(name);
+                        material_names.len() - 1
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+    if !vertex_uvs.is_empty() {
+        vertex_uvs.resize(vertices.len(), (0.0, 0.0));
+    }
+
+    let mut model = ObjModel {
+        object: VertexObject {
+            vertices,
+            faces,
+            vertex_uvs,
+            ..Default::default()
+        },
+        textures: Vec::new(),
+        warnings: Vec::new(),
+    };
+    // The library is relative to the .obj file's directory.
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let library = match library_path.map(|library_path| directory.join(library_path)) {
+        Some(library_path) => match load_mtl_library(&library_path)? {
+            Some(library) => library,
+            None => {
+                let path = library_path.display().to_string();
+                model
+                    .warnings
+                    .push(SceneWarning::MissingMaterialLibrary(path));
+                return Ok(model);
+            }
+        },
+        None => return Ok(model),
+    };
+    // Names that aren't in the library leave their faces with the object's
+    // own material.
+    let material_indices: Vec<Option<usize>> = material_names
+        .iter()
+        .map(|name| library.iter().position(|(n, _, _)| n == name))
+        .collect();
+    for (_, mut material, texture) in library {
+        if let Some(texture) = texture {
+            material.texture = Some(model.textures.len());
+            model.textures.push(texture);
+        }
+        model.object.materials.push(material);
+    }
+    model.object.face_materials = face_materials
+        .into_iter()
+        .map(|material| material.and_then(|index| material_indices[index]))
+        .collect();
+    Ok(model)
+}
+
+/// A material of a Wavefront .mtl file: its name, the material and the path
+/// to its diffuse texture.
+type MtlMaterial = (String, Material, Option<PathBuf>);
+
+/// Load the materials of a Wavefront .mtl file, by `newmtl` name, with the
+/// path to their diffuse texture. Their diffuse (`Kd`) color is used as their
+/// color, their specular (`Ks`) color as the color of their highlights and
+/// their specular exponent (`Ns`) as their shine. Returns `None` if the file
+/// can't be read.
+fn load_mtl_library(path: &Path) -> Result<Option<Vec<MtlMaterial>>, ObjParseError> {
+    let mut s = String::new();
+    if File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut s))
+        .is_err()
+    {
+        return Ok(None);
+    }
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut library: Vec<MtlMaterial> = Vec::new();
+    for (line_index, line) in s.lines().enumerate() {
+        let invalid =
+            || ObjParseError::InvalidMaterialValue(path.display().to_string(), line_index + 1);
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next();
+        if keyword == Some("newmtl") {
+            let name = tokens.next().unwrap_or("").to_string();
+            library.push((name, Material::default(), None));
+            continue;
+        }
+        // Statements before the first `newmtl` don't belong to a material.
+        let Some((_, material, texture)) = library.last_mut() else {
+            continue;
+        };
+        let numbers = |tokens: std::str::SplitWhitespace| -> Result<Vec<f32>, ObjParseError> {
+            tokens.map(|t| t.parse().map_err(|_| invalid())).collect()
+        };
+        let color = |numbers: Vec<f32>| match numbers[..] {
+            [r, g, b] => {
+                let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+                Ok(RGBA8::new(channel(r), channel(g), channel(b), 255))
+            }
+            _ => Err(invalid()),
+        };
+        match keyword {
+            Some("Kd") => material.ambient_constant = color(numbers(tokens)?)?,
+            Some("Ks") => material.specular_color = Some(color(numbers(tokens)?)?),
+            Some("Ns") => match numbers(tokens)?[..] {
+                [shine] => material.shine = shine,
+                _ => return Err(invalid()),
+            },
+            // Options like `-s 2 2 1` come before the file name.
+            Some("map_Kd") => *texture = tokens.last().map(|file| directory.join(file)),
+            _ => {}
+        }
+    }
+    Ok(Some(library))
+}
+
+/// Convert an index of an .obj face, like the `3` or `1` of `3/1`, to a
+/// 0-based index into the `count` vertices or texture coordinates read so
+/// far. .obj indices start at 1, and negative indices count back from the
+/// last one read.
+fn obj_index(token: &str, count: usize, line_number: usize) -> Result<usize, ObjParseError> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| ObjParseError::InvalidFace(line_number))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if (0..count as i64).contains(&resolved) {
+        Ok(resolved as usize)
+    } else {
+        Err(ObjParseError::IndexOutOfRange(line_number, index))
+    }
+}
+
+#[test]
+fn test_load_cube_obj() {
+    let cube = load_obj_model("res/objects/cube.obj").unwrap().object;
+    assert_eq!(cube.vertices.len(), 8);
+    assert_eq!(cube.faces.len(), 12);
+    // The first quad, `f 1 4 3 2`, is split into a fan around its first vertex.
+    assert_eq!(cube.faces[0], (0, 3, 2));
+    assert_eq!(cube.faces[1], (0, 2, 1));
+    assert!(cube.faces.iter().all(|&(a, b, c)| a < 8 && b < 8 && c < 8));
+}
+
+#[test]
+fn test_load_obj_with_materials() {
+    let directory = std::env::temp_dir().join("renderer_test_obj_materials");
+    std::fs::create_dir_all(&directory).unwrap();
+    // Two triangles with a material each, and one before any `usemtl`.
+    let obj = "mtllib two.mtl\n\
+        v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\n\
+        vt 0 0\nvt 1 0\nvt 0 1\n\
+        f 1 2 3\n\
+        usemtl red\nf 1/1 2/2 3/3\n\
+        usemtl shiny\nf 2 4 3\n";
+    let mtl = "newmtl red\nKd 1 0 0\nmap_Kd -s 2 2 1 bricks.png\n\
+        newmtl shiny\nKd 0 0 1\nKs 0.5 0.5 0.5\nNs 50\n";
+    std::fs::write(directory.join("two.obj"), obj).unwrap();
+    std::fs::write(directory.join("two.mtl"), mtl).unwrap();
+    let model = load_obj_model(directory.join("two.obj")).unwrap();
+    let object = &model.object;
+    assert!(model.warnings.is_empty());
+    assert_eq!(object.face_materials, vec![None, Some(0), Some(1)]);
+    assert_eq!(model.textures, vec![directory.join("bricks.png")]);
+    assert_eq!(
+        object.vertex_uvs,
+        vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, 0.0)]
+    );
+
+    let at = |face_index| object.material_at(face_index, (1.0, 0.0, 0.0));
+    assert_eq!(at(0).ambient_constant, object.material.ambient_constant);
+    let red = at(1);
+    assert_eq!(red.ambient_constant, RGBA8::new(255, 0, 0, 255));
+    assert_eq!(red.texture, Some(0));
+    let shiny = at(2);
+    assert_eq!(shiny.ambient_constant, RGBA8::new(0, 0, 255, 255));
+    assert_eq!(shiny.specular_color, Some(RGBA8::new(128, 128, 128, 255)));
+    assert_eq!(shiny.shine, 50.0);
+    assert_eq!(shiny.texture, None);
+
+    // Without the library, the faces keep the object's material.
+    std::fs::remove_file(directory.join("two.mtl")).unwrap();
+    let model = load_obj_model(directory.join("two.obj")).unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+    let path = directory.join("two.mtl").display().to_string();
+    assert_eq!(
+        model.warnings,
+        vec![SceneWarning::MissingMaterialLibrary(path)]
+    );
+    assert!(model.object.materials.is_empty());
+    assert_eq!(
+        model
+            .object
+            .material_at(1, (1.0, 0.0, 0.0))
+            .ambient_constant,
+        model.object.material.ambient_constant
+    );
+}
+
 /// Write an object into a binary file using bincode/Serde serialization.
 pub fn save_object_as_file_bin<O, P>(object: O, path: P) -> Result<(), Box<dyn std::error::Error>>
 where
@@ -178,3 +604,72 @@ where
     let result = bincode::deserialize_from(buf_reader)?;
     Ok(result)
 }
+/// Write an RGBA frame buffer into a png file.
+pub fn save_frame_as_png<P>(
+    frame_buffer: &[u8],
+    resolution: Resolution,
+    path: P,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    P: AsRef<Path>,
+{
+    image::save_buffer(
+        path,
+        frame_buffer,
+        resolution.w,
+        resolution.h,
+        image::ColorType::Rgba8,
+    )?;
+    Ok(())
+}
+
+/// Write an RGBA frame buffer into a binary (P6) ppm file, dropping the alpha
+/// channel. Unlike png, this needs no image library.
+pub fn save_frame_as_ppm<P>(
+    frame_buffer: &[u8],
+    resolution: Resolution,
+    path: P,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    P: AsRef<Path>,
+{
+    let expected = resolution.w as usize * resolution.h as usize * 4;
+    if frame_buffer.len() != expected {
+        return Err(FrameBufferError::WrongLength(expected, frame_buffer.len()).into());
+    }
+    let mut buf_writer = BufWriter::new(File::create(path)?);
+    write!(buf_writer, "P6\n{} {}\n255\n", resolution.w, resolution.h)?;
+    for pixel in frame_buffer.chunks_exact(4) {
+        buf_writer.write_all(&pixel[..3])?;
+    }
+    buf_writer.flush()?;
+    Ok(())
+}
+
+#[test]
+fn test_save_frame_as_ppm() {
+    let resolution = Resolution { w: 2, h: 1 };
+    let frame_buffer = [255, 0, 0, 255, 0, 128, 255, 255];
+    let path = std::env::temp_dir().join("renderer_test_frame.ppm");
+    save_frame_as_ppm(&frame_buffer, resolution, &path).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let header = b"P6\n2 1\n255\n";
+    assert_eq!(&bytes[..header.len()], header);
+    assert_eq!(&bytes[header.len()..], &[255, 0, 0, 0, 128, 255]);
+
+    assert!(save_frame_as_ppm(&frame_buffer[..4], resolution, &path).is_err());
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_frame_stats() {
+    let mut stats = FrameStats::default();
+    assert_eq!(stats.min_max_mean_median(), None);
+    for frame_time in [4.0, 1.0, 3.0, 2.0] {
+        stats.record(frame_time);
+    }
+    assert_eq!(stats.min_max_mean_median(), Some((1.0, 4.0, 2.5, 2.5)));
+    stats.record(10.0);
+    assert_eq!(stats.min_max_mean_median(), Some((1.0, 10.0, 4.0, 3.0)));
+}
@@ -1,6 +1,6 @@
 use std::{
     f32::consts::PI,
-    ops::{Add, AddAssign, Mul, Sub},
+    ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign},
 };
 
 use serde::{Deserialize, Serialize};
@@ -27,7 +27,7 @@ pub fn vec3(x: f32, y: f32, z: f32) -> Vec3 {
     Vec3 { x, y, z }
 }
 
-/// Dot-product
+/// Dot-product, see also `Vec3::dot`.
 impl Mul for Vec3 {
     type Output = f32;
 
@@ -44,6 +44,22 @@ impl Mul<f32> for Vec3 {
     }
 }
 
+impl Div<f32> for Vec3 {
+    type Output = Self;
+
+    fn div(self, rhs: f32) -> Self::Output {
+        vec3(self.x / rhs, self.y / rhs, self.z / rhs)
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        vec3(-self.x, -self.y, -self.z)
+    }
+}
+
 impl Add for Vec3 {
     type Output = Self;
 
@@ -70,6 +86,18 @@ impl AddAssign for Vec3 {
     }
 }
 
+impl SubAssign for Vec3 {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+impl MulAssign<f32> for Vec3 {
+    fn mul_assign(&mut self, rhs: f32) {
+        *self = *self * rhs;
+    }
+}
+
 impl From<SphericalCoordinates> for Vec3 {
     fn from(sphere: SphericalCoordinates) -> Self {
         let x = sphere.rad * (sphere.phi).cos() * (sphere.theta).sin();
@@ -81,16 +109,26 @@ impl From<SphericalCoordinates> for Vec3 {
 
 // Custom implementations
 impl Vec3 {
+    pub fn dot(&self, other: Vec3) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    /// Multiply component by component (the Hadamard product), e.g. to
+    /// scale light intensity per color channel.
+    pub fn mul_componentwise(&self, other: Vec3) -> Vec3 {
+        vec3(self.x * other.x, self.y * other.y, self.z * other.z)
+    }
+
     pub fn len(&self) -> f32 {
         (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
     }
 
     pub fn normalize(&mut self) {
-        *self = *self * (1.0 / self.len())
+        *self = *self / self.len()
     }
 
     pub fn normalized(&self) -> Vec3 {
-        *self * (1.0 / self.len())
+        *self / self.len()
     }
 
     pub fn cross_product(&self, other: Self) -> Vec3 {
@@ -109,32 +147,153 @@ impl Vec3 {
     /// Set the length of a vector while keeping the direction.
     pub fn set_length(&mut self, length: f32) {
         self.normalize();
-        *self = *self * length;
+        *self *= length;
     }
 
     /// Rotate around the x-axis, starting from the positive z-axis.
     pub fn rotate_x_rad(&mut self, angle: f32) {
-        let current_angle = self.y.atan2(self.z);
-        let new_angle = current_angle + angle;
-        let len_yz = (self.y * self.y + self.z * self.z).sqrt();
-        self.z = new_angle.cos() * len_yz;
-        self.y = new_angle.sin() * len_yz;
+        *self = Mat3::rotation_x(angle) * *self;
     }
     /// Rotate around the y-axis, starting from the positive x-axis.
     pub fn rotate_y_rad(&mut self, angle: f32) {
-        let current_angle = self.z.atan2(self.x);
-        let new_angle = current_angle + angle;
-        let len_xz = (self.x * self.x + self.z * self.z).sqrt();
-        self.x = new_angle.cos() * len_xz;
-        self.z = new_angle.sin() * len_xz;
+        *self = Mat3::rotation_y(angle) * *self;
     }
     /// Rotate around the z-axis, starting from the positive y-axis.
     pub fn rotate_z_rad(&mut self, angle: f32) {
-        let current_angle = self.y.atan2(self.x);
-        let new_angle = current_angle + angle;
-        let len_xy = (self.x * self.x + self.y * self.y).sqrt();
-        self.x = new_angle.cos() * len_xy;
-        self.y = new_angle.sin() * len_xy;
+        *self = Mat3::rotation_z(angle) * *self;
+    }
+    /// Rotate around an arbitrary axis using Rodrigues' rotation formula. The
+    /// rotation is counterclockwise when looking down the axis towards the
+    /// origin (right-handed), so note that `rotate_y_rad` rotates the other
+    /// way around. The axis doesn't need to be normalized.
+    pub fn rotate_around_axis(&mut self, axis: Vec3, angle: f32) {
+        *self = Mat3::rotation_around_axis(axis, angle) * *self;
+    }
+
+    /// Reflect a direction off a surface with a normalized `normal`.
+    pub fn reflect(&self, normal: Vec3) -> Vec3 {
+        *self - normal * (2.0 * self.dot(normal))
+    }
+
+    /// Refract a normalized direction through a surface with a normalized
+    /// `normal` that faces the incoming ray, using Snell's law. `eta` is the
+    /// ratio of the refractive index on the incoming side to the one on the
+    /// other side. Returns `None` on total internal reflection.
+    pub fn refract(&self, normal: Vec3, eta: f32) -> Option<Vec3> {
+        let cos_incoming = -self.dot(normal);
+        let sin_sq_refracted = eta * eta * (1.0 - cos_incoming * cos_incoming);
+        if sin_sq_refracted > 1.0 {
+            return None;
+        }
+        let cos_refracted = (1.0 - sin_sq_refracted).sqrt();
+        Some(*self * eta + normal * (eta * cos_incoming - cos_refracted))
+    }
+}
+
+/// A 3x3 matrix, stored as rows. Multiplying a `Vec3` by it transforms the
+/// vector, and multiplying two matrices composes their transformations, so
+/// `a * b` first applies `b`, then `a`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat3 {
+    pub rows: [Vec3; 3],
+}
+
+impl Mat3 {
+    /// Rotate around the x-axis, from the positive z-axis towards the
+    /// positive y-axis.
+    pub fn rotation_x(angle: f32) -> Mat3 {
+        let (sin, cos) = angle.sin_cos();
+        Mat3 {
+            rows: [
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, cos, sin),
+                vec3(0.0, -sin, cos),
+            ],
+        }
+    }
+
+    /// Rotate around the y-axis, from the positive x-axis towards the
+    /// positive z-axis.
+    pub fn rotation_y(angle: f32) -> Mat3 {
+        let (sin, cos) = angle.sin_cos();
+        Mat3 {
+            rows: [
+                vec3(cos, 0.0, -sin),
+                vec3(0.0, 1.0, 0.0),
+                vec3(sin, 0.0, cos),
+            ],
+        }
+    }
+
+    /// Rotate around the z-axis, from the positive x-axis towards the
+    /// positive y-axis.
+    pub fn rotation_z(angle: f32) -> Mat3 {
+        let (sin, cos) = angle.sin_cos();
+        Mat3 {
+            rows: [
+                vec3(cos, -sin, 0.0),
+                vec3(sin, cos, 0.0),
+                vec3(0.0, 0.0, 1.0),
+            ],
+        }
+    }
+
+    /// Rotate around an arbitrary axis, the same way as
+    /// `Vec3::rotate_around_axis`.
+    pub fn rotation_around_axis(axis: Vec3, angle: f32) -> Mat3 {
+        let k = axis.normalized();
+        let (sin, cos) = angle.sin_cos();
+        let t = 1.0 - cos;
+        Mat3 {
+            rows: [
+                vec3(
+                    t * k.x * k.x + cos,
+                    t * k.x * k.y - sin * k.z,
+                    t * k.x * k.z + sin * k.y,
+                ),
+                vec3(
+                    t * k.x * k.y + sin * k.z,
+                    t * k.y * k.y + cos,
+                    t * k.y * k.z - sin * k.x,
+                ),
+                vec3(
+                    t * k.x * k.z - sin * k.y,
+                    t * k.y * k.z + sin * k.x,
+                    t * k.z * k.z + cos,
+                ),
+            ],
+        }
+    }
+
+    pub fn transposed(&self) -> Mat3 {
+        let [a, b, c] = self.rows;
+        Mat3 {
+            rows: [
+                vec3(a.x, b.x, c.x),
+                vec3(a.y, b.y, c.y),
+                vec3(a.z, b.z, c.z),
+            ],
+        }
+    }
+}
+
+impl Mul<Vec3> for Mat3 {
+    type Output = Vec3;
+
+    fn mul(self, rhs: Vec3) -> Self::Output {
+        let [a, b, c] = self.rows;
+        vec3(a.dot(rhs), b.dot(rhs), c.dot(rhs))
+    }
+}
+
+impl Mul for Mat3 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let columns = rhs.transposed();
+        Mat3 {
+            rows: self.rows.map(|row| columns * row),
+        }
     }
 }
 
@@ -149,3 +308,162 @@ fn test_sphere_to_vec_conversion() {
     let epsilon = 0.000001f32;
     assert!((v - s.into()).len() < epsilon);
 }
+
+#[test]
+fn test_operators() {
+    let v = vec3(1.0, -2.0, 4.0);
+    assert_eq!(-v, vec3(-1.0, 2.0, -4.0));
+    assert_eq!(v / 2.0, vec3(0.5, -1.0, 2.0));
+
+    let mut w = v;
+    w -= vec3(1.0, 1.0, 1.0);
+    assert_eq!(w, vec3(0.0, -3.0, 3.0));
+    w *= -2.0;
+    assert_eq!(w, vec3(0.0, 6.0, -6.0));
+}
+
+#[test]
+fn test_dot() {
+    let (a, b) = (vec3(1.0, 2.0, 3.0), vec3(4.0, -5.0, 6.0));
+    assert_eq!(a.dot(b), 12.0);
+    assert_eq!(a.dot(b), a * b);
+    assert_eq!(a.dot(a), 14.0);
+    // Perpendicular vectors.
+    assert_eq!(vec3(1.0, 0.0, 0.0).dot(vec3(0.0, 3.0, 0.0)), 0.0);
+}
+
+#[test]
+fn test_mul_componentwise() {
+    let v = vec3(1.0, -2.0, 4.0);
+    assert_eq!(
+        v.mul_componentwise(vec3(3.0, 0.5, 0.0)),
+        vec3(3.0, -1.0, 0.0)
+    );
+    assert_eq!(v.mul_componentwise(vec3(1.0, 1.0, 1.0)), v);
+}
+
+#[test]
+fn test_rotate_around_axis() {
+    let mut v = vec3(1.0, 0.0, 0.0);
+    v.rotate_around_axis(vec3(0.0, 1.0, 0.0), PI / 2.0);
+    assert!((v - vec3(0.0, 0.0, -1.0)).len() < 1e-6);
+
+    // Rotating around a diagonal axis by a third of a turn cycles the axes.
+    let mut v = vec3(1.0, 0.0, 0.0);
+    v.rotate_around_axis(vec3(2.0, 2.0, 2.0), 2.0 * PI / 3.0);
+    assert!((v - vec3(0.0, 1.0, 0.0)).len() < 1e-6);
+}
+
+#[test]
+fn test_reflect() {
+    let normal = vec3(0.0, 1.0, 0.0);
+    assert_eq!(vec3(1.0, -1.0, 0.0).reflect(normal), vec3(1.0, 1.0, 0.0));
+    assert_eq!(vec3(0.0, -2.0, 0.0).reflect(normal), vec3(0.0, 2.0, 0.0));
+    // Moving along the surface, nothing changes.
+    assert_eq!(vec3(1.0, 0.0, 3.0).reflect(normal), vec3(1.0, 0.0, 3.0));
+}
+
+#[test]
+fn test_refract() {
+    let normal = vec3(0.0, 1.0, 0.0);
+    // Straight through the surface, the direction doesn't change.
+    let down = vec3(0.0, -1.0, 0.0);
+    assert!((down.refract(normal, 1.0 / 1.5).unwrap() - down).len() < 1e-6);
+    // Entering glass at 45 degrees bends the ray towards the normal, following
+    // Snell's law: sin(out) = sin(in) / 1.5.
+    let incoming = vec3(1.0, -1.0, 0.0).normalized();
+    let refracted = incoming.refract(normal, 1.0 / 1.5).unwrap();
+    assert!((refracted.len() - 1.0).abs() < 1e-6);
+    assert!((refracted.x - (0.5f32).sqrt() / 1.5).abs() < 1e-6);
+    // Leaving glass at the same angle is past the critical angle.
+    assert!(incoming.refract(normal, 1.5).is_none());
+}
+
+/// Rotate a vector around an axis the way the `rotate_*_rad` methods did
+/// before they used `Mat3`: by rebuilding the angle in the plane `(u, v)` of
+/// the rotation with `atan2`, where `get` and `set` access those components.
+#[cfg(test)]
+fn rotate_with_atan2(
+    mut vector: Vec3,
+    angle: f32,
+    get: fn(&Vec3) -> (f32, f32),
+    set: fn(&mut Vec3, f32, f32),
+) -> Vec3 {
+    let (u, v) = get(&vector);
+    let new_angle = v.atan2(u) + angle;
+    let len = (u * u + v * v).sqrt();
+    set(&mut vector, new_angle.cos() * len, new_angle.sin() * len);
+    vector
+}
+
+#[cfg(test)]
+fn rotate_x_with_atan2(vector: Vec3, angle: f32) -> Vec3 {
+    rotate_with_atan2(vector, angle, |v| (v.z, v.y), |v, z, y| (v.z, v.y) = (z, y))
+}
+
+#[cfg(test)]
+fn rotate_y_with_atan2(vector: Vec3, angle: f32) -> Vec3 {
+    rotate_with_atan2(vector, angle, |v| (v.x, v.z), |v, x, z| (v.x, v.z) = (x, z))
+}
+
+#[cfg(test)]
+fn rotate_z_with_atan2(vector: Vec3, angle: f32) -> Vec3 {
+    rotate_with_atan2(vector, angle, |v| (v.x, v.y), |v, x, y| (v.x, v.y) = (x, y))
+}
+
+#[test]
+fn test_rotation_matrices_match_rotate_methods() {
+    let v = vec3(0.3, -1.2, 2.5);
+    let close = |a: Vec3, b: Vec3| (a - b).len() < 1e-5;
+    for angle in [0.37, -2.1, 1.3, PI / 2.0, 3.0] {
+        assert!(close(
+            Mat3::rotation_x(angle) * v,
+            rotate_x_with_atan2(v, angle)
+        ));
+        assert!(close(
+            Mat3::rotation_y(angle) * v,
+            rotate_y_with_atan2(v, angle)
+        ));
+        assert!(close(
+            Mat3::rotation_z(angle) * v,
+            rotate_z_with_atan2(v, angle)
+        ));
+        let mut rotated = v;
+        rotated.rotate_x_rad(angle);
+        assert!(close(rotated, rotate_x_with_atan2(v, angle)));
+        let mut rotated = v;
+        rotated.rotate_y_rad(angle);
+        assert!(close(rotated, rotate_y_with_atan2(v, angle)));
+        let mut rotated = v;
+        rotated.rotate_z_rad(angle);
+        assert!(close(rotated, rotate_z_with_atan2(v, angle)));
+    }
+    // Hand-computed: a quarter turn moves each rotation's starting axis onto
+    // the next one.
+    assert!(close(
+        Mat3::rotation_x(PI / 2.0) * vec3(0.0, 0.0, 1.0),
+        vec3(0.0, 1.0, 0.0)
+    ));
+    assert!(close(
+        Mat3::rotation_y(PI / 2.0) * vec3(1.0, 0.0, 0.0),
+        vec3(0.0, 0.0, 1.0)
+    ));
+    assert!(close(
+        Mat3::rotation_z(PI / 2.0) * vec3(1.0, 0.0, 0.0),
+        vec3(0.0, 1.0, 0.0)
+    ));
+}
+
+#[test]
+fn test_mat3_multiplication() {
+    let v = vec3(0.3, -1.2, 2.5);
+    let (pitch, yaw) = (0.37, -2.1);
+    // Composing the matrices applies the right one first, like pitching and
+    // then turning the camera did before.
+    let combined = Mat3::rotation_y(yaw) * Mat3::rotation_z(pitch);
+    let expected = rotate_y_with_atan2(rotate_z_with_atan2(v, pitch), yaw);
+    assert!((combined * v - expected).len() < 1e-5);
+    // A rotation's transpose undoes it.
+    let undone = combined.transposed() * combined;
+    assert!((undone * v - v).len() < 1e-5);
+}
@@ -1,33 +1,712 @@
+use std::borrow::Cow;
+use std::error::Error;
 use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use crate::bvh::{Bvh, BvhTriangle};
+use crate::camera::CameraSettings;
+use crate::color::Color;
+use crate::errors::{SceneError, SceneWarning};
 use crate::objects::*;
+use crate::texture::{Cubemap, Texture, TextureFilter};
+use crate::util::{
+    load_obj_model, load_object_from_file_json, load_objects_from_file_json,
+    save_object_as_file_json,
+};
 
-use crate::vector::vec3;
+use crate::vector::{vec3, Vec3};
 
 use rgb::*;
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct World {
+    #[serde(default)]
     pub vertex_objects: Vec<VertexObject>,
+    #[serde(default)]
     pub balls: Vec<Ball>,
+    #[serde(default)]
+    pub planes: Vec<Plane>,
+    #[serde(default)]
+    pub boxes: Vec<BoxAABB>,
+    #[serde(default)]
     pub lights: Vec<Light>,
+    #[serde(default)]
+    pub spot_lights: Vec<SpotLight>,
+    /// What rays that don't hit anything show. Older scene files call this
+    /// `color`.
+    #[serde(default, alias = "color")]
+    pub background: Background,
+    /// Fog between the camera and the objects, which hides the background.
+    #[serde(default)]
+    pub fog: Option<Fog>,
+    /// Light that reaches every surface, regardless of the lights. It scales
+    /// the ambient color of materials per channel, so 1.0 shows the ambient
+    /// color as is and 0.0 leaves unlit areas black.
+    #[serde(default = "default_ambient_light")]
+    pub ambient_light: LightIntensity,
+    /// Meshes that are only drawn through `instances`, not by themselves.
+    #[serde(default)]
+    pub meshes: Vec<VertexObject>,
+    #[serde(default)]
+    pub instances: Vec<Instance>,
+    /// When paused, `update` leaves the world untouched, so only the camera
+    /// moves.
+    #[serde(skip)]
+    pub paused: bool,
+    /// An image shown behind the objects, stretched over the whole frame. It
+    /// replaces `background` for camera rays that don't hit anything.
+    #[serde(skip)]
+    pub background_image: Option<Texture>,
+    /// The surroundings seen by rays that don't hit anything, in every
+    /// direction. It replaces `background`, also in reflections.
+    #[serde(skip)]
+    pub skybox: Option<Cubemap>,
+    /// Images that materials refer to by index, for textured faces.
+    #[serde(skip)]
+    pub textures: Vec<Texture>,
+    /// Objects whose edges are drawn on top of the render.
+    #[serde(skip)]
+    pub wireframes: Vec<ObjectHandle>,
+    /// The faces of `vertex_objects` and `meshes` with their positions
+    /// already added, so the renderer doesn't have to offset every vertex for
+    /// every ray. Rebuilt by `cache_faces`.
+    #[serde(skip)]
+    face_cache: FaceCache,
+    /// The indices of the vertex objects changed through `vertex_object_mut`,
+    /// so `update` knows to cache their faces again.
+    #[serde(skip)]
+    changed_vertex_objects: Vec<usize>,
+}
+
+fn default_ambient_light() -> LightIntensity {
+    LightIntensity::new(1.0, 1.0, 1.0)
+}
+
+/// The color seen by rays that don't hit anything. In scene files, a solid
+/// background is written as a plain color and a gradient as an object with
+/// `top` and `bottom` colors.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum Background {
+    Solid(RGBA8),
+    /// A blend from `bottom`, looking straight down, to `top`, looking
+    /// straight up, by the y component of the ray direction.
+    VerticalGradient {
+        top: RGBA8,
+        bottom: RGBA8,
+    },
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Solid(RGBA8::new(0, 0, 0, 255))
+    }
+}
+
+impl Background {
+    /// Get the color seen in a direction, which doesn't need to be normalized.
+    pub fn color_in_direction(&self, direction: Vec3) -> Color {
+        match *self {
+            Background::Solid(color) => Color::from(color),
+            Background::VerticalGradient { top, bottom } => {
+                let t = (direction.normalized().y + 1.0) / 2.0;
+                Color::from(bottom) * (1.0 - t) + Color::from(top) * t
+            }
+        }
+    }
+}
+
+/// Exponential fog: the further away a surface is, the more its color fades
+/// into the fog color.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Fog {
     pub color: RGBA8,
+    /// How quickly the fog thickens with distance.
+    pub density: f32,
+}
+
+impl Fog {
+    /// The fraction of the fog color in the color of a surface at `distance`,
+    /// from 0 (no fog) to 1 (only fog).
+    pub fn amount(&self, distance: f32) -> f32 {
+        1.0 - (-self.density * distance).exp()
+    }
+}
+
+#[derive(Default)]
+struct FaceCache {
+    vertex_objects: Vec<CachedFaces>,
+    meshes: Vec<CachedFaces>,
+    /// A hierarchy over the cached faces of all vertex objects.
+    bvh: Bvh,
+}
+
+/// Positioned faces and the bounding sphere of a single object, with the
+/// position they were computed for.
+struct CachedFaces {
+    pos: Vec3,
+    faces: Vec<TriangleFace>,
+    bounding_sphere: Option<BoundingSphere>,
+}
+
+impl CachedFaces {
+    fn new(object: &VertexObject) -> Self {
+        CachedFaces {
+            pos: object.pos,
+            faces: object.positioned_faces(),
+            bounding_sphere: object.bounding_sphere(),
+        }
+    }
+}
+
+impl CachedFaces {
+    /// Check whether the cached faces still belong to the object, i.e. it
+    /// hasn't been moved or gained or lost faces since the cache was built.
+    /// This is cheap enough to check for every ray, but doesn't notice
+    /// changed vertices.
+    fn is_valid_for(&self, object: &VertexObject) -> bool {
+        self.pos == object.pos && self.faces.len() == object.faces.len()
+    }
+}
+
+/// Check whether there is a valid cache entry for every object.
+fn all_valid(cached: &[CachedFaces], objects: &[VertexObject]) -> bool {
+    cached.len() == objects.len()
+        && cached
+            .iter()
+            .zip(objects)
+            .all(|(cached, object)| cached.is_valid_for(object))
+}
+
+/// Get the positioned faces of an object from the cache, or compute them when
+/// the object has been moved or changed shape since the cache was built.
+fn cached_or_positioned_faces<'a>(
+    cached: Option<&'a CachedFaces>,
+    object: &VertexObject,
+) -> Cow<'a, [TriangleFace]> {
+    match cached {
+        Some(cached) if cached.is_valid_for(object) => Cow::Borrowed(&cached.faces),
+        _ => Cow::Owned(object.positioned_faces()),
+    }
+}
+
+/// Get the bounding sphere of an object from the cache, or compute it when the
+/// cached one is out of date.
+fn cached_or_bounding_sphere(
+    cached: Option<&CachedFaces>,
+    object: &VertexObject,
+) -> Option<BoundingSphere> {
+    match cached {
+        Some(cached) if cached.is_valid_for(object) => cached.bounding_sphere,
+        _ => object.bounding_sphere(),
+    }
+}
+
+/// A handle to an object in a `World`, e.g. the object selected with the mouse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectHandle {
+    Ball(usize),
+    VertexObject(usize),
+    Instance(usize),
+    Plane(usize),
+    Box(usize),
+}
+
+/// A complete scene that can be stored in a single (json) file: the `World`
+/// and the camera looking at it.
+#[derive(Serialize, Deserialize)]
+pub struct Scene {
+    #[serde(flatten)]
+    pub world: World,
+    #[serde(default)]
+    pub camera: CameraSettings,
+    /// Path to an image file to use as the world's background image. Like the
+    /// other image paths, it is relative to the scene file's directory.
+    #[serde(default)]
+    pub background_image: Option<PathBuf>,
+    /// Paths to the six image files of the world's skybox, in the order +X,
+    /// -X, +Y, -Y, +Z, -Z.
+    #[serde(default)]
+    pub skybox: Option<[PathBuf; 6]>,
+    /// Paths to the image files of the world's textures, in the order that
+    /// materials refer to them.
+    #[serde(default)]
+    pub textures: Vec<PathBuf>,
+    /// Paths to json files with lists of objects, which are added to the
+    /// world, so props can be shared between scenes.
+    #[serde(default)]
+    pub objects: Vec<PathBuf>,
+    /// Problems found while loading the scene's files, which `validate`
+    /// reports along with the problems of the scene itself.
+    #[serde(skip)]
+    pub load_warnings: Vec<SceneWarning>,
+}
+
+/// The scene that is shown when no scene file is given.
+pub const DEFAULT_SCENE_PATH: &str = "res/scenes/default.json";
+
+/// Fields of view (in degrees) outside of this range are allowed, but likely
+/// a mistake.
+const SENSIBLE_FIELD_OF_VIEW: (f32, f32) = (5.0, 160.0);
+
+impl Scene {
+    /// Load a scene from a json file, or the default scene when no path is
+    /// given.
+    /// Fails when a face refers to a vertex that doesn't exist.
+    pub fn load(path: Option<&Path>) -> Result<Scene, Box<dyn Error>> {
+        let path = path.unwrap_or_else(|| Path::new(DEFAULT_SCENE_PATH));
+        let mut scene: Scene = load_object_from_file_json(path)?;
+        // Image paths are relative to the directory of the scene file.
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        if let Some(image_path) = &scene.background_image {
+            scene.world.background_image = Some(Texture::load(directory.join(image_path))?);
+        }
+        if let Some(face_paths) = &scene.skybox {
+            let face_paths = face_paths
+                .clone()
+                .map(|face_path| directory.join(face_path));
+            scene.world.skybox = Some(Cubemap::load(&face_paths)?);
+        }
+        for texture_path in &scene.textures {
+            let texture = Texture::load(directory.join(texture_path))?;
+            scene.world.textures.push(texture);
+        }
+        let world = &mut scene.world;
+        let warnings = &mut scene.load_warnings;
+        for object in world.vertex_objects.iter_mut() {
+            load_obj_file(object, directory, &mut world.textures, warnings)?;
+        }
+        for mesh in world.meshes.iter_mut() {
+            load_obj_file(mesh, directory, &mut world.textures, warnings)?;
+        }
+        // The .obj files of an object list are relative to the list's directory.
+        for objects_path in &scene.objects {
+            let objects_path = directory.join(objects_path);
+            let mut entries = load_objects_from_file_json(&objects_path)?;
+            let objects_directory = objects_path.parent().unwrap_or_else(|| Path::new(""));
+            for entry in entries.iter_mut() {
+                if let ObjectEntry::VertexObject(object) = entry {
+                    load_obj_file(
+                        object,
+                        objects_directory,
+                        &mut scene.world.textures,
+                        &mut scene.load_warnings,
+                    )?;
+                }
+            }
+            scene.world.add_objects(entries);
+        }
+        scene.world.check_faces()?;
+        Ok(scene)
+    }
+
+    /// Check the scene for common authoring mistakes.
+    pub fn validate(&self) -> Vec<SceneWarning> {
+        let mut warnings = self.load_warnings.clone();
+        warnings.extend(self.world.validate());
+        let fov = self.camera.field_of_view;
+        if fov < SENSIBLE_FIELD_OF_VIEW.0 || fov > SENSIBLE_FIELD_OF_VIEW.1 {
+            warnings.push(SceneWarning::ExtremeFieldOfView(fov));
+        }
+        warnings
+    }
+}
+
+/// Take the vertices, faces and materials of an object that refers to an .obj
+/// file from that file, which is relative to `directory`. The textures of the
+/// materials are added to `textures`, and problems that don't stop the file
+/// from loading to `warnings`.
+fn load_obj_file(
+    object: &mut VertexObject,
+    directory: &Path,
+    textures: &mut Vec<Texture>,
+    warnings: &mut Vec<SceneWarning>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(obj_path) = &object.obj {
+        let mut model = load_obj_model(directory.join(obj_path))?;
+        let first_texture = textures.len();
+        for texture_path in &model.textures {
+            textures.push(Texture::load(texture_path)?);
+        }
+        for material in model.object.materials.iter_mut() {
+            material.texture = material.texture.map(|index| first_texture + index);
+        }
+        object.vertices = model.object.vertices;
+        object.faces = model.object.faces;
+        object.vertex_uvs = model.object.vertex_uvs;
+        object.materials = model.object.materials;
+        object.face_materials = model.object.face_materials;
+        warnings.append(&mut model.warnings);
+    }
+    Ok(())
 }
 
 impl World {
-    /// Create a new `World` instance that can draw a moving box.
+    #[cfg(test)]
+    /// Create an empty world.
     pub fn new() -> Self {
         Self {
             vertex_objects: Vec::new(),
             balls: Vec::new(),
+            planes: Vec::new(),
+            boxes: Vec::new(),
             lights: Vec::new(),
-            color: RGBA8::new(0, 0, 0, 255),
+            spot_lights: Vec::new(),
+            background: Background::default(),
+            fog: None,
+            ambient_light: default_ambient_light(),
+            meshes: Vec::new(),
+            instances: Vec::new(),
+            paused: false,
+            background_image: None,
+            skybox: None,
+            textures: Vec::new(),
+            wireframes: Vec::new(),
+            face_cache: FaceCache::default(),
+            changed_vertex_objects: Vec::new(),
+        }
+    }
+
+    #[cfg(test)]
+    /// Load a world from a json file. Scene files can be loaded as well, their
+    /// camera settings are ignored. The application loads scenes with
+    /// `Scene::load`, which also loads the files they refer to.
+    pub fn load_from_file_json<P: AsRef<Path>>(path: P) -> Result<World, Box<dyn Error>> {
+        let mut world: World = load_object_from_file_json(path)?;
+        world.check_faces()?;
+        world.cache_faces();
+        Ok(world)
+    }
+
+    /// Save the world to a json file.
+    pub fn save_to_file_json<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        save_object_as_file_json(self, path)
+    }
+
+    /// Rebuild the cache of positioned faces and bounding spheres. This has to
+    /// be called after changing the vertices of an object directly; moving an
+    /// object invalidates its cached faces automatically. `update` does this
+    /// when the cache is out of date or objects were changed through
+    /// `vertex_object_mut`, so animated objects stay up to date.
+    pub fn cache_faces(&mut self) {
+        let vertex_objects: Vec<CachedFaces> =
+            self.vertex_objects.iter().map(CachedFaces::new).collect();
+        let triangles = vertex_objects
+            .iter()
+            .enumerate()
+            .flat_map(|(object_index, cached)| {
+                cached
+                    .faces
+                    .iter()
+                    .enumerate()
+                    .map(move |(face_index, &face)| BvhTriangle {
+                        face,
+                        object_index,
+                        face_index,
+                    })
+            })
+            .collect();
+        self.face_cache = FaceCache {
+            vertex_objects,
+            meshes: self.meshes.iter().map(CachedFaces::new).collect(),
+            bvh: Bvh::new(triangles),
+        };
+        self.changed_vertex_objects.clear();
+    }
+
+    /// Get mutable access to a vertex object. Its faces are cached again on
+    /// the next `update`, so its vertices can be changed as well.
+    pub fn vertex_object_mut(&mut self, index: usize) -> Option<&mut VertexObject> {
+        if index < self.vertex_objects.len() && !self.changed_vertex_objects.contains(&index) {
+            self.changed_vertex_objects.push(index);
+        }
+        self.vertex_objects.get_mut(index)
+    }
+
+    /// Bring the cached faces up to date. Vertex objects that were only
+    /// changed through `vertex_object_mut`, keeping their position and number
+    /// of faces, are cached again on their own and refitted in the
+    /// hierarchy. Any other change rebuilds the whole cache.
+    fn update_face_cache(&mut self) {
+        if !all_valid(&self.face_cache.vertex_objects, &self.vertex_objects)
+            || !all_valid(&self.face_cache.meshes, &self.meshes)
+        {
+            self.cache_faces();
+            return;
+        }
+        for index in std::mem::take(&mut self.changed_vertex_objects) {
+            let cached = CachedFaces::new(&self.vertex_objects[index]);
+            self.face_cache.bvh.refit(index, &cached.faces);
+            self.face_cache.vertex_objects[index] = cached;
+        }
+    }
+
+    /// Get the bounding volume hierarchy over the faces of all vertex
+    /// objects, or `None` if an object has been added, moved or changed shape
+    /// since `cache_faces` was last called.
+    pub fn triangle_bvh(&self) -> Option<&Bvh> {
+        all_valid(&self.face_cache.vertex_objects, &self.vertex_objects)
+            .then_some(&self.face_cache.bvh)
+    }
+
+    /// Check whether the cached faces are up to date: no vertex object or
+    /// mesh has been added, removed or moved, and no vertex object has been
+    /// changed through `vertex_object_mut`, since they were cached.
+    pub fn face_cache_is_current(&self) -> bool {
+        self.changed_vertex_objects.is_empty()
+            && all_valid(&self.face_cache.vertex_objects, &self.vertex_objects)
+            && all_valid(&self.face_cache.meshes, &self.meshes)
+    }
+
+    /// Get the faces of a vertex object in world space.
+    pub fn vertex_object_faces(&self, index: usize) -> Cow<'_, [TriangleFace]> {
+        cached_or_positioned_faces(
+            self.face_cache.vertex_objects.get(index),
+            &self.vertex_objects[index],
+        )
+    }
+
+    /// Get the bounding sphere of a vertex object in world space.
+    pub fn vertex_object_bounding_sphere(&self, index: usize) -> Option<BoundingSphere> {
+        cached_or_bounding_sphere(
+            self.face_cache.vertex_objects.get(index),
+            &self.vertex_objects[index],
+        )
+    }
+
+    /// Get the bounding sphere of a mesh, in the local space of its
+    /// instances.
+    pub fn mesh_bounding_sphere(&self, index: usize) -> Option<BoundingSphere> {
+        cached_or_bounding_sphere(self.face_cache.meshes.get(index), &self.meshes[index])
+    }
+
+    /// Get the faces of an object in world space, or `None` for objects
+    /// without faces.
+    pub fn object_faces(&self, handle: ObjectHandle) -> Option<Vec<TriangleFace>> {
+        match handle {
+            ObjectHandle::Ball(_) | ObjectHandle::Plane(_) | ObjectHandle::Box(_) => None,
+            ObjectHandle::VertexObject(i) => {
+                self.vertex_objects.get(i)?;
+                Some(self.vertex_object_faces(i).into_owned())
+            }
+            ObjectHandle::Instance(i) => {
+                let instance = self.instances.get(i)?;
+                self.meshes.get(instance.mesh_id)?;
+                let faces = self.mesh_faces(instance.mesh_id);
+                Some(
+                    faces
+                        .iter()
+                        .map(|&face| instance.transform.apply_to_face(face))
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    /// Get the balls, boxes and planes in the world, with their handles.
+    pub fn primitives(&self) -> impl Iterator<Item = (ObjectHandle, Primitive<'_>)> {
+        let balls = self.balls.iter().enumerate();
+        let boxes = self.boxes.iter().enumerate();
+        let planes = self.planes.iter().enumerate();
+        balls
+            .map(|(i, ball)| (ObjectHandle::Ball(i), Primitive::Ball(ball)))
+            .chain(boxes.map(|(i, boxaabb)| (ObjectHandle::Box(i), Primitive::Box(boxaabb))))
+            .chain(planes.map(|(i, plane)| (ObjectHandle::Plane(i), Primitive::Plane(plane))))
+    }
+
+    /// Get the color of a texture at (u, v). The coordinates wrap around, so
+    /// the texture repeats. Returns `None` without a texture or coordinates.
+    pub fn texture_color(
+        &self,
+        texture: Option<usize>,
+        uv: Option<(f32, f32)>,
+        filter: TextureFilter,
+    ) -> Option<RGBA8> {
+        let texture = self.textures.get(texture?)?;
+        let (u, v) = uv?;
+        // Image rows start at the top, while v = 0 is the bottom.
+        Some(texture.sample_filtered(u.rem_euclid(1.0), 1.0 - v.rem_euclid(1.0), filter))
+    }
+
+    /// Get the faces of a mesh, offset by the mesh's position, in the local
+    /// space of its instances.
+    pub fn mesh_faces(&self, index: usize) -> Cow<'_, [TriangleFace]> {
+        cached_or_positioned_faces(self.face_cache.meshes.get(index), &self.meshes[index])
+    }
+
+    /// Get mutable access to the material of an object. Changing the
+    /// material of an instance that uses its mesh's material gives the
+    /// instance its own copy.
+    pub fn material_mut(&mut self, handle: ObjectHandle) -> Option<&mut Material> {
+        match handle {
+            ObjectHandle::Ball(i) => Some(&mut self.balls.get_mut(i)?.material),
+            ObjectHandle::Plane(i) => Some(&mut self.planes.get_mut(i)?.material),
+            ObjectHandle::Box(i) => Some(&mut self.boxes.get_mut(i)?.material),
+            ObjectHandle::VertexObject(i) => Some(&mut self.vertex_objects.get_mut(i)?.material),
+            ObjectHandle::Instance(i) => {
+                let instance = self.instances.get_mut(i)?;
+                let mesh_material = self.meshes.get(instance.mesh_id)?.material;
+                Some(instance.material.get_or_insert(mesh_material))
+            }
+        }
+    }
+
+    /// Add objects loaded from a file to the world.
+    pub fn add_objects(&mut self, entries: Vec<ObjectEntry>) {
+        for entry in entries {
+            match entry {
+                ObjectEntry::Ball(ball) => self.balls.push(ball),
+                ObjectEntry::VertexObject(object) => self.vertex_objects.push(*object),
+                ObjectEntry::Plane(plane) => self.planes.push(plane),
+                ObjectEntry::BoxAabb(boxaabb) => self.boxes.push(boxaabb),
+                ObjectEntry::Light(light) => self.lights.push(light),
+                ObjectEntry::SpotLight(spot_light) => self.spot_lights.push(spot_light),
+            }
+        }
+    }
+
+    /// Check that every face of the vertex objects and meshes refers to
+    /// vertices that exist, which the renderer relies on.
+    pub fn check_faces(&self) -> Result<(), SceneError> {
+        let vertex_objects = self
+            .vertex_objects
+            .iter()
+            .enumerate()
+            .map(|(i, object)| (format!("vertex object {}", i), object));
+        let meshes = self
+            .meshes
+            .iter()
+            .enumerate()
+            .map(|(i, mesh)| (format!("mesh {}", i), mesh));
+        for (name, object) in vertex_objects.chain(meshes) {
+            if let Some(&face_index) = object.invalid_faces().first() {
+                return Err(SceneError::FaceIndexOutOfRange(name, face_index));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the world for common authoring mistakes, like lights without
+    /// intensity or faces without area.
+    pub fn validate(&self) -> Vec<SceneWarning> {
+        let mut warnings = Vec::new();
+        for (i, light) in self.lights.iter().enumerate() {
+            if light.is_dark() {
+                warnings.push(SceneWarning::DarkLight(i));
+            }
+        }
+        let check_material = |warnings: &mut Vec<SceneWarning>, material: &Material, name| {
+            if material.is_invisible() {
+                warnings.push(SceneWarning::InvisibleMaterial(name));
+            } else if material.diffuse_constant == 0.0 {
+                warnings.push(SceneWarning::NoDiffuse(name));
+            }
+        };
+        for (i, ball) in self.balls.iter().enumerate() {
+            check_material(&mut warnings, &ball.material, format!("ball {}", i));
+        }
+        for (i, plane) in self.planes.iter().enumerate() {
+            check_material(&mut warnings, &plane.material, format!("plane {}", i));
+        }
+        for (i, boxaabb) in self.boxes.iter().enumerate() {
+            check_material(&mut warnings, &boxaabb.material, format!("box {}", i));
         }
+        for (i, object) in self.vertex_objects.iter().enumerate() {
+            check_material(
+                &mut warnings,
+                &object.material,
+                format!("vertex object {}", i),
+            );
+            for face_index in object.degenerate_faces() {
+                warnings.push(SceneWarning::DegenerateTriangle(i, face_index));
+            }
+        }
+        for (i, instance) in self.instances.iter().enumerate() {
+            if instance.mesh_id >= self.meshes.len() {
+                warnings.push(SceneWarning::MissingMesh(i));
+            }
+        }
+        warnings
+    }
+
+    /// Get the bounding box of everything in the world, or `None` if the
+    /// world is empty. Planes are left out, since they are infinite.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let balls = self.balls.iter().map(|ball| Some(ball.bounding_box()));
+        let boxes = self.boxes.iter().map(|boxaabb| {
+            let mut bounding_box = BoundingBox::from_point(boxaabb.min);
+            bounding_box.grow(boxaabb.max);
+            Some(bounding_box)
+        });
+        let vertex_objects = self.vertex_objects.iter().map(|o| o.bounding_box());
+        let instances = self.instances.iter().map(|instance| {
+            let mesh_box = self.meshes.get(instance.mesh_id)?.bounding_box()?;
+            // Transforming the corners is enough, since the transform only
+            // scales and translates.
+            let mut bounding_box =
+                BoundingBox::from_point(instance.transform.apply_to_point(mesh_box.min));
+            bounding_box.grow(instance.transform.apply_to_point(mesh_box.max));
+            Some(bounding_box)
+        });
+        balls
+            .chain(boxes)
+            .chain(vertex_objects)
+            .chain(instances)
+            .flatten()
+            .reduce(|mut a, b| {
+                a.merge(&b);
+                a
+            })
+    }
+
+    /// Get a short, human readable summary of the world's contents.
+    pub fn summary(&self) -> String {
+        let triangle_count: usize = self
+            .vertex_objects
+            .iter()
+            .map(|object| object.faces.len())
+            .chain(self.instances.iter().map(|instance| {
+                self.meshes
+                    .get(instance.mesh_id)
+                    .map_or(0, |mesh| mesh.faces.len())
+            }))
+            .sum();
+        let mut summary = format!(
+            "{} triangles across {} vertex objects and {} instances, {} balls, {} planes, {} boxes, {} lights",
+            triangle_count,
+            self.vertex_objects.len(),
+            self.instances.len(),
+            self.balls.len(),
+            self.planes.len(),
+            self.boxes.len(),
+            self.lights.len() + self.spot_lights.len()
+        );
+        if let Some(bounding_box) = self.bounding_box() {
+            let (min, max) = (bounding_box.min, bounding_box.max);
+            summary += &format!(
+                "\nBounding box: ({:.2}, {:.2}, {:.2}) to ({:.2}, {:.2}, {:.2})",
+                min.x, min.y, min.z, max.x, max.y, max.z
+            );
+        }
+        summary
     }
 
     /// Update the `World` internal state; bounce the box around the screen.
+    /// The faces are only cached again when they are out of date, see
+    /// `face_cache_is_current`.
     pub fn update(&mut self, _last_frame_time: f32, time: Duration) {
-        if let Some(pyramid) = self.vertex_objects.get_mut(1) {
+        if !self.paused {
+            self.animate(time);
+        }
+        if !self.face_cache_is_current() {
+            self.update_face_cache();
+        }
+    }
+
+    fn animate(&mut self, time: Duration) {
+        if let Some(pyramid) = self.vertex_object_mut(1) {
             if pyramid.vertices.len() > 3 {
                 let time_passed_s = time.as_millis() as f32 / 1000.0;
                 *pyramid.vertices.get_mut(2).unwrap() = vec3(
@@ -54,3 +733,324 @@ impl World {
         }
     }
 }
+
+#[test]
+fn test_validate_scene() {
+    let mut world = World::new();
+    let material = Material {
+        diffuse_constant: 10.0,
+        ..Default::default()
+    };
+    world.balls.push(Ball {
+        pos: vec3(0.0, 0.0, 0.0),
+        rad: 1.0,
+        material,
+    });
+    let intensity = LightIntensity::new(100.0, 100.0, 100.0);
+    world.lights.push(Light {
+        pos: vec3(0.0, 5.0, 0.0),
+        diffuse_intensity: intensity,
+        specular_intensity: intensity,
+        casts_shadows: true,
+    });
+    let mut scene = Scene {
+        world,
+        camera: CameraSettings::default(),
+        background_image: None,
+        skybox: None,
+        textures: Vec::new(),
+        objects: Vec::new(),
+        load_warnings: Vec::new(),
+    };
+    assert!(scene.validate().is_empty());
+
+    scene.world.balls[0].material.diffuse_constant = 0.0;
+    scene.world.balls.push(Ball {
+        pos: vec3(3.0, 0.0, 0.0),
+        rad: 1.0,
+        material: Material {
+            ambient_constant: RGBA8::new(0, 0, 0, 255),
+            diffuse_constant: 0.0,
+            specular_constant: 0.0,
+            ..Default::default()
+        },
+    });
+    let dark = LightIntensity::new(0.0, 0.0, 0.0);
+    scene.world.lights.push(Light {
+        pos: vec3(0.0, 5.0, 0.0),
+        diffuse_intensity: dark,
+        specular_intensity: dark,
+        casts_shadows: true,
+    });
+    scene.world.vertex_objects.push(VertexObject {
+        vertices: vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(2.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        ],
+        faces: vec![(0, 1, 3), (0, 1, 2)],
+        material,
+        ..Default::default()
+    });
+    scene.camera.field_of_view = 179.0;
+    assert_eq!(
+        scene.validate(),
+        vec![
+            SceneWarning::DarkLight(1),
+            SceneWarning::NoDiffuse("ball 0".to_string()),
+            SceneWarning::InvisibleMaterial("ball 1".to_string()),
+            SceneWarning::DegenerateTriangle(0, 1),
+            SceneWarning::ExtremeFieldOfView(179.0),
+        ]
+    );
+}
+
+#[test]
+fn test_world_summary() {
+    let mut world = World::new();
+    assert_eq!(world.bounding_box(), None);
+    world.balls.push(Ball {
+        pos: vec3(0.0, 1.0, 0.0),
+        rad: 1.0,
+        material: Material::default(),
+    });
+    world.meshes.push(VertexObject {
+        vertices: vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        ],
+        faces: vec![(0, 1, 2)],
+        ..Default::default()
+    });
+    for x in [5.0, 10.0] {
+        world.instances.push(Instance {
+            mesh_id: 0,
+            transform: Transform {
+                translation: vec3(x, 0.0, -3.0),
+                ..Default::default()
+            },
+            material: None,
+        });
+    }
+    assert_eq!(
+        world.summary(),
+        "2 triangles across 0 vertex objects and 2 instances, 1 balls, 0 planes, 0 boxes, 0 lights\n\
+         Bounding box: (-1.00, 0.00, -3.00) to (11.00, 2.00, 1.00)"
+    );
+}
+
+#[test]
+fn test_load_default_scene() {
+    let world = World::load_from_file_json(DEFAULT_SCENE_PATH).unwrap();
+    assert_eq!(world.vertex_objects.len(), 3);
+    assert_eq!(world.balls.len(), 3);
+    assert_eq!(world.lights.len(), 1);
+    assert_eq!(
+        world.background,
+        Background::Solid(RGBA8::new(196, 196, 196, 255))
+    );
+
+    let scene = Scene::load(None).unwrap();
+    assert_eq!(scene.camera.pos, vec3(0.0, 2.5, 5.0));
+    assert_eq!(scene.camera.look_at, world.balls[0].pos);
+}
+
+#[test]
+fn test_scene_image_paths_are_relative_to_the_scene() {
+    let directory = std::env::temp_dir().join("renderer_test_scene_images");
+    std::fs::create_dir_all(&directory).unwrap();
+    let pixel = [10, 20, 30, 255];
+    let resolution = crate::util::Resolution { w: 1, h: 1 };
+    crate::util::save_frame_as_png(&pixel, resolution, directory.join("sky.png")).unwrap();
+    let scene = r#"{ "background_image": "sky.png", "textures": ["sky.png"] }"#;
+    std::fs::write(directory.join("scene.json"), scene).unwrap();
+    let loaded = Scene::load(Some(&directory.join("scene.json")));
+    std::fs::remove_dir_all(&directory).unwrap();
+    let world = loaded.unwrap().world;
+    assert_eq!(
+        world.textures[0].sample(0.5, 0.5),
+        RGBA8::new(10, 20, 30, 255)
+    );
+    assert!(world.background_image.is_some());
+}
+
+#[test]
+fn test_scene_object_lists() {
+    let directory = std::env::temp_dir().join("renderer_test_scene_objects");
+    std::fs::create_dir_all(&directory).unwrap();
+    let objects_path = std::env::current_dir()
+        .unwrap()
+        .join("res/objects/pillars.json");
+    let scene = serde_json::json!({ "objects": [objects_path] }).to_string();
+    std::fs::write(directory.join("scene.json"), scene).unwrap();
+    let loaded = Scene::load(Some(&directory.join("scene.json")));
+    std::fs::remove_dir_all(&directory).unwrap();
+    let world = loaded.unwrap().world;
+    assert_eq!(world.boxes.len(), 2);
+    assert_eq!(world.balls.len(), 1);
+    assert_eq!(world.lights.len(), 1);
+    assert!(!world.lights[0].casts_shadows);
+}
+
+#[test]
+fn test_scene_vertex_object_from_obj() {
+    let directory = std::env::temp_dir().join("renderer_test_scene_obj");
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::copy("res/objects/cube.obj", directory.join("cube.obj")).unwrap();
+    let scene = r#"{
+        "vertex_objects": [{
+            "pos": { "x": 0.0, "y": 1.0, "z": 0.0 },
+            "obj": "cube.obj",
+            "material": {
+                "ambient_constant": { "r": 255, "g": 255, "b": 255, "a": 255 },
+                "diffuse_constant": 35.0,
+                "specular_constant": 10.0,
+                "shine": 7.0
+            }
+        }]
+    }"#;
+    std::fs::write(directory.join("scene.json"), scene).unwrap();
+    let loaded = Scene::load(Some(&directory.join("scene.json")));
+    std::fs::remove_dir_all(&directory).unwrap();
+    let world = loaded.unwrap().world;
+    let cube = &world.vertex_objects[0];
+    assert_eq!(cube.pos, vec3(0.0, 1.0, 0.0));
+    assert_eq!(cube.vertices.len(), 8);
+    assert_eq!(cube.faces.len(), 12);
+}
+
+#[test]
+fn test_face_index_out_of_range_fails_to_load() {
+    let directory = std::env::temp_dir().join("renderer_test_bad_face_index");
+    std::fs::create_dir_all(&directory).unwrap();
+    let scene = r#"{
+        "meshes": [{
+            "pos": { "x": 0.0, "y": 0.0, "z": 0.0 },
+            "vertices": [
+                { "x": 0.0, "y": 0.0, "z": 0.0 },
+                { "x": 1.0, "y": 0.0, "z": 0.0 },
+                { "x": 0.0, "y": 1.0, "z": 0.0 }
+            ],
+            "faces": [[0, 1, 2], [0, 2, 3]],
+            "material": {
+                "ambient_constant": { "r": 255, "g": 255, "b": 255, "a": 255 },
+                "diffuse_constant": 35.0,
+                "specular_constant": 10.0,
+                "shine": 7.0
+            }
+        }]
+    }"#;
+    let path = directory.join("scene.json");
+    std::fs::write(&path, scene).unwrap();
+    let scene = Scene::load(Some(&path));
+    let world = World::load_from_file_json(&path);
+    std::fs::remove_dir_all(&directory).unwrap();
+    let error = scene.err().unwrap().to_string();
+    assert_eq!(
+        error,
+        SceneError::FaceIndexOutOfRange("mesh 0".to_string(), 1).to_string()
+    );
+    assert!(world.is_err());
+}
+
+#[test]
+fn test_world_json_round_trip() {
+    let world = World::load_from_file_json(DEFAULT_SCENE_PATH).unwrap();
+    let path = std::env::temp_dir().join("renderer_test_world_round_trip.json");
+    world.save_to_file_json(&path).unwrap();
+    let loaded = World::load_from_file_json(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.summary(), world.summary());
+    assert_eq!(loaded.background, world.background);
+    assert_eq!(loaded.balls[2].material.reflectivity, 0.8);
+}
+
+#[test]
+fn test_face_cache() {
+    let mut world = World::load_from_file_json(DEFAULT_SCENE_PATH).unwrap();
+    world.cache_faces();
+    let object = &world.vertex_objects[0];
+    let faces = world.vertex_object_faces(0);
+    assert!(matches!(faces, Cow::Borrowed(_)));
+    assert_eq!(faces.into_owned(), object.positioned_faces());
+
+    // Moving an object invalidates its cached faces.
+    let by = vec3(1.0, 2.0, 3.0);
+    world.vertex_objects[0].pos += by;
+    let faces = world.vertex_object_faces(0);
+    assert!(matches!(faces, Cow::Owned(_)));
+    let (v0, _, _) = faces[0];
+    let (cached_v0, _, _) = world.face_cache.vertex_objects[0].faces[0];
+    assert_eq!(v0, cached_v0 + by);
+}
+
+#[test]
+fn test_update_only_caches_changed_faces() {
+    let mut world = World::load_from_file_json(DEFAULT_SCENE_PATH).unwrap();
+    world.paused = true;
+    assert!(world.face_cache_is_current());
+    let cached_faces = world.face_cache.vertex_objects[0].faces.as_ptr();
+    world.update(0.0, Duration::ZERO);
+    assert_eq!(
+        world.face_cache.vertex_objects[0].faces.as_ptr(),
+        cached_faces
+    );
+
+    // Moving an object invalidates the cache, until the next update.
+    world.vertex_objects[0].pos += vec3(0.0, 1.0, 0.0);
+    assert!(!world.face_cache_is_current());
+    world.update(0.0, Duration::ZERO);
+    assert!(world.face_cache_is_current());
+    assert_eq!(world.face_cache.vertex_objects[0].pos, vec3(0.0, 1.0, 0.0));
+
+    // Editing a vertex keeps the position and the number of faces, so it has
+    // to go through `vertex_object_mut`.
+    // Only that object is cached again, and the hierarchy is refitted
+    // instead of being rebuilt.
+    let other_cached_faces = world.face_cache.vertex_objects[1].faces.as_ptr();
+    world.vertex_object_mut(0).unwrap().vertices[0] += vec3(0.0, 1.0, 0.0);
+    assert!(!world.face_cache_is_current());
+    world.update(0.0, Duration::ZERO);
+    assert!(world.face_cache_is_current());
+    let (v0, _, _) = world.vertex_object_faces(0)[0];
+    assert_eq!(v0, world.vertex_objects[0].positioned_faces()[0].0);
+    assert_eq!(
+        world.face_cache.vertex_objects[1].faces.as_ptr(),
+        other_cached_faces
+    );
+    assert!(world.triangle_bvh().is_some());
+}
+
+#[test]
+fn test_background_gradient() {
+    let top = RGBA8::new(100, 150, 255, 255);
+    let bottom = RGBA8::new(40, 30, 20, 255);
+    let background = Background::VerticalGradient { top, bottom };
+    assert_eq!(
+        background.color_in_direction(vec3(0.0, 3.0, 0.0)),
+        Color::from(top)
+    );
+    assert_eq!(
+        background.color_in_direction(vec3(0.0, -0.5, 0.0)),
+        Color::from(bottom)
+    );
+    // Looking at the horizon gives the average of both.
+    assert_eq!(
+        background.color_in_direction(vec3(1.0, 0.0, -1.0)),
+        Color::new(70.0, 90.0, 137.5)
+    );
+
+    // Both a plain color, also under the old `color` name, and a gradient
+    // can be loaded from a scene.
+    let world: World =
+        serde_json::from_str(r#"{ "color": { "r": 1, "g": 2, "b": 3, "a": 255 } }"#).unwrap();
+    assert_eq!(
+        world.background,
+        Background::Solid(RGBA8::new(1, 2, 3, 255))
+    );
+    let json = serde_json::to_string(&background).unwrap();
+    let world: World = serde_json::from_str(&format!(r#"{{ "background": {} }}"#, json)).unwrap();
+    assert_eq!(world.background, background);
+}